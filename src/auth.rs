@@ -0,0 +1,39 @@
+/* auth.rs
+
+   Contains the authentication of RPC commands, independent of any transport encryption
+
+   With a shared secret configured on the worker (Worker::rpc_key), every command is followed on the rpc stream
+   by an HMAC-SHA256 tag over the command and its trailing data. The receiver verifies it before dispatching,
+   so that a compromised connection cannot forge commands.
+
+   Author: Binghong(Leo) Li
+   Creation Date: 10/14/2026
+*/
+
+use crate::error::FusionError;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// length of the tag following each signed command
+pub const TAG_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// tag over the concatenation of `parts`
+pub fn sign(key: &[u8], parts: &[&[u8]]) -> [u8; TAG_LEN] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+// checking the tag of `parts` in constant time
+pub fn verify(key: &[u8], parts: &[&[u8]], tag: &[u8]) -> Result<(), FusionError> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.verify_slice(tag).map_err(|_| FusionError::BadSignature)
+}
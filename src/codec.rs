@@ -0,0 +1,188 @@
+/* codec.rs
+
+   Contains the format values travel over rpc in, so that it can be swapped without touching the rpc core
+
+   Workers, data stores and vertices are generic over two Codecs, both BincodeCodec unless picked otherwise
+   (see Worker::with_codec()):
+       C: the command codec, of the commands and the response headers, the hello of a connection, the caller
+          context and the other small trailing data of the commands (leases, versions, ...)
+       P: the payload codec, of the auxiliary information, the results and the data of the vertices sent
+          along, the same as C unless given
+   so the bulk of the traffic can go in a format of its own while the commands stay in the global one.
+   The ones read off a stream before anything tells how long they are (the commands, the response headers,
+   the hello) and the caller context, split off the front of the trailing data, are preceded by their
+   encoded length as a little endian u32 (see frame()).
+   What never leaves the machine stays bincode whatever the codecs: checkpoints, snapshots, and the past
+   versions of the data a vertex keeps.
+
+   Every machine of a deployment has to use the same two codecs. The hello a machine sends first on its rpc
+   streams names both (see Worker::hello()), and the receiving machine refuses the stream when they aren't
+   its own, rather than failing on the first payload it can't decode.
+
+   The encoded payloads can additionally be compressed with zstd (see Worker::compression), flagged per
+   message so machines with and without compression still understand each other.
+
+   Author: Binghong(Leo) Li
+   Creation Date: 10/14/2026
+*/
+
+use core::fmt::Debug;
+use std::io;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub type CodecError = Box<dyn std::error::Error + Send + Sync>;
+
+/*
+   Trait requirement for wire formats
+
+   Associated functions rather than methods, as the codec is picked at compile time: a type parameter of the
+   worker and of everything holding one, which the bounds keep Debug, Send and Sync
+*/
+pub trait Codec: Debug + Send + Sync + 'static {
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, CodecError>;
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError>;
+
+    // bytes `value` takes once encoded, for accounting what a computation sends
+    fn encoded_len<T: Serialize>(value: &T) -> Result<usize, CodecError> {
+        Self::encode(value).map(|bytes| bytes.len())
+    }
+
+    // identifies the codec in the hello of a connection (see Worker::hello()), its type name unless overridden
+    fn name() -> &'static str {
+        core::any::type_name::<Self>()
+    }
+}
+
+/*
+   The compact binary format used since the beginning, the default
+*/
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec;
+
+impl Codec for BincodeCodec {
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(bincode::serialize(value)?)
+    }
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+    fn encoded_len<T: Serialize>(value: &T) -> Result<usize, CodecError> {
+        Ok(bincode::serialized_size(value)? as usize)
+    }
+    fn name() -> &'static str {
+        "bincode"
+    }
+}
+
+// bytes of the length in front of the values framed by frame()
+pub const FRAME_PREFIX_LEN: usize = 4;
+
+// `value` encoded by C behind its length, for values read off a stream or split off the front of a buffer
+pub fn frame<C: Codec, T: Serialize>(value: &T) -> Result<Vec<u8>, CodecError> {
+    let encoded = C::encode(value)?;
+    let len = u32::try_from(encoded.len())?;
+    Ok([len.to_le_bytes().to_vec(), encoded].concat())
+}
+
+// the value framed by frame() at the front of `bytes`, and the bytes after it
+pub fn unframe<C: Codec, T: DeserializeOwned>(bytes: &[u8]) -> Result<(T, &[u8]), CodecError> {
+    let (prefix, rest) = bytes
+        .split_first_chunk::<FRAME_PREFIX_LEN>()
+        .ok_or("missing the length of a framed value")?;
+    let len = u32::from_le_bytes(*prefix) as usize;
+    if rest.len() < len {
+        return Err(format!("framed value of {len} bytes, only {} left", rest.len()).into());
+    }
+    let (encoded, rest) = rest.split_at(len);
+    Ok((C::decode(encoded)?, rest))
+}
+
+/*
+   Reading the value framed by frame() off `stream`, at most `max_len` bytes long
+
+   The length is checked before anything is allocated for the value, so a stream that lost its framing
+   fails with InvalidData rather than allocating whatever its bytes happen to say
+*/
+pub async fn read_framed<C: Codec, T: DeserializeOwned, R: AsyncRead + Unpin>(
+    stream: &mut R,
+    max_len: usize,
+) -> io::Result<T> {
+    let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, reason);
+    let mut prefix = [0u8; FRAME_PREFIX_LEN];
+    stream.read_exact(&mut prefix).await?;
+    let len = u32::from_le_bytes(prefix) as usize;
+    if len > max_len {
+        return Err(invalid(format!(
+            "framed value of {len} bytes, longer than {max_len}"
+        )));
+    }
+    let mut encoded = vec![0u8; len];
+    stream.read_exact(&mut encoded).await?;
+    C::decode(&encoded).map_err(|e| invalid(e.to_string()))
+}
+
+// payloads shorter than this many bytes are sent as is, compressing them doesn't pay off
+pub const MIN_COMPRESSED_LEN: usize = 512;
+
+// longest payload the rpc payloads and results are decompressed into, see decompress()
+pub const MAX_DECOMPRESSED_LEN: usize = 1 << 30;
+
+// compressing an encoded payload at zstd `level`, None if it is too small or doesn't shrink
+pub fn compress(bytes: &[u8], level: i32) -> Option<Vec<u8>> {
+    if bytes.len() < MIN_COMPRESSED_LEN {
+        return None;
+    }
+    let compressed = zstd::bulk::compress(bytes, level).ok()?;
+    (compressed.len() < bytes.len()).then_some(compressed)
+}
+
+/*
+   Decompressing a payload from compress(), into no more than the length its frame header announces
+
+   The length is checked against `max_len` before anything is allocated, so a small payload can't expand
+   into an arbitrarily large one, and a payload holding more than announced fails. A frame announcing no
+   length (not from compress()) is refused.
+*/
+pub fn decompress(bytes: &[u8], max_len: usize) -> io::Result<Vec<u8>> {
+    let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, reason);
+    let announced = match zstd::zstd_safe::get_frame_content_size(bytes) {
+        Ok(Some(len)) => len,
+        Ok(None) => return Err(invalid("compressed payload without its length".to_string())),
+        Err(_) => return Err(invalid("corrupted compressed payload".to_string())),
+    };
+    if announced > max_len as u64 {
+        return Err(invalid(format!(
+            "compressed payload of {announced} bytes, longer than {max_len}"
+        )));
+    }
+    zstd::bulk::decompress(bytes, announced as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_payload_comes_back_as_is() {
+        let payload = vec![7u8; 4 * MIN_COMPRESSED_LEN];
+        let compressed = compress(&payload, 3).unwrap();
+        assert!(compressed.len() < payload.len());
+        assert_eq!(decompress(&compressed, payload.len()).unwrap(), payload);
+    }
+
+    #[test]
+    fn decompress_refuses_more_than_the_cap() {
+        let payload = vec![7u8; 4 * MIN_COMPRESSED_LEN];
+        let compressed = compress(&payload, 3).unwrap();
+        assert!(decompress(&compressed, payload.len() - 1).is_err());
+
+        // nor is a frame without its length accepted
+        let mut encoder = zstd::stream::Encoder::new(vec![], 3).unwrap();
+        encoder.include_contentsize(false).unwrap();
+        std::io::Write::write_all(&mut encoder, &payload).unwrap();
+        assert!(decompress(&encoder.finish().unwrap(), MAX_DECOMPRESSED_LEN).is_err());
+    }
+}
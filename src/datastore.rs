@@ -9,107 +9,1898 @@
 
 extern crate alloc;
 use alloc::sync::Arc;
-use core::fmt::{self, Debug};
-use core::sync::atomic::{AtomicU32, Ordering};
+use core::fmt::{self, Debug, Display};
+use core::hash::{Hash, Hasher};
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+use std::hash::DefaultHasher;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::pin::pin;
+use std::time::Instant;
 
+use crate::codec::{BincodeCodec, Codec};
+use crate::error::FusionError;
+use crate::partition::Partitioner;
+use crate::rpc::{CallReport, LeaseMode, Neighbors, RPCResPayload, RPC};
+use crate::store::{InMemoryStore, VertexStore};
 use crate::vertex::{
-    Data, LocalVertex, MachineID, RemoteVertex, Vertex, VertexID, VertexKind, VertexType,
+    memo_call, run_memoized, run_with_limits, CallLimits, Data, LocalVertex, MachineID,
+    RemoteVertex, Vertex, VertexID, VertexKind, VertexType,
 };
-use crate::worker::Worker;
+use crate::worker::{MachineHealth, MachineRpcMetrics, Worker};
+use crate::{UdfId, UserDefinedFunction};
 
+use futures::future::{join_all, try_join_all, FutureExt, LocalBoxFuture};
+use futures::stream::{FuturesUnordered, Stream, StreamExt};
 use hashbrown::{HashMap, HashSet};
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tracing::debug;
+use uuid::Uuid;
 
-pub struct DataStore<T: Serialize + DeserializeOwned + Debug + Default, V: Debug> {
-    pub(crate) map: HashMap<VertexID, Vertex<T, V>>,
-    pub(crate) next_id: AtomicU32,
+// upper bound on the machines visited when following redirects, guards against stale loops
+pub const MAX_REDIRECT_HOPS: usize = 8;
+
+// local executes between two yields to the rpc handlers, unless configured otherwise
+pub const DEFAULT_YIELD_EVERY_N_VERTICES: usize = 256;
+
+// ids handed out by next_vertex_id() carry the issuing machine in their high bits, keeping them unique across machines
+pub const MACHINE_ID_BITS: u32 = 8;
+const LOCAL_ID_BITS: u32 = VertexID::BITS - MACHINE_ID_BITS;
+
+// deepest recursion an execution may reach, unless configured otherwise
+// Note: kept well below where polling that many nested futures would overflow a thread's stack
+pub const DEFAULT_MAX_EXEC_DEPTH: usize = 1024;
+
+/*
+   Statistics of the executions on this machine
+       max_depth: deepest recursion a vertex was executed at here, counted from the root of the call
+                  on whichever machine it started
+*/
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecStats {
+    pub max_depth: usize,
+}
+
+/*
+   A vertex as written by DataStore::snapshot(), remote references only keeping the machine they point to
+*/
+#[derive(Serialize, Deserialize)]
+enum VertexSnapshot<T> {
+    Local {
+        id: VertexID,
+        incoming: HashSet<VertexID>,
+        outgoing: HashSet<VertexID>,
+        edges: HashSet<VertexID>,
+        data: Option<Data<T>>,
+        borrowed: bool,
+    },
+    Remote {
+        id: VertexID,
+        location: MachineID,
+    },
+}
+
+/*
+   A change to the graph, as received from an event stream (see DataStore::ingest())
+*/
+#[derive(Debug)]
+pub enum EdgeEvent<T> {
+    AddEdge(VertexID, VertexID), // (from, to)
+    RemoveEdge(VertexID, VertexID),
+    SetData(VertexID, Data<T>),
+}
+
+pub struct DataStore<
+    T: Serialize + DeserializeOwned + Debug + Default,
+    V: Debug,
+    C = BincodeCodec,
+    P = C,
+> {
+    pub(crate) map: Box<dyn VertexStore<T, V, C, P>>,
+    pub(crate) next_id: AtomicU32, // local part of the next id handed out by next_vertex_id()
+    pub(crate) worker: Arc<Worker<T, V, C, P>>,
+    owner_cache: std::sync::RwLock<HashMap<VertexID, MachineID>>, // resolved owners of remote vertices
+    subtree_hashes: std::sync::RwLock<HashMap<VertexID, (u64, u64, u64)>>, // (data version, children hash, subtree hash)
+    udf_usage: RwLock<HashMap<UdfId, UdfUsage>>, // execution time of the UDFs served by this machine
+    yield_every_n_vertices: usize, // local executes between yields to the other tasks, 0 never yields
+    local_executes: AtomicUsize,   // local executes so far, paced by yield_every_n_vertices
+    max_exec_depth: usize,         // recursion depth past which executes fail with MaxDepthExceeded
+    max_depth_reached: AtomicUsize, // see ExecStats
+    memos: std::sync::Mutex<HashMap<u64, Memo<V>>>, // memoized calls in progress, by call
+    next_memo_call: AtomicU64,
+    remote_results: std::sync::Mutex<Option<RemoteResults<V>>>, // None unless enabled, see enable_remote_result_cache()
+    lenders: std::sync::Mutex<HashMap<VertexID, (MachineID, Uuid)>>, // owners of the vertices borrowed here and the leases, see borrow()
+    traversals: std::sync::Mutex<HashMap<u64, HashSet<VertexID>>>, // vertices of this machine each traversal reached, see first_visit()
+    cached_subtrees: std::sync::Mutex<HashMap<VertexID, Vec<VertexID>>>, // vertices borrowed for reading per root, see cache_subtree()
 } // vertex_id -> vertex mapping
 
-impl<T, V> Debug for DataStore<T, V>
-where
-    T: Serialize + DeserializeOwned + Debug + Default,
-    V: Debug,
-{
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "DataStore {{")?;
-        for (_, vertex) in &self.map {
-            writeln!(f, "\t{:?}", vertex)?;
+/*
+   Execution time a UDF used on this machine in the current window, against its quota if it has one
+*/
+struct UdfUsage {
+    quota: Option<(Duration, Duration)>, // (budget, window)
+    window_start: Instant,
+    used: Duration,
+}
+
+impl UdfUsage {
+    fn new(now: Instant) -> Self {
+        UdfUsage {
+            quota: None,
+            window_start: now,
+            used: Duration::ZERO,
+        }
+    }
+
+    // starting over once the window is through
+    fn roll_window(&mut self, now: Instant) {
+        if let Some((_, window)) = self.quota {
+            if now.duration_since(self.window_start) >= window {
+                self.window_start = now;
+                self.used = Duration::ZERO;
+            }
+        }
+    }
+}
+
+/*
+   Results of the vertices executed so far by one memoized call
+
+   V is only required to be Clone by apply_function_memoized(), which hands its clone over with the results
+*/
+struct Memo<V> {
+    clone: fn(&V) -> V,
+    results: HashMap<VertexID, V>,
+}
+
+/*
+   Results of remote executes kept across calls, by vertex and hash of the encoded auxiliary information
+*/
+struct RemoteResults<V> {
+    clone: fn(&V) -> V,
+    results: HashMap<(VertexID, u64), V>,
+}
+
+// forgetting the results of a memoized call once it returns (or is dropped)
+struct MemoGuard<'a, V> {
+    memos: &'a std::sync::Mutex<HashMap<u64, Memo<V>>>,
+    call: u64,
+}
+
+impl<V> Drop for MemoGuard<'_, V> {
+    fn drop(&mut self) {
+        self.memos.lock().unwrap().remove(&self.call);
+    }
+}
+
+impl<T, V, C, P> Debug for DataStore<T, V, C, P>
+where
+    T: Serialize + DeserializeOwned + Debug + Default,
+    V: Debug,
+    C: Codec,
+    P: Codec,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "DataStore {{")?;
+        for (_, vertex) in self.map.iter_local() {
+            writeln!(f, "\t{:?}", vertex)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Debug + Default, V: Debug, C: Codec, P: Codec>
+    DataStore<T, V, C, P>
+{
+    pub fn new(worker: Arc<Worker<T, V, C, P>>) -> Self
+    where
+        T: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        Self::with_store(worker, Box::new(InMemoryStore::new()))
+    }
+
+    /*
+       Constructor, loading the graph from a file of `src dst` pairs, one directed edge per line
+
+       `partition` assigns every vertex to the machine owning it. The vertices of this machine become local
+       vertices with the default data, the endpoints of their edges owned elsewhere become remote references,
+       and the edges between two vertices of other machines are left to those machines.
+       Duplicate edges are recorded once, blank lines and lines starting with # are skipped.
+    */
+    pub fn from_edge_list(
+        worker: Arc<Worker<T, V, C, P>>,
+        path: &Path,
+        partition: impl Partitioner,
+    ) -> io::Result<Self>
+    where
+        T: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        let machine_id = worker.machine_id;
+
+        // (incoming, outgoing) of the local vertices, and the machines of the remote endpoints
+        let mut local: HashMap<VertexID, (HashSet<VertexID>, HashSet<VertexID>)> = HashMap::new();
+        let mut remote: HashMap<VertexID, MachineID> = HashMap::new();
+        for (line_no, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: expected `src dst`, got `{line}`", line_no + 1),
+                )
+            };
+            let mut endpoints = line.split_whitespace().map(str::parse::<VertexID>);
+            let (Some(Ok(src)), Some(Ok(dst)), None) =
+                (endpoints.next(), endpoints.next(), endpoints.next())
+            else {
+                return Err(invalid());
+            };
+
+            let (src_owner, dst_owner) = (partition.owner(src), partition.owner(dst));
+            if src_owner != machine_id && dst_owner != machine_id {
+                continue;
+            }
+            for (v_id, owner) in [(src, src_owner), (dst, dst_owner)] {
+                if owner == machine_id {
+                    local.entry(v_id).or_default();
+                } else {
+                    remote.insert(v_id, owner);
+                }
+            }
+            if let Some((_, outgoing)) = local.get_mut(&src) {
+                outgoing.insert(dst);
+            }
+            if let Some((incoming, _)) = local.get_mut(&dst) {
+                incoming.insert(src);
+            }
+        }
+
+        let data_store = Self::new(worker.clone());
+        for (v_id, (incoming, outgoing)) in local {
+            let edges = incoming.union(&outgoing).copied().collect();
+            let local_v = LocalVertex::new(incoming, outgoing, edges, Some(Data::default()));
+            data_store.add_vertex(
+                v_id,
+                Vertex {
+                    id: v_id,
+                    v_type: VertexType::Local(local_v),
+                },
+            );
+        }
+        for (v_id, location) in remote {
+            data_store.add_vertex(
+                v_id,
+                Vertex {
+                    id: v_id,
+                    v_type: VertexType::Remote(RemoteVertex::new(location, worker.clone())),
+                },
+            );
+        }
+        Ok(data_store)
+    }
+
+    /*
+       Constructor, keeping the vertices in the given storage backend instead of in memory
+    */
+    pub fn with_store(
+        worker: Arc<Worker<T, V, C, P>>,
+        store: Box<dyn VertexStore<T, V, C, P>>,
+    ) -> Self {
+        Self {
+            map: store,
+            next_id: AtomicU32::new(0),
+            worker,
+            owner_cache: std::sync::RwLock::new(HashMap::new()),
+            subtree_hashes: std::sync::RwLock::new(HashMap::new()),
+            udf_usage: RwLock::new(HashMap::new()),
+            yield_every_n_vertices: DEFAULT_YIELD_EVERY_N_VERTICES,
+            local_executes: AtomicUsize::new(0),
+            max_exec_depth: DEFAULT_MAX_EXEC_DEPTH,
+            max_depth_reached: AtomicUsize::new(0),
+            memos: std::sync::Mutex::new(HashMap::new()),
+            next_memo_call: AtomicU64::new(0),
+            remote_results: std::sync::Mutex::new(None),
+            lenders: std::sync::Mutex::new(HashMap::new()),
+            traversals: std::sync::Mutex::new(HashMap::new()),
+            cached_subtrees: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+    /*
+       Fairness between local compute and serving rpc
+
+       Everything runs on the same LocalSet, so a deep local recursion that never waits on a remote would hold
+       off the rpc handlers until it's done. Every n local executes, the recursion yields to let them run.
+       0 never yields, trading the responsiveness to peers for the throughput of local folds.
+    */
+    pub fn set_yield_every_n_vertices(&mut self, n: usize) {
+        self.yield_every_n_vertices = n;
+    }
+
+    // called before every local execute
+    pub(crate) async fn pace_local_execute(&self) {
+        let n = self.yield_every_n_vertices;
+        if n != 0 && self.local_executes.fetch_add(1, Ordering::Relaxed) % n == n - 1 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    /*
+       Capping the depth of the recursion, as a guard against malformed graphs (e.g. ones with cycles)
+
+       The depth is carried along remote calls, so the cap holds for the whole call rather than per machine.
+       Crossing it fails the call with FusionError::MaxDepthExceeded, returned by the entry points that
+       return a Result (apply_function_accounted(), spawn_apply(), ...), apply_function() panics with it.
+    */
+    pub fn set_max_exec_depth(&mut self, n: usize) {
+        self.max_exec_depth = n;
+    }
+
+    pub fn exec_stats(&self) -> ExecStats {
+        ExecStats {
+            max_depth: self.max_depth_reached.load(Ordering::Relaxed),
+        }
+    }
+
+    // the remote executes issued from this machine, per machine, see Worker::metrics_snapshot()
+    pub fn metrics_snapshot(&self) -> HashMap<MachineID, MachineRpcMetrics> {
+        self.worker.metrics_snapshot()
+    }
+
+    // called before executing `v_id` at `depth`, failing it past the maximum depth
+    pub(crate) fn enter_depth(&self, v_id: VertexID, depth: usize) -> Result<(), FusionError> {
+        self.max_depth_reached.fetch_max(depth, Ordering::Relaxed);
+        if depth > self.max_exec_depth {
+            debug!(
+                vertex_id = v_id,
+                max_depth = self.max_exec_depth,
+                "vertex is past the maximum depth"
+            );
+            return Err(FusionError::MaxDepthExceeded(v_id, self.max_exec_depth));
+        }
+        Ok(())
+    }
+
+    /*
+       Adding an existing Vertex
+    */
+    pub fn add_vertex(&self, v_id: VertexID, vertex: Vertex<T, V, C, P>) {
+        self.reserve_id(v_id);
+        self.map.insert(v_id, Arc::new(vertex));
+    }
+
+    // keeping fresh ids above the existing ones of this machine's namespace, see next_vertex_id()
+    fn reserve_id(&self, v_id: VertexID) {
+        if v_id >> LOCAL_ID_BITS == self.worker.machine_id {
+            let local_id = v_id & ((1 << LOCAL_ID_BITS) - 1);
+            self.next_id.fetch_max(local_id + 1, Ordering::Relaxed);
+        }
+    }
+
+    /*
+       Handing out a VertexID no other inserter, on this machine or another, has been given
+
+       The high MACHINE_ID_BITS hold this machine's id and the rest come from an atomic counter, which is kept
+       above every id of that namespace added so far
+    */
+    pub fn next_vertex_id(&self) -> VertexID {
+        assert!(
+            self.worker.machine_id < 1 << MACHINE_ID_BITS,
+            "machine id {} does not fit in the vertex id namespace",
+            self.worker.machine_id
+        );
+        let local_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        assert!(local_id < 1 << LOCAL_ID_BITS, "vertex ids exhausted");
+        (self.worker.machine_id << LOCAL_ID_BITS) | local_id
+    }
+
+    /*
+       Adding a vertex from scratch
+
+       A Borrowed vertex is built from the given data, without a lease from its owner, so it can't be handed
+       back with return_borrow(). Adding it as Remote and calling borrow() fetches the owner's copy instead.
+    */
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_new_vertex(
+        &self,
+        id: VertexID,
+        incoming: &[VertexID],
+        outgoing: &[VertexID],
+        data: Option<Data<T>>,       // only exists for local nodes
+        vertex_kind: VertexKind,     // determining the type of node (remote | local)
+        location: Option<MachineID>, // only exists for remote nodes
+        worker: Arc<Worker<T, V, C, P>>,
+    ) {
+        let vertex = match vertex_kind {
+            VertexKind::Local => Vertex {
+                id,
+                v_type: VertexType::Local(LocalVertex::create_vertex(
+                    incoming,
+                    outgoing,
+                    data.expect("Local vertex must have data."),
+                )),
+            },
+            VertexKind::Remote => {
+                let location = location.expect("Remote vertex must have a location.");
+                Vertex {
+                    id,
+                    v_type: VertexType::Remote(RemoteVertex::new(location, worker)),
+                }
+            }
+            VertexKind::Borrowed => Vertex {
+                id,
+                v_type: VertexType::Borrowed(
+                    LocalVertex::create_vertex(
+                        incoming,
+                        outgoing,
+                        data.expect("Borrowed vertex must have data."),
+                    )
+                    .into_borrowed(),
+                ),
+            },
+        };
+
+        self.add_vertex(id, vertex);
+    }
+
+    /*
+       Adding a vertex from scratch, as a local vertex if `partitioner` assigns it to this machine and as a
+       remote reference to its owner otherwise (the edges and data are then left to the owner)
+    */
+    pub fn add_partitioned_vertex(
+        &self,
+        id: VertexID,
+        incoming: &[VertexID],
+        outgoing: &[VertexID],
+        data: Data<T>,
+        partitioner: &impl Partitioner,
+    ) {
+        let owner = partitioner.owner(id);
+        let worker = self.worker.clone();
+        if owner == worker.machine_id {
+            self.add_new_vertex(
+                id,
+                incoming,
+                outgoing,
+                Some(data),
+                VertexKind::Local,
+                None,
+                worker,
+            );
+        } else {
+            self.add_new_vertex(id, &[], &[], None, VertexKind::Remote, Some(owner), worker);
+        }
+    }
+
+    // Getter, assumes no error
+    pub fn get_vertex_by_id(&self, v_id: &VertexID) -> Arc<Vertex<T, V, C, P>> {
+        self.try_get_vertex_by_id(v_id)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    // Getter, for callers that can skip or report a vertex unknown here (e.g. referenced by a wrong partitioning)
+    pub fn try_get_vertex_by_id(
+        &self,
+        v_id: &VertexID,
+    ) -> Result<Arc<Vertex<T, V, C, P>>, FusionError> {
+        self.map.get(v_id).ok_or(FusionError::UnknownVertex(*v_id))
+    }
+
+    /*
+       Inserting a vertex into the live graph, its edges are also recorded on the local vertices at their other end
+
+       A remote reference held for `v_id` is replaced, like in merge(). Every neighbor has to be known already, as a
+       local vertex or a remote reference: vertices linked to each other are inserted one after the other, the edge
+       being recorded on both once the second one is in.
+    */
+    pub fn insert_vertex(
+        &self,
+        v_id: VertexID,
+        vertex: Vertex<T, V, C, P>,
+    ) -> Result<(), FusionError> {
+        if self
+            .map
+            .get(&v_id)
+            .is_some_and(|vertex| vertex.local().is_some())
+        {
+            return Err(FusionError::IdCollision(v_id));
+        }
+        if let VertexType::Local(local_v) | VertexType::Borrowed(local_v) = &vertex.v_type {
+            let neighbors: Vec<VertexID> = local_v
+                .edges()
+                .iter()
+                .copied()
+                .filter(|id| *id != v_id)
+                .collect();
+            self.check_known(&neighbors)?;
+
+            for child in local_v.children().iter() {
+                if let Some(child_v) = self.map.get(child).as_deref().and_then(Vertex::local) {
+                    child_v.add_edge(v_id, Neighbors::Parents);
+                }
+            }
+            for parent in local_v.parents().iter() {
+                if let Some(parent_v) = self.map.get(parent).as_deref().and_then(Vertex::local) {
+                    parent_v.add_edge(v_id, Neighbors::Children);
+                }
+            }
+        }
+        self.add_vertex(v_id, vertex);
+        Ok(())
+    }
+
+    /*
+       Removing a vertex from the live graph, along with every edge the local vertices have to it
+
+       A vertex lent out is refused, and so is a borrowed one, which has to go back to its owner first (see
+       return_borrow()). Removing a remote reference only drops it here, the vertex stays on its owner.
+       Note: machines holding a reference to a removed local vertex are not told, their executes on it will fail
+    */
+    pub fn remove_vertex(&self, v_id: VertexID) -> Result<Arc<Vertex<T, V, C, P>>, FusionError> {
+        let vertex = self
+            .map
+            .get(&v_id)
+            .ok_or(FusionError::UnknownVertex(v_id))?;
+        let neighbors: Vec<VertexID> = match &vertex.v_type {
+            VertexType::Local(local_v) if local_v.is_leased_out() => {
+                return Err(FusionError::LeasedOut(v_id))
+            }
+            VertexType::Local(local_v) => local_v.edges().iter().copied().collect(),
+            VertexType::Borrowed(_) => return Err(FusionError::NotOwned(v_id)),
+            // a reference doesn't record the edges to it, the local vertices do
+            VertexType::Remote(_) => self
+                .map
+                .iter_local()
+                .filter_map(|(id, vertex)| match &vertex.v_type {
+                    VertexType::Local(local_v) | VertexType::Borrowed(local_v)
+                        if local_v.edges().contains(&v_id) =>
+                    {
+                        Some(id)
+                    }
+                    _ => None,
+                })
+                .collect(),
+        };
+
+        for neighbor in neighbors {
+            if let Some(neighbor_v) = self.map.get(&neighbor).as_deref().and_then(Vertex::local) {
+                neighbor_v.remove_edge(v_id, Neighbors::Edges);
+            }
+        }
+        self.owner_cache.write().unwrap().remove(&v_id);
+        self.subtree_hashes.write().unwrap().remove(&v_id);
+        Ok(self.map.remove(&v_id).expect("checked above"))
+    }
+
+    /*
+       Merging the vertices of `other` (e.g. a partition pulled from a peer) into this DataStore
+
+       Local vertices of either side stay local, and replace the remote references the other side held to them.
+       Remote references of `other` to vertices this side doesn't have are kept, now going through this
+       machine's worker. A vertex local on both sides is a collision: nothing is merged in that case.
+    */
+    pub fn merge(&self, other: DataStore<T, V, C, P>) -> Result<(), FusionError> {
+        let ids: Vec<VertexID> = other.map.iter_local().map(|(id, _)| id).collect();
+
+        // checking every vertex before moving any, so that a collision leaves both sides untouched
+        for id in &ids {
+            let other_is_local =
+                !matches!(other.get_vertex_by_id(id).v_type, VertexType::Remote(_));
+            let self_is_local = self
+                .map
+                .get(id)
+                .is_some_and(|vertex| !matches!(vertex.v_type, VertexType::Remote(_)));
+            if other_is_local && self_is_local {
+                return Err(FusionError::IdCollision(*id));
+            }
+        }
+
+        for id in ids {
+            let vertex = other.map.remove(&id).unwrap();
+            match &vertex.v_type {
+                VertexType::Local(_) | VertexType::Borrowed(_) => {
+                    // the owner is known now, no need to resolve it anymore
+                    self.owner_cache.write().unwrap().remove(&id);
+                    self.reserve_id(id);
+                    self.map.insert(id, vertex);
+                }
+                VertexType::Remote(remote_v) => {
+                    if self.map.get(&id).is_none() {
+                        let vertex = Vertex {
+                            id,
+                            v_type: VertexType::Remote(RemoteVertex::new(
+                                remote_v.location(),
+                                self.worker.clone(),
+                            )),
+                        };
+                        self.add_vertex(id, vertex);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /*
+       Applying the events of a stream to the graph as they arrive, returns how many were applied
+
+       Events are pulled one at a time, each applied before the next is requested, so a stream producing
+       faster than the graph keeps up is held back instead of buffered. Stops at the first failing event.
+
+       An edge is recorded on whichever of its endpoints are local; remote endpoints record it on their owner,
+       from the owner's stream. SetData goes to the vertex wherever it lives. An edge to a vertex lent out
+       fails with LeasedOut like a write to its data would, leaving both endpoints untouched.
+       Note: both endpoints need to be known to this machine, as local vertices or remote references
+    */
+    pub async fn ingest<S>(&self, events: S) -> Result<usize, FusionError>
+    where
+        S: Stream<Item = EdgeEvent<T>>,
+    {
+        let mut events = pin!(events);
+        let mut applied = 0;
+        while let Some(event) = events.next().await {
+            match event {
+                EdgeEvent::AddEdge(from, to) => {
+                    self.check_known(&[from, to])?;
+                    self.check_not_leased(&[from, to])?;
+                    if let Some(from_v) = self.map.get(&from).as_deref().and_then(Vertex::local) {
+                        from_v.add_edge(to, Neighbors::Children);
+                    }
+                    if let Some(to_v) = self.map.get(&to).as_deref().and_then(Vertex::local) {
+                        to_v.add_edge(from, Neighbors::Parents);
+                    }
+                }
+                EdgeEvent::RemoveEdge(from, to) => {
+                    self.check_known(&[from, to])?;
+                    self.check_not_leased(&[from, to])?;
+                    // still adjacent in edges() through an edge the other way round, see remove_edge()
+                    if let Some(from_v) = self.map.get(&from).as_deref().and_then(Vertex::local) {
+                        from_v.remove_edge(to, Neighbors::Children);
+                    }
+                    if let Some(to_v) = self.map.get(&to).as_deref().and_then(Vertex::local) {
+                        to_v.remove_edge(from, Neighbors::Parents);
+                    }
+                }
+                EdgeEvent::SetData(id, data) => {
+                    self.check_known(&[id])?;
+                    self.get_vertex_by_id(&id).update(data).await?;
+                }
+            }
+            applied += 1;
+        }
+        Ok(applied)
+    }
+
+    /*
+       Borrowing the remote vertex `id` from its owner, to serve it here as a Borrowed vertex until
+       return_borrow(), without an rpc for every execute on it
+
+       While lent out, the owner refuses writes to the vertex and lends it to no one else (see LocalVertex::lend()).
+       The lease expires after the owner's Worker::lease_duration unless renewed with renew_borrow().
+       A vertex already served here, local or borrowed, is left as is.
+    */
+    pub async fn borrow(&self, id: VertexID) -> Result<(), FusionError> {
+        self.borrow_as(id, LeaseMode::Exclusive).await
+    }
+
+    /*
+       Borrowing the remote vertex `id` from its owner for reading only, see borrow()
+
+       Several machines can hold the vertex under a read lease at once, and the owner keeps writing it: its first
+       write (or exclusive borrow) revokes every read lease, notifying the borrowers with RPC::LeaseRevoked.
+       The vertex then points to the owner again here, while the executes already running on the borrowed copy
+       finish reading it. The copy can't be written here (FusionError::ReadOnly).
+       Fairness: writers never wait on readers and take precedence over them, a reader only ever reads data
+       as of some write, possibly one already superseded until its revocation arrives. Readers can borrow again
+       right after, so a vertex written continuously is mostly read through its owner.
+    */
+    pub async fn borrow_read(&self, id: VertexID) -> Result<(), FusionError> {
+        self.borrow_as(id, LeaseMode::Read).await
+    }
+
+    async fn borrow_as(&self, id: VertexID, mode: LeaseMode) -> Result<(), FusionError> {
+        self.check_known(&[id])?;
+        let vertex = self.get_vertex_by_id(&id);
+        let VertexType::Remote(remote_v) = &vertex.v_type else {
+            return Ok(());
+        };
+        let location = remote_v.location();
+        let (borrowed, lease) = remote_v.borrow(id, mode).await?;
+
+        self.lenders.lock().unwrap().insert(id, (location, lease));
+        self.map.insert(
+            id,
+            Arc::new(Vertex {
+                id,
+                v_type: VertexType::Borrowed(borrowed),
+            }),
+        );
+        Ok(())
+    }
+
+    /*
+       Handing the vertex borrowed with borrow() back to its owner, along with its data if written since,
+       and pointing to the owner again
+
+       With the lease expired, the owner refuses the return (LeaseExpired) and the data written here is lost,
+       the vertex points to the owner again all the same. On other errors it stays borrowed, to be retried.
+    */
+    pub async fn return_borrow(&self, id: VertexID) -> Result<(), FusionError> {
+        let Some(&(location, lease)) = self.lenders.lock().unwrap().get(&id) else {
+            return Err(FusionError::NotLeased(id));
+        };
+        let remote_v = RemoteVertex::new(location, self.worker.clone());
+        let mut res = Ok(());
+        if let VertexType::Borrowed(borrowed) = &self.get_vertex_by_id(&id).v_type {
+            res = remote_v.return_borrow(id, lease, borrowed).await;
+        }
+        if let Err(e) = &res {
+            if !matches!(e.root_cause(), FusionError::LeaseExpired(_)) {
+                return res;
+            }
+        }
+
+        self.lenders.lock().unwrap().remove(&id);
+        self.map.insert(
+            id,
+            Arc::new(Vertex {
+                id,
+                v_type: VertexType::Remote(remote_v),
+            }),
+        );
+        res
+    }
+
+    /*
+       Offloading the execution of `udf` on the subtree under `root` to the machine `to`, e.g. a less busy one
+
+       The receiving machine borrows every vertex of the subtree it doesn't own (see borrow()), runs the udf
+       there against the borrowed copies without an rpc per vertex, then returns the borrows along with what
+       the udf wrote. The vertices of this machine refuse writes until then, as for any borrow.
+       Note: as for any remote execute, `to` runs the udf it serves, which has to be the same as `udf`
+    */
+    pub async fn offload_subtree<F, U>(
+        &self,
+        root: VertexID,
+        to: MachineID,
+        _udf: &F,
+        aux_info: U,
+    ) -> Result<V, FusionError>
+    where
+        F: UserDefinedFunction<T, U, V, C, P>,
+        U: Serialize + DeserializeOwned,
+    {
+        RemoteVertex::new(to, self.worker.clone())
+            .remote_execute(root, aux_info, 0, None, RPC::Offload, false)
+            .await
+    }
+
+    /*
+       The receiving side of offload_subtree(), borrowing the subtree under `root` to run `udf` on it here
+
+       Vertices this machine doesn't know of yet are borrowed from `lender`, the machine offloading them.
+       The borrows are returned whether the execution succeeded or not, the first error is the one reported.
+    */
+    pub(crate) async fn run_borrowed<F, U>(
+        self: &Arc<Self>,
+        root: VertexID,
+        lender: MachineID,
+        udf: &F,
+        aux_info: U,
+    ) -> Result<V, FusionError>
+    where
+        F: UserDefinedFunction<T, U, V, C, P>,
+        U: Serialize + DeserializeOwned,
+    {
+        let mut borrowed = vec![];
+        let mut seen = HashSet::new();
+        let mut pending = vec![root];
+        let mut res = Ok(());
+        while let Some(id) = pending.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+            if self.map.get(&id).is_none() {
+                let remote_v = RemoteVertex::new(lender, self.worker.clone());
+                self.add_vertex(
+                    id,
+                    Vertex {
+                        id,
+                        v_type: VertexType::Remote(remote_v),
+                    },
+                );
+            }
+            if let VertexType::Remote(_) = self.get_vertex_by_id(&id).v_type {
+                if let Err(e) = self.borrow(id).await {
+                    res = Err(e);
+                    break;
+                }
+                borrowed.push(id);
+            }
+            pending.extend(self.get_vertex_by_id(&id).children().iter());
+        }
+
+        let mut res = match res {
+            Ok(()) => {
+                self.get_vertex_by_id(&root)
+                    .apply_function(udf, self.clone(), aux_info)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        for id in borrowed {
+            if let Err(e) = self.return_borrow(id).await {
+                res = res.and(Err(e));
+            }
+        }
+        res
+    }
+
+    /*
+       Caching the vertices up to `radius` hops below `root` here, for repeated executes over them to run
+       without an rpc per vertex
+
+       The remote ones are borrowed for reading (see borrow_read()) and kept until invalidate_subtree(root).
+       A vertex its owner writes in the meantime is dropped from the cache by the revocation, and read through
+       its owner from then on. Children this machine doesn't know of are borrowed from the owner of their parent.
+       On failure, what was borrowed so far stays cached until invalidated.
+    */
+    pub async fn cache_subtree(&self, root: VertexID, radius: usize) -> Result<(), FusionError> {
+        let mut seen: HashSet<VertexID> = [root].into_iter().collect();
+        let mut level = vec![root];
+        for depth in 0..=radius {
+            let mut next = vec![];
+            for id in level {
+                let vertex = self.try_get_vertex_by_id(&id)?;
+                if let VertexType::Remote(remote_v) = &vertex.v_type {
+                    let location = remote_v.location();
+                    self.borrow_read(id).await?;
+                    self.cached_subtrees
+                        .lock()
+                        .unwrap()
+                        .entry(root)
+                        .or_default()
+                        .push(id);
+                    if depth < radius {
+                        self.add_unknown(&self.get_vertex_by_id(&id).children(), location);
+                    }
+                }
+                if depth < radius {
+                    let children = self.get_vertex_by_id(&id).children();
+                    next.extend(children.iter().filter(|child| seen.insert(**child)));
+                }
+            }
+            level = next;
+        }
+        Ok(())
+    }
+
+    /*
+       Handing back the vertices cached with cache_subtree(root), the first failure is the one reported
+    */
+    pub async fn invalidate_subtree(&self, root: VertexID) -> Result<(), FusionError> {
+        let cached = self.cached_subtrees.lock().unwrap().remove(&root);
+        let mut res = Ok(());
+        for id in cached.into_iter().flatten() {
+            match self.return_borrow(id).await {
+                // revoked by its owner since
+                Err(FusionError::NotLeased(_)) => {}
+                Err(e) => res = res.and(Err(e)),
+                Ok(()) => {}
+            }
+        }
+        res
+    }
+
+    // remote references to `location` for the vertices of `ids` this machine doesn't know of
+    fn add_unknown(&self, ids: &HashSet<VertexID>, location: MachineID) {
+        for &id in ids {
+            if self.map.get(&id).is_none() {
+                let remote_v = RemoteVertex::new(location, self.worker.clone());
+                self.add_vertex(
+                    id,
+                    Vertex {
+                        id,
+                        v_type: VertexType::Remote(remote_v),
+                    },
+                );
+            }
+        }
+    }
+
+    // the owner revoked the read lease `lease` on `id` (see borrow_read()), pointing to the owner again
+    pub(crate) fn lease_revoked(&self, id: VertexID, lease: Uuid) {
+        let mut lenders = self.lenders.lock().unwrap();
+        let Some(&(location, _)) = lenders.get(&id).filter(|(_, current)| *current == lease) else {
+            // already returned, or borrowed again since
+            return;
+        };
+        lenders.remove(&id);
+        self.map.insert(
+            id,
+            Arc::new(Vertex {
+                id,
+                v_type: VertexType::Remote(RemoteVertex::new(location, self.worker.clone())),
+            }),
+        );
+    }
+
+    // keeping the vertex borrowed with borrow() for another lease duration of its owner
+    pub async fn renew_borrow(&self, id: VertexID) -> Result<(), FusionError> {
+        let Some(&(location, lease)) = self.lenders.lock().unwrap().get(&id) else {
+            return Err(FusionError::NotLeased(id));
+        };
+        RemoteVertex::new(location, self.worker.clone())
+            .renew_lease(id, lease)
+            .await
+    }
+
+    fn check_known(&self, ids: &[VertexID]) -> Result<(), FusionError> {
+        match ids.iter().find(|id| self.map.get(id).is_none()) {
+            Some(id) => Err(FusionError::UnknownVertex(*id)),
+            None => Ok(()),
+        }
+    }
+
+    fn check_not_leased(&self, ids: &[VertexID]) -> Result<(), FusionError> {
+        let leased = |id: &&VertexID| {
+            self.map
+                .get(id)
+                .as_deref()
+                .and_then(Vertex::local)
+                .is_some_and(LocalVertex::is_leased_out)
+        };
+        match ids.iter().find(leased) {
+            Some(id) => Err(FusionError::LeasedOut(*id)),
+            None => Ok(()),
+        }
+    }
+
+    // every remote reference held by this machine, with the machine it points to
+    fn remote_vertices(&self) -> impl Iterator<Item = (VertexID, MachineID)> + '_ {
+        self.map
+            .iter_local()
+            .filter_map(|(id, vertex)| match &vertex.v_type {
+                VertexType::Remote(remote_v) => Some((id, remote_v.location())),
+                VertexType::Local(_) | VertexType::Borrowed(_) => None,
+            })
+    }
+
+    /*
+       Pre-flight check that every remote reference points to a machine this one has a connection to
+
+       Returns the (vertex, machine) pairs that would fail with FusionError::UnknownMachine once reached,
+       sorted by vertex, instead of finding out in the middle of a computation
+    */
+    pub async fn verify_remotes(&self) -> Result<(), Vec<(VertexID, MachineID)>> {
+        let rpc_sending_streams = self.worker.rpc_sending_streams.read().await;
+        let mut unreachable: Vec<_> = self
+            .remote_vertices()
+            .filter(|(_, location)| !rpc_sending_streams.contains_key(location))
+            .collect();
+        if unreachable.is_empty() {
+            return Ok(());
+        }
+        unreachable.sort_unstable();
+        Err(unreachable)
+    }
+
+    /*
+       Startup check that every machine a remote reference points to answers, before any computation reaches it
+
+       Pings them all at once over their rpc streams, a broken stream being reconnected to its address in
+       peer_addresses on the way (see Worker::send_rpc()). Fails with Unreachable, sorted by machine, listing
+       every machine without a connection, shutting down, or not answering within `timeout`, along with why
+    */
+    pub async fn connect_all(&self, timeout: Duration) -> Result<(), FusionError> {
+        let machines: HashSet<MachineID> = self.remote_vertices().map(|(_, m_id)| m_id).collect();
+        let pings = machines.into_iter().map(|machine| async move {
+            let pong = tokio::time::timeout(
+                timeout,
+                self.worker
+                    .request(machine, |id, len| RPC::Ping(id, 0, len), vec![]),
+            )
+            .await;
+            let e = match pong {
+                Ok(Ok(RPCResPayload::PongResPayload(false))) => return None,
+                Ok(Ok(RPCResPayload::PongResPayload(true))) => {
+                    self.worker
+                        .set_health(machine, MachineHealth::Draining)
+                        .await;
+                    FusionError::Draining(machine)
+                }
+                Ok(Ok(other)) => panic!("received other rpc payload than pong: {other:?}"),
+                Ok(Err(e)) => e,
+                Err(_) => FusionError::Timeout(timeout),
+            };
+            Some((machine, e))
+        });
+
+        let mut unreachable: Vec<_> = join_all(pings).await.into_iter().flatten().collect();
+        if unreachable.is_empty() {
+            return Ok(());
+        }
+        unreachable.sort_unstable_by_key(|(m_id, _)| *m_id);
+        Err(FusionError::Unreachable(unreachable))
+    }
+
+    /*
+       Joining values computed elsewhere (e.g. by another computation) back onto the local vertices
+
+       Every vertex held here (remote references excluded) is combined with the value under its id,
+       None if other_values has none. Results are collected straight from the iteration, without
+       copying the vertices or the values.
+    */
+    pub fn join_with<O, R>(
+        &self,
+        other_values: &HashMap<VertexID, O>,
+        combine: impl Fn(VertexID, &Vertex<T, V, C, P>, Option<&O>) -> R,
+    ) -> HashMap<VertexID, R> {
+        self.map
+            .iter_local()
+            .filter(|(_, vertex)| !matches!(vertex.v_type, VertexType::Remote(_)))
+            .map(|(id, vertex)| (id, combine(id, &vertex, other_values.get(&id))))
+            .collect()
+    }
+
+    /*
+       Resolving the machine that currently owns a vertex
+
+       A remote reference may point to a machine that no longer holds the vertex (e.g. after a migration),
+       in which case that machine redirects to the next one it knows of. The chain is followed until a
+       machine claims the vertex, and the result is cached so later calls skip the round trips. A machine on the
+       way that doesn't know the vertex at all fails the lookup with FusionError::UnknownVertex.
+
+       Borrowed vertices are served locally, hence resolve to this machine just like local ones.
+    */
+    pub async fn resolve_owner(&self, id: VertexID) -> Result<MachineID, FusionError> {
+        if let Some(owner) = self.owner_cache.read().unwrap().get(&id) {
+            return Ok(*owner);
+        }
+
+        let mut location = match &self.try_get_vertex_by_id(&id)?.v_type {
+            VertexType::Local(_) | VertexType::Borrowed(_) => return Ok(self.worker.machine_id),
+            VertexType::Remote(remote_v) => remote_v.location(),
+        };
+
+        // coming back to an already visited machine means the redirects form a loop
+        let mut visited: HashSet<MachineID> = HashSet::from([self.worker.machine_id]);
+        for _ in 0..MAX_REDIRECT_HOPS {
+            if !visited.insert(location) {
+                return Err(FusionError::RedirectLoop(id));
+            }
+
+            let res = self
+                .worker
+                .request(location, |uuid, len| RPC::Redirect(uuid, id, len), vec![])
+                .await
+                .map_err(|e| e.at(id, location))?;
+            match res {
+                RPCResPayload::RedirectResPayload(None) => {
+                    self.owner_cache.write().unwrap().insert(id, location);
+                    return Ok(location);
+                }
+                RPCResPayload::RedirectResPayload(Some(next_hop)) => location = next_hop,
+                RPCResPayload::ErrorResPayload(e) => return Err(e.at(id, location)),
+                other => {
+                    panic!(
+                        "received other rpc payload than redirect response: {:?}",
+                        other
+                    )
+                }
+            }
+        }
+
+        Err(FusionError::RedirectLoop(id))
+    }
+
+    /*
+       Whether this is the first time `traversal` reaches the local vertex `v_id`, for UDFs to visit every vertex
+       of a graph with cycles or shared descendants once (see new_traversal())
+
+       Every traversal has a visited set of its own on each machine, the machine owning a vertex being the one
+       executing it, so any number of traversals can run over the same vertices at once. The sets are kept until
+       end_traversal().
+    */
+    pub fn first_visit(&self, traversal: u64, v_id: VertexID) -> bool {
+        self.traversals
+            .lock()
+            .unwrap()
+            .entry(traversal)
+            .or_default()
+            .insert(v_id)
+    }
+
+    /*
+       Dropping the visited sets of `traversal`, here and on every connected machine, once it is done
+
+       Called by whoever started the traversal, after its root returns. The other machines are told through
+       RPC::EndTraversal, which isn't answered: a machine that can't be reached keeps its set.
+    */
+    pub async fn end_traversal(&self, traversal: u64) {
+        self.forget_traversal(traversal);
+
+        let trailing = C::encode(&traversal).unwrap();
+        let Ok(command) = self
+            .worker
+            .frame_request(|len| RPC::EndTraversal(Uuid::new_v4(), 0, len), trailing)
+        else {
+            return;
+        };
+        for (machine, rpc_sending_stream) in self.worker.rpc_sending_streams.read().await.iter() {
+            let sent = rpc_sending_stream
+                .lock()
+                .await
+                .send(
+                    &command,
+                    self.worker.flush_policy,
+                    self.worker.rpc_key.as_deref(),
+                )
+                .await;
+            if let Err(e) = sent {
+                debug!(error = %e, machine, traversal, "failed to end the traversal");
+            }
+        }
+    }
+
+    // dropping the visited set of `traversal` on this machine only, see RPC::EndTraversal
+    pub(crate) fn forget_traversal(&self, traversal: u64) {
+        self.traversals.lock().unwrap().remove(&traversal);
+    }
+
+    /*
+       Merkle-style hash of the subtree rooted at `root`, for telling whether anything below changed between runs
+
+       A vertex hashes its serialized data together with the hashes of its children, computed bottom-up through
+       children() (so, like GraphSum, it assumes there are no cycles). Each local vertex caches its hash with the
+       data version and children hash it was computed from: the traversal still visits every vertex, but only
+       vertices whose data or descendants changed hash their data again. Remote children are hashed by their
+       owner, which keeps its own cache.
+
+       Note: DefaultHasher is only stable within a build, hashes shouldn't be compared across builds
+    */
+    pub async fn subtree_hash(&self, root: VertexID) -> Result<u64, FusionError> {
+        self.subtree_hash_rec(root).await
+    }
+
+    fn subtree_hash_rec(&self, id: VertexID) -> LocalBoxFuture<'_, Result<u64, FusionError>> {
+        async move {
+            let vertex = self.try_get_vertex_by_id(&id)?;
+            let local_v = match &vertex.v_type {
+                VertexType::Local(local_v) | VertexType::Borrowed(local_v) => local_v,
+                VertexType::Remote(remote_v) => {
+                    let res = self
+                        .worker
+                        .request(
+                            remote_v.location(),
+                            |uuid, len| RPC::SubtreeHash(uuid, id, len),
+                            vec![],
+                        )
+                        .await
+                        .map_err(|e| e.at(id, remote_v.location()))?;
+                    return match res {
+                        RPCResPayload::SubtreeHashResPayload(hash) => Ok(hash),
+                        RPCResPayload::ErrorResPayload(e) => Err(e.at(id, remote_v.location())),
+                        other => {
+                            panic!(
+                                "received other rpc payload than subtree hash response: {:?}",
+                                other
+                            )
+                        }
+                    };
+                }
+            };
+
+            // read before the data, so that a concurrent write can only make the cache entry look outdated
+            let version = local_v.version();
+
+            // children in a fixed order, the HashSet iteration order isn't
+            let mut children: Vec<VertexID> = local_v.children().iter().copied().collect();
+            children.sort_unstable();
+            let mut hasher = DefaultHasher::new();
+            for child in children {
+                let child_hash = self
+                    .subtree_hash_rec(child)
+                    .await
+                    .map_err(|e| e.via(id, self.worker.machine_id))?;
+                (child, child_hash).hash(&mut hasher);
+            }
+            let children_hash = hasher.finish();
+
+            if let Some(&(cached_version, cached_children_hash, hash)) =
+                self.subtree_hashes.read().unwrap().get(&id)
+            {
+                if cached_version == version && cached_children_hash == children_hash {
+                    return Ok(hash);
+                }
+            }
+
+            let mut hasher = DefaultHasher::new();
+            bincode::serialize(&*local_v.get_data().await)
+                .unwrap()
+                .hash(&mut hasher);
+            children_hash.hash(&mut hasher);
+            let hash = hasher.finish();
+
+            self.subtree_hashes
+                .write()
+                .unwrap()
+                .insert(id, (version, children_hash, hash));
+            Ok(hash)
+        }
+        .boxed_local()
+    }
+
+    /*
+       Applying a UDF to many roots at once, results are returned in the order of `roots`
+
+       Local roots are executed in place, remote roots are grouped by the machine they live on and each group
+       is shipped in a single RPC::ExecuteBatch, instead of paying one Execute round trip per root. The local
+       roots and the remote groups all run concurrently, a root this machine doesn't know fails the call with
+       FusionError::UnknownVertex before anything runs.
+    */
+    pub async fn apply_function_batch<F, U>(
+        self: &Arc<Self>,
+        udf: &F,
+        roots: Vec<(VertexID, U)>,
+    ) -> Result<Vec<V>, FusionError>
+    where
+        F: UserDefinedFunction<T, U, V, C, P>,
+        U: Serialize + DeserializeOwned,
+    {
+        let mut results: Vec<Option<V>> =
+            core::iter::repeat_with(|| None).take(roots.len()).collect();
+        let mut local = vec![];
+        let mut batches: HashMap<MachineID, (Vec<usize>, Vec<_>)> = HashMap::new();
+
+        for (position, (v_id, aux_info)) in roots.into_iter().enumerate() {
+            let vertex = self.try_get_vertex_by_id(&v_id)?;
+            match &vertex.v_type {
+                VertexType::Local(_) | VertexType::Borrowed(_) => {
+                    local.push((position, vertex, aux_info))
+                }
+                VertexType::Remote(remote_v) => {
+                    let (positions, batch) = batches.entry(remote_v.location()).or_default();
+                    positions.push(position);
+                    batch.push((v_id, aux_info));
+                }
+            }
+        }
+
+        let local = try_join_all(local.into_iter().map(
+            |(position, vertex, aux_info)| async move {
+                let res = vertex.apply_function(udf, self.clone(), aux_info).await?;
+                Ok::<_, FusionError>((position, res))
+            },
+        ));
+        // one round trip per machine, all machines in parallel
+        let remote = try_join_all(batches.into_iter().map(
+            |(location, (positions, batch))| async move {
+                let values = RemoteVertex::new(location, self.worker.clone())
+                    .execute_batch(&batch)
+                    .await?;
+                Ok::<_, FusionError>(positions.into_iter().zip(values).collect::<Vec<_>>())
+            },
+        ));
+        let (local, remote) = futures::try_join!(local, remote)?;
+        // correlating results back to the roots by position
+        for (position, value) in local.into_iter().chain(remote.into_iter().flatten()) {
+            results[position] = Some(value);
         }
-        write!(f, "}}")
+
+        Ok(results
+            .into_iter()
+            .map(|res| res.expect("missing result in execute batch response"))
+            .collect())
     }
-}
 
-impl<T: Serialize + DeserializeOwned + Debug + Default, V: Debug> DataStore<T, V> {
-    pub fn new() -> Self {
-        Self {
-            map: HashMap::new(),
-            next_id: AtomicU32::new(0),
+    /*
+       Applying a UDF to every vertex this machine knows of, returning the result of each
+
+       Local vertices (borrowed ones included) are executed in place, concurrently. Remote references are
+       grouped by the machine they live on, each group in a single RPC::ExecuteBatch, all machines in parallel.
+       The UDF doesn't have to recurse into the children, a UDF computing a per-vertex value makes it a sweep
+       over the whole graph.
+       Note: a vertex referenced from no vertex of this machine isn't known here, sweep from its owner then
+    */
+    pub async fn map_all<F, U>(
+        self: &Arc<Self>,
+        udf: &F,
+        aux_info: U,
+    ) -> Result<HashMap<VertexID, V>, FusionError>
+    where
+        F: UserDefinedFunction<T, U, V, C, P>,
+        U: Serialize + DeserializeOwned + Clone,
+    {
+        let mut local = vec![];
+        let mut remote: HashMap<MachineID, Vec<VertexID>> = HashMap::new();
+        for (v_id, vertex) in self.map.iter_local() {
+            match &vertex.v_type {
+                VertexType::Local(_) | VertexType::Borrowed(_) => local.push((v_id, vertex)),
+                VertexType::Remote(remote_v) => {
+                    remote.entry(remote_v.location()).or_default().push(v_id)
+                }
+            }
         }
+
+        let local = try_join_all(local.into_iter().map(|(v_id, vertex)| {
+            let aux_info = aux_info.clone();
+            async move {
+                let res = vertex.apply_function(udf, self.clone(), aux_info).await?;
+                Ok::<_, FusionError>((v_id, res))
+            }
+        }));
+        let remote = try_join_all(remote.into_iter().map(|(location, v_ids)| {
+            let aux_info = &aux_info;
+            async move {
+                let values = RemoteVertex::new(location, self.worker.clone())
+                    .remote_execute_batch(&v_ids, aux_info)
+                    .await?;
+                Ok::<_, FusionError>(v_ids.into_iter().zip(values).collect::<Vec<_>>())
+            }
+        }));
+        let (local, remote) = futures::try_join!(local, remote)?;
+        Ok(local
+            .into_iter()
+            .chain(remote.into_iter().flatten())
+            .collect())
     }
+
     /*
-       Adding an existing Vertex
+       Starting a UDF on `root` without awaiting it, the handle can be awaited later or along with others
+
+       Aborting the handle (JoinHandle::abort(), dropping it is not enough) stops the computation, and sends
+       RPC::Cancel for the remote calls it was waiting on so that their machines stop working on them too.
+       Must be called from within a LocalSet, like the rest of the execution.
     */
-    pub fn add_vertex(&mut self, v_id: VertexID, vertex: Vertex<T, V>) {
-        self.next_id.fetch_add(1, Ordering::Relaxed);
-        self.map.insert(v_id, vertex);
+    pub fn spawn_apply<F, U>(
+        self: &Arc<Self>,
+        root: VertexID,
+        udf: &F,
+        aux_info: U,
+    ) -> JoinHandle<Result<V, FusionError>>
+    where
+        F: UserDefinedFunction<T, U, V, C, P> + 'static,
+        U: Serialize + DeserializeOwned + 'static,
+        T: 'static,
+        V: 'static,
+    {
+        let data_store = self.clone();
+        let udf = udf.clone();
+        tokio::task::spawn_local(async move {
+            data_store
+                .get_vertex_by_id(&root)
+                .apply_function(&udf, data_store.clone(), aux_info)
+                .await
+        })
     }
 
     /*
-       Adding a vertex from scratch
+       Applying a UDF to `root` while sending at most `budget` bytes over rpc, returning the result along with the
+       bytes actually sent
+
+       Commands and results count, including the ones of other machines working on the call, which are
+       handed what is left of the budget and report back what they used. Crossing it fails the whole call
+       with FusionError::BudgetExceeded.
     */
-    pub fn add_new_vertex(
-        &mut self,
-        id: VertexID,
-        incoming: &[VertexID],
-        outgoing: &[VertexID],
-        data: Option<Data<T>>,       // only exists for local nodes
-        vertex_kind: VertexKind,     // determining the type of node (remote | local)
-        location: Option<MachineID>, // only exists for remote nodes
-        worker: Arc<Worker<T, V>>,
-    ) {
-        let vertex = match vertex_kind {
-            VertexKind::Local => Vertex {
-                id,
-                v_type: VertexType::Local(LocalVertex::create_vertex(
-                    incoming,
-                    outgoing,
-                    data.expect("Local vertex must have data."),
-                )),
+    pub async fn apply_function_budgeted<F, U>(
+        self: &Arc<Self>,
+        root: VertexID,
+        udf: &F,
+        aux_info: U,
+        budget: usize,
+    ) -> Result<(V, usize), FusionError>
+    where
+        F: UserDefinedFunction<T, U, V, C, P>,
+        U: Serialize + DeserializeOwned,
+    {
+        let limits = CallLimits {
+            send_bytes: Some(budget),
+            ..Default::default()
+        };
+        let (res, used) = self
+            .apply_function_limited(root, udf, aux_info, limits)
+            .await?;
+        Ok((res, used.sent))
+    }
+
+    /*
+       Applying a UDF to `root` within `limits`, returning the result along with what the call used of them
+
+       Other machines working on the call are handed what is left and report back what they used, so the
+       limits hold for the call as a whole (see CallLimits)
+    */
+    pub async fn apply_function_limited<F, U>(
+        self: &Arc<Self>,
+        root: VertexID,
+        udf: &F,
+        aux_info: U,
+        limits: CallLimits,
+    ) -> Result<(V, CallReport), FusionError>
+    where
+        F: UserDefinedFunction<T, U, V, C, P>,
+        U: Serialize + DeserializeOwned,
+    {
+        let (res, used) = run_with_limits(
+            limits,
+            HashSet::new(),
+            self.get_vertex_by_id(&root)
+                .apply_function(udf, self.clone(), aux_info),
+        )
+        .await;
+        Ok((res?, used))
+    }
+
+    /*
+       Applying a UDF to `root`, executing each vertex at most once for the duration of the call
+
+       In a DAG, a descendant shared by several paths is otherwise executed again on every path leading to it.
+       Here the first result of every vertex is reused for the others, which is only correct if the UDF's result
+       depends only on the vertex and its subtree (e.g. not on aux_info changing along the path).
+       Note: results are kept on this machine only, so vertices reached through a remote reference are executed
+       once per reference here, and the subtree over there is executed without memoizing.
+    */
+    pub async fn apply_function_memoized<F, U>(
+        self: &Arc<Self>,
+        root: VertexID,
+        udf: &F,
+        aux_info: U,
+    ) -> Result<V, FusionError>
+    where
+        F: UserDefinedFunction<T, U, V, C, P>,
+        U: Serialize + DeserializeOwned,
+        V: Clone,
+    {
+        let call = self.next_memo_call.fetch_add(1, Ordering::Relaxed);
+        self.memos.lock().unwrap().insert(
+            call,
+            Memo {
+                clone: V::clone,
+                results: HashMap::new(),
             },
-            VertexKind::Remote => {
-                let location = location.expect("Remote vertex must have a location.");
-                Vertex {
-                    id,
-                    v_type: VertexType::Remote(RemoteVertex::new(location, worker)),
-                }
+        );
+        let _guard = MemoGuard {
+            memos: &self.memos,
+            call,
+        };
+
+        run_memoized(
+            call,
+            self.get_vertex_by_id(&root)
+                .apply_function(udf, self.clone(), aux_info),
+        )
+        .await
+    }
+
+    // result of `v_id` if the execution is part of a memoized call that already executed it
+    pub(crate) fn memoized(&self, v_id: VertexID) -> Option<V> {
+        let call = memo_call()?;
+        let memos = self.memos.lock().unwrap();
+        let memo = memos.get(&call)?;
+        memo.results.get(&v_id).map(memo.clone)
+    }
+
+    pub(crate) fn memoize(&self, v_id: VertexID, res: &V) {
+        let Some(call) = memo_call() else {
+            return;
+        };
+        if let Some(memo) = self.memos.lock().unwrap().get_mut(&call) {
+            let res = (memo.clone)(res);
+            memo.results.insert(v_id, res);
+        }
+    }
+
+    /*
+       Caching the results of remote executes, for a remote vertex reached through several paths to be
+       executed over there only once
+
+       Results are keyed by the vertex and the hash of its encoded auxiliary information, so the same vertex
+       reached with different aux_info is still executed for each. Unlike apply_function_memoized(), results
+       outlive the call: they are only correct as long as the remote subtrees don't change, clear them between
+       runs that shouldn't share them.
+       Note: only remote executes through apply_function() and try_apply_function() are cached
+    */
+    pub fn enable_remote_result_cache(&self)
+    where
+        V: Clone,
+    {
+        let mut remote_results = self.remote_results.lock().unwrap();
+        if remote_results.is_none() {
+            *remote_results = Some(RemoteResults {
+                clone: V::clone,
+                results: HashMap::new(),
+            });
+        }
+    }
+    pub fn disable_remote_result_cache(&self) {
+        *self.remote_results.lock().unwrap() = None;
+    }
+    pub fn clear_remote_result_cache(&self) {
+        if let Some(remote_results) = self.remote_results.lock().unwrap().as_mut() {
+            remote_results.results.clear();
+        }
+    }
+
+    // key of the remote execute of `v_id` with `aux_info`, if results are cached at all
+    pub(crate) fn remote_result_key<U: Serialize>(
+        &self,
+        v_id: VertexID,
+        aux_info: &U,
+    ) -> Option<(VertexID, u64)> {
+        self.remote_results.lock().unwrap().as_ref()?;
+        let mut hasher = DefaultHasher::new();
+        P::encode(aux_info).ok()?.hash(&mut hasher);
+        Some((v_id, hasher.finish()))
+    }
+
+    pub(crate) fn cached_remote_result(&self, key: (VertexID, u64)) -> Option<V> {
+        let remote_results = self.remote_results.lock().unwrap();
+        let remote_results = remote_results.as_ref()?;
+        remote_results.results.get(&key).map(remote_results.clone)
+    }
+
+    pub(crate) fn cache_remote_result(&self, key: (VertexID, u64), res: &V) {
+        if let Some(remote_results) = self.remote_results.lock().unwrap().as_mut() {
+            let res = (remote_results.clone)(res);
+            remote_results.results.insert(key, res);
+        }
+    }
+
+    /*
+       Applying a UDF to many roots, checkpointing the results of the completed ones to `checkpoint`
+
+       The results are persisted every `every_n` completed roots, once all are done, and before returning the
+       error of a root that failed. Starting over with
+       the same checkpoint (e.g. after the machine went down, or the call was aborted through spawn_apply())
+       only executes the roots it has no result for. Results are keyed by root, so the roots have to be
+       called with the same aux_info on every attempt for the resumed results to be the same.
+       Note: each write replaces the whole file, pick every_n so that rewriting it stays cheap
+    */
+    pub async fn apply_function_checkpointed<F, U>(
+        self: &Arc<Self>,
+        udf: &F,
+        roots: Vec<(VertexID, U)>,
+        checkpoint: &Path,
+        every_n: usize,
+    ) -> Result<Vec<V>, FusionError>
+    where
+        F: UserDefinedFunction<T, U, V, C, P>,
+        U: Serialize + DeserializeOwned,
+        V: Serialize + DeserializeOwned + Clone,
+    {
+        let mut completed: HashMap<VertexID, V> = match tokio::fs::read(checkpoint).await {
+            Ok(bytes) => {
+                bincode::deserialize(&bytes).map_err(|e| checkpoint_error(checkpoint, e))?
             }
-            VertexKind::Borrowed => Vertex {
-                id,
-                v_type: VertexType::Borrowed(LocalVertex::create_vertex(
-                    incoming,
-                    outgoing,
-                    data.expect("Borrowed vertex must have data."),
-                )),
-            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(checkpoint_error(checkpoint, e)),
         };
 
-        self.add_vertex(id, vertex);
+        let order: Vec<VertexID> = roots.iter().map(|(v_id, _)| *v_id).collect();
+        let mut pending: FuturesUnordered<_> = roots
+            .into_iter()
+            .filter(|(v_id, _)| !completed.contains_key(v_id))
+            .map(|(v_id, aux_info)| async move {
+                let res = self
+                    .get_vertex_by_id(&v_id)
+                    .apply_function(udf, self.clone(), aux_info)
+                    .await;
+                (v_id, res)
+            })
+            .collect();
+
+        let mut since_checkpoint = 0;
+        while let Some((v_id, res)) = pending.next().await {
+            let res = match res {
+                Ok(res) => res,
+                Err(e) => {
+                    // what completed so far is kept for the next attempt, the execution's error wins over the write's
+                    if since_checkpoint > 0 {
+                        let _ = write_checkpoint(checkpoint, &completed).await;
+                    }
+                    return Err(e);
+                }
+            };
+            completed.insert(v_id, res);
+            since_checkpoint += 1;
+            if since_checkpoint >= every_n.max(1) || pending.is_empty() {
+                write_checkpoint(checkpoint, &completed).await?;
+                since_checkpoint = 0;
+            }
+        }
+
+        Ok(order
+            .into_iter()
+            .map(|v_id| completed[&v_id].clone())
+            .collect())
     }
 
-    // Getter, assumes no error
-    pub fn get_vertex_by_id(&self, v_id: &VertexID) -> &Vertex<T, V> {
-        self.map.get(v_id).expect("node not found")
+    /*
+       Limiting the execution time the UDF `id` may use on this machine to `budget` per `window`
+
+       Only executes served for other machines are accounted, timed from the start of the UDF
+       until its result (children on other machines included, since they are awaited)
+    */
+    pub async fn set_udf_quota(&self, id: UdfId, budget: Duration, window: Duration) {
+        let mut udf_usage = self.udf_usage.write().await;
+        let usage = udf_usage
+            .entry(id)
+            .or_insert_with(|| UdfUsage::new(Instant::now()));
+        usage.quota = Some((budget, window));
+    }
+
+    // execution time the UDF `id` used in its current window
+    pub async fn udf_usage(&self, id: UdfId) -> Duration {
+        self.udf_usage
+            .read()
+            .await
+            .get(id)
+            .map_or(Duration::ZERO, |usage| usage.used)
+    }
+
+    /*
+       Executing a UDF on behalf of another machine, refused once the UDF used up its quota (see set_udf_quota())
+
+       An execute already running is not interrupted, it is charged when it returns
+    */
+    pub async fn apply_function_accounted<F, U>(
+        self: &Arc<Self>,
+        v_id: VertexID,
+        udf: &F,
+        aux_info: U,
+    ) -> Result<V, FusionError>
+    where
+        F: UserDefinedFunction<T, U, V, C, P>,
+        U: Serialize + DeserializeOwned,
+    {
+        let id = udf.udf_id();
+        if let Some(usage) = self.udf_usage.write().await.get_mut(id) {
+            usage.roll_window(Instant::now());
+            if matches!(usage.quota, Some((budget, _)) if usage.used >= budget) {
+                return Err(FusionError::QuotaExceeded(id.to_string()));
+            }
+        }
+
+        let start = Instant::now();
+        let res = self
+            .get_vertex_by_id(&v_id)
+            .apply_function(udf, self.clone(), aux_info)
+            .await;
+        let elapsed = start.elapsed();
+
+        let mut udf_usage = self.udf_usage.write().await;
+        let usage = udf_usage.entry(id).or_insert_with(|| UdfUsage::new(start));
+        usage.roll_window(Instant::now());
+        usage.used += elapsed;
+        res
+    }
+
+    /*
+       Reference executor, applying a UDF to `root` as if the graph were a single in-memory one
+
+       Meant as a test oracle for the distributed execution: it refuses to run unless every vertex is held
+       locally, so no part of the result goes through rpc. Load the whole graph on one machine, then compare
+       against what apply_function() returns on the partitioned graph.
+    */
+    pub async fn apply_function_reference<F, U>(
+        self: &Arc<Self>,
+        root: VertexID,
+        udf: &F,
+        aux_info: U,
+    ) -> Result<V, FusionError>
+    where
+        F: UserDefinedFunction<T, U, V, C, P>,
+        U: Serialize + DeserializeOwned,
+    {
+        if let Some((id, location)) = self.remote_vertices().next() {
+            return Err(FusionError::NotLocal(id, location));
+        }
+
+        self.get_vertex_by_id(&root)
+            .apply_function(udf, self.clone(), aux_info)
+            .await
+    }
+
+    /*
+       Writing the local portion of the graph as GraphML, for external tools (Gephi, NetworkX, ...)
+
+       Every vertex in the map becomes a node marked with the machine that owns it, and the children of local
+       vertices become directed edges. Only local vertices carry data, rendered by `attribute`.
+    */
+    pub async fn to_graphml_with<W: Write>(
+        &self,
+        writer: &mut W,
+        attribute: impl Fn(&T) -> String,
+    ) -> io::Result<()> {
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="data" for="node" attr.name="data" attr.type="string"/>"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="machine" for="node" attr.name="machine" attr.type="int"/>"#
+        )?;
+        writeln!(writer, r#"  <graph id="G" edgedefault="directed">"#)?;
+
+        // in id order, so that exports of the same graph are identical
+        let mut ids: Vec<VertexID> = self.map.iter_local().map(|(id, _)| id).collect();
+        ids.sort_unstable();
+
+        let mut edges = vec![];
+        for id in ids {
+            match &self.get_vertex_by_id(&id).v_type {
+                VertexType::Local(local_v) | VertexType::Borrowed(local_v) => {
+                    let data = match &*local_v.get_data().await {
+                        Some(Data(data)) => escape_xml(&attribute(data)),
+                        None => String::new(),
+                    };
+                    writeln!(
+                        writer,
+                        r#"    <node id="{id}"><data key="data">{data}</data><data key="machine">{}</data></node>"#,
+                        self.worker.machine_id
+                    )?;
+
+                    let mut children: Vec<VertexID> = local_v.children().iter().copied().collect();
+                    children.sort_unstable();
+                    edges.extend(children.into_iter().map(|child| (id, child)));
+                }
+                VertexType::Remote(remote_v) => {
+                    writeln!(
+                        writer,
+                        r#"    <node id="{id}"><data key="machine">{}</data></node>"#,
+                        remote_v.location()
+                    )?;
+                }
+            }
+        }
+
+        // after all nodes, edges may point to any of them
+        for (source, target) in edges {
+            writeln!(writer, r#"    <edge source="{source}" target="{target}"/>"#)?;
+        }
+
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</graphml>")
+    }
+
+    /*
+       Same as to_graphml_with(), with the data rendered through Display
+    */
+    pub async fn to_graphml<W: Write>(&self, writer: &mut W) -> io::Result<()>
+    where
+        T: Display,
+    {
+        self.to_graphml_with(writer, |data| data.to_string()).await
+    }
+
+    /*
+       Writing every vertex of this machine to `writer`, for a crashed machine to resume from with restore()
+
+       Local and borrowed vertices keep their edges and current data, remote references where they point to.
+       Note: versions and version history are not kept, a restored vertex starts over at version 0
+    */
+    pub async fn snapshot<W: Write>(&self, writer: &mut W) -> Result<(), FusionError> {
+        // in id order, so that snapshots of the same graph are identical
+        let mut ids: Vec<VertexID> = self.map.iter_local().map(|(id, _)| id).collect();
+        ids.sort_unstable();
+
+        let mut vertices = Vec::with_capacity(ids.len());
+        for id in ids {
+            let vertex = self.get_vertex_by_id(&id);
+            vertices.push(match &vertex.v_type {
+                VertexType::Local(local_v) | VertexType::Borrowed(local_v) => {
+                    VertexSnapshot::Local {
+                        id,
+                        incoming: local_v.parents().as_ref().clone(),
+                        outgoing: local_v.children().as_ref().clone(),
+                        edges: local_v.edges().as_ref().clone(),
+                        data: local_v.get_data_at(id, None).await?,
+                        borrowed: matches!(vertex.v_type, VertexType::Borrowed(_)),
+                    }
+                }
+                VertexType::Remote(remote_v) => VertexSnapshot::Remote {
+                    id,
+                    location: remote_v.location(),
+                },
+            });
+        }
+        bincode::serialize_into(writer, &vertices)
+            .map_err(|e| FusionError::Checkpoint(format!("failed to write the snapshot: {e}")))
+    }
+
+    /*
+       Constructor, rebuilding the vertices written by snapshot(), remote references pointing through `worker`
+    */
+    pub fn restore<R: Read>(worker: Arc<Worker<T, V, C, P>>, reader: R) -> Result<Self, FusionError>
+    where
+        T: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        let vertices: Vec<VertexSnapshot<T>> = bincode::deserialize_from(reader)
+            .map_err(|e| FusionError::Checkpoint(format!("failed to read the snapshot: {e}")))?;
+
+        let data_store = Self::new(worker.clone());
+        for vertex in vertices {
+            let (id, v_type) = match vertex {
+                VertexSnapshot::Local {
+                    id,
+                    incoming,
+                    outgoing,
+                    edges,
+                    data,
+                    borrowed,
+                } => {
+                    let local_v = LocalVertex::new(incoming, outgoing, edges, data);
+                    if borrowed {
+                        (id, VertexType::Borrowed(local_v.into_borrowed()))
+                    } else {
+                        (id, VertexType::Local(local_v))
+                    }
+                }
+                VertexSnapshot::Remote { id, location } => (
+                    id,
+                    VertexType::Remote(RemoteVertex::new(location, worker.clone())),
+                ),
+            };
+            data_store.add_vertex(id, Vertex { id, v_type });
+        }
+        Ok(data_store)
     }
 
     // perhaps provide interfaces for later on adding to the datastore during run-time.
 }
 
+// swapping the new checkpoint in whole, so that a crash mid-write leaves the previous one intact
+async fn write_checkpoint<V: Serialize>(
+    checkpoint: &Path,
+    completed: &HashMap<VertexID, V>,
+) -> Result<(), FusionError> {
+    let bytes = bincode::serialize(completed).unwrap();
+    let partial = checkpoint.with_extension("partial");
+    let res = match tokio::fs::write(&partial, bytes).await {
+        Ok(()) => tokio::fs::rename(&partial, checkpoint).await,
+        Err(e) => Err(e),
+    };
+    res.map_err(|e| checkpoint_error(checkpoint, e))
+}
+
+fn checkpoint_error(checkpoint: &Path, e: impl Display) -> FusionError {
+    FusionError::Checkpoint(format!("{}: {e}", checkpoint.display()))
+}
+
+// escaping the characters that are not allowed in GraphML text
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 // custom graph builder for testing based on machine_id (the 1,2 scenario), for now
-pub fn build_graph_integer_data<V: Debug>(
-    data_store: &mut DataStore<isize, V>,
+pub fn build_graph_integer_data<V: Debug, C: Codec, P: Codec>(
+    data_store: &mut DataStore<isize, V, C, P>,
     machine_id: MachineID,
-    worker: Arc<Worker<isize, V>>,
+    worker: Arc<Worker<isize, V, C, P>>,
 ) {
     // Note: this is specific testing function
 
@@ -424,3 +2215,489 @@ pub fn build_graph_integer_data<V: Debug>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::udf::{AccumulateSubtree, GraphReduce, Sum};
+    use crate::vertex::new_traversal;
+    use crate::worker::tests::{in_memory_pair, in_memory_pair_serving};
+    use crate::TryUserDefinedFunction;
+
+    use futures::stream;
+
+    // a machine of its own holding the local vertices `ids` without any edges, each with its id for data
+    fn local_store(ids: &[VertexID]) -> DataStore<isize, isize> {
+        let data_store = DataStore::new(Arc::new(Worker::new(1)));
+        for &id in ids {
+            let local_v = LocalVertex::create_vertex(&[], &[], Data(id as isize));
+            data_store.add_vertex(
+                id,
+                Vertex {
+                    id,
+                    v_type: VertexType::Local(local_v),
+                },
+            );
+        }
+        data_store
+    }
+
+    fn neighbors(
+        data_store: &DataStore<isize, isize>,
+        id: VertexID,
+        which: Neighbors,
+    ) -> Vec<VertexID> {
+        let mut ids: Vec<VertexID> = data_store
+            .get_vertex_by_id(&id)
+            .local()
+            .unwrap()
+            .neighbors(which)
+            .iter()
+            .copied()
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    // (id, owner if remote, parents, children)
+    type Structure = (VertexID, Option<MachineID>, Vec<VertexID>, Vec<VertexID>);
+
+    // every vertex by id, with its parents and children if local and its owner if remote
+    fn structure(data_store: &DataStore<isize, isize>) -> Vec<Structure> {
+        let mut vertices: Vec<_> = data_store
+            .map
+            .iter_local()
+            .map(|(id, vertex)| match &vertex.v_type {
+                VertexType::Remote(remote_v) => (id, Some(remote_v.location()), vec![], vec![]),
+                _ => (
+                    id,
+                    None,
+                    neighbors(data_store, id, Neighbors::Parents),
+                    neighbors(data_store, id, Neighbors::Children),
+                ),
+            })
+            .collect();
+        vertices.sort_unstable_by_key(|(id, ..)| *id);
+        vertices
+    }
+
+    // returns the data of the vertex, counting its executions, and fails on `fail_on` once the others are done
+    #[derive(Clone)]
+    struct CountedData {
+        executed: Arc<AtomicUsize>,
+        fail_on: Option<VertexID>,
+    }
+
+    #[async_trait::async_trait]
+    impl TryUserDefinedFunction<isize, (), isize> for CountedData {
+        async fn try_execute(
+            &self,
+            vertex: &Vertex<isize, isize>,
+            _data_store: Arc<DataStore<isize, isize>>,
+            _aux_info: (),
+        ) -> Result<isize, FusionError> {
+            self.executed.fetch_add(1, Ordering::Relaxed);
+            if self.fail_on == Some(vertex.id) {
+                tokio::task::yield_now().await;
+                return Err(FusionError::Udf("interrupted".to_string()));
+            }
+            Ok(vertex.get_val_async().await?.map_or(0, |data| data.0))
+        }
+    }
+
+    // sums the data of the subtree, counting the executions
+    #[derive(Clone)]
+    struct CountedSum {
+        executed: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl TryUserDefinedFunction<isize, (), isize> for CountedSum {
+        async fn try_execute(
+            &self,
+            vertex: &Vertex<isize, isize>,
+            data_store: Arc<DataStore<isize, isize>>,
+            _aux_info: (),
+        ) -> Result<isize, FusionError> {
+            self.executed.fetch_add(1, Ordering::Relaxed);
+            let mut sum = vertex.get_val_async().await?.map_or(0, |data| data.0);
+            for child in vertex.children().iter() {
+                sum += data_store
+                    .get_vertex_by_id(child)
+                    .apply_function(self, data_store.clone(), ())
+                    .await?;
+            }
+            Ok(sum)
+        }
+    }
+
+    #[tokio::test]
+    async fn memoized_apply_executes_shared_descendants_once() {
+        // three diamonds stacked: 0 -> {1, 2} -> 3 -> {4, 5} -> 6 -> {7, 8} -> 9
+        let data_store = Arc::new(local_store(&[]));
+        for top in [0, 3, 6] {
+            let edges: [(VertexID, &[VertexID], &[VertexID]); 3] = [
+                (
+                    top,
+                    if top == 0 { &[] } else { &[top - 2, top - 1] },
+                    &[top + 1, top + 2],
+                ),
+                (top + 1, &[top], &[top + 3]),
+                (top + 2, &[top], &[top + 3]),
+            ];
+            for (id, incoming, outgoing) in edges {
+                let local_v = LocalVertex::create_vertex(incoming, outgoing, Data(id as isize));
+                data_store.add_vertex(
+                    id,
+                    Vertex {
+                        id,
+                        v_type: VertexType::Local(local_v),
+                    },
+                );
+            }
+        }
+        let local_v = LocalVertex::create_vertex(&[7, 8], &[], Data(9));
+        data_store.add_vertex(
+            9,
+            Vertex {
+                id: 9,
+                v_type: VertexType::Local(local_v),
+            },
+        );
+        let udf = CountedSum {
+            executed: Arc::new(AtomicUsize::new(0)),
+        };
+
+        // once per path: the vertex under the k-th diamond is reached 2^k times
+        let plain = data_store
+            .get_vertex_by_id(&0)
+            .apply_function(&udf, data_store.clone(), ())
+            .await
+            .unwrap();
+        assert_eq!(udf.executed.swap(0, Ordering::Relaxed), 29);
+
+        let memoized = data_store
+            .apply_function_memoized(0, &udf, ())
+            .await
+            .unwrap();
+        assert_eq!(udf.executed.load(Ordering::Relaxed), 10);
+        assert_eq!(memoized, plain);
+    }
+
+    #[tokio::test]
+    async fn checkpointed_apply_resumes_after_a_failed_root() {
+        let data_store = Arc::new(local_store(&[1, 2, 3]));
+        let checkpoint = std::env::temp_dir().join(format!("fusion-checkpoint-{}", Uuid::new_v4()));
+        let roots = || vec![(1, ()), (2, ()), (3, ())];
+        let executed = Arc::new(AtomicUsize::new(0));
+
+        // interrupted by root 3, with no periodic write before it
+        let interrupted = CountedData {
+            executed: executed.clone(),
+            fail_on: Some(3),
+        };
+        let res = data_store
+            .apply_function_checkpointed(&interrupted, roots(), &checkpoint, 100)
+            .await;
+        assert!(res.is_err());
+        assert_eq!(executed.swap(0, Ordering::Relaxed), 3);
+
+        // only the root without a result runs again
+        let resumed = CountedData {
+            executed: executed.clone(),
+            fail_on: None,
+        };
+        let res = data_store
+            .apply_function_checkpointed(&resumed, roots(), &checkpoint, 100)
+            .await;
+        assert_eq!(res.unwrap(), [1, 2, 3]);
+        assert_eq!(executed.load(Ordering::Relaxed), 1);
+
+        tokio::fs::remove_file(&checkpoint).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn interleaved_traversals_keep_their_own_visits() {
+        let data_store = local_store(&[1]);
+        let (first, second) = (new_traversal(), new_traversal());
+
+        assert!(data_store.first_visit(first, 1));
+        assert!(data_store.first_visit(second, 1));
+        // the second traversal reaching the vertex doesn't make it new to the first again
+        assert!(!data_store.first_visit(first, 1));
+
+        data_store.end_traversal(first).await;
+        assert!(data_store.first_visit(first, 1));
+        assert!(!data_store.first_visit(second, 1));
+    }
+
+    #[test]
+    fn merging_the_partitions_gives_back_the_graph() {
+        // ids of machine 1's namespace, the highest of them (5) only known to machine 2
+        let base: VertexID = 1 << LOCAL_ID_BITS;
+        let edges = [(0, 1), (0, 2), (1, 3), (2, 3), (3, 4), (4, 0), (3, 5)];
+        let path = std::env::temp_dir().join(format!("fusion-edges-{}", Uuid::new_v4()));
+        let edge_list: String = edges
+            .iter()
+            .map(|(from, to)| format!("{} {}\n", base + from, base + to))
+            .collect();
+        std::fs::write(&path, edge_list).unwrap();
+        let load = |machine_id, partition: fn(VertexID) -> MachineID| {
+            DataStore::<isize, isize>::from_edge_list(
+                Arc::new(Worker::new(machine_id)),
+                &path,
+                partition,
+            )
+            .unwrap()
+        };
+        let original = load(1, |_| 1);
+        let by_parity: fn(VertexID) -> MachineID = |id| if id % 2 == 0 { 1 } else { 2 };
+        let (merged, partition_2) = (load(1, by_parity), load(2, by_parity));
+        std::fs::remove_file(&path).unwrap();
+
+        // every edge crossing the partitions was a remote reference on both sides
+        assert!(merged.remote_vertices().next().is_some());
+        merged.merge(partition_2).unwrap();
+        assert_eq!(structure(&merged), structure(&original));
+
+        // fresh ids stay clear of the ones merged in
+        assert!(merged.next_vertex_id() > base + 5);
+    }
+
+    #[test]
+    fn merge_refuses_a_vertex_local_on_both_sides() {
+        let data_store = local_store(&[1, 2]);
+        let before = structure(&data_store);
+
+        assert!(matches!(
+            data_store.merge(local_store(&[2, 3])),
+            Err(FusionError::IdCollision(2))
+        ));
+        // vertex 3 wasn't merged in either
+        assert_eq!(structure(&data_store), before);
+    }
+
+    #[tokio::test]
+    async fn offloaded_subtree_gives_the_local_result() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let sum = GraphReduce(Sum);
+                let (data_store_1, data_store_2) =
+                    in_memory_pair_serving((&sum, &AccumulateSubtree), build_graph_integer_data)
+                        .await;
+                let expected = data_store_1
+                    .get_vertex_by_id(&1)
+                    .apply_function(&sum, data_store_1.clone(), None)
+                    .await
+                    .unwrap();
+
+                // machine 2 only knew of vertex 4 of the subtree under 1, it borrows the rest
+                let offloaded = data_store_1
+                    .offload_subtree(1, 2, &sum, None)
+                    .await
+                    .unwrap();
+                assert_eq!(offloaded, expected);
+
+                // every borrow is handed back, machine 2 keeping references to the vertices it learned of
+                for id in [1, 3, 4, 7] {
+                    let vertex = data_store_1.get_vertex_by_id(&id);
+                    assert!(!vertex.local().unwrap().is_leased_out());
+                    let vertex = data_store_2.get_vertex_by_id(&id);
+                    assert!(matches!(vertex.v_type, VertexType::Remote(_)));
+                }
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn write_by_the_owner_revokes_the_read_leases() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                // vertex 1 lives on machine 2, machine 1 only holds a reference to it
+                let (data_store_1, data_store_2) =
+                    in_memory_pair(|data_store, machine_id, worker| {
+                        let (kind, data, location) = match machine_id {
+                            1 => (VertexKind::Remote, None, Some(2)),
+                            _ => (VertexKind::Local, Some(Data(10)), None),
+                        };
+                        data_store.add_new_vertex(1, &[], &[], data, kind, location, worker);
+                    })
+                    .await;
+
+                data_store_1.borrow_read(1).await.unwrap();
+                let borrowed = data_store_1.get_vertex_by_id(&1);
+                assert!(borrowed.local().unwrap().is_read_only());
+                assert!(matches!(
+                    borrowed.update(Data(11)).await,
+                    Err(FusionError::ReadOnly(1))
+                ));
+
+                // the owner isn't held back by the reader
+                let owned = data_store_2.get_vertex_by_id(&1);
+                assert!(!owned.local().unwrap().is_leased_out());
+                owned.update(Data(20)).await.unwrap();
+
+                // the reader points to the owner again once notified, an execute still holding the copy
+                // finishes reading it
+                tokio::time::timeout(Duration::from_secs(1), async {
+                    while !matches!(
+                        data_store_1.get_vertex_by_id(&1).v_type,
+                        VertexType::Remote(_)
+                    ) {
+                        tokio::task::yield_now().await;
+                    }
+                })
+                .await
+                .unwrap();
+                assert_eq!(borrowed.get_val_async().await.unwrap().unwrap().0, 10);
+                let current = data_store_1.get_vertex_by_id(&1).get_val_async().await;
+                assert_eq!(current.unwrap().unwrap().0, 20);
+                assert!(matches!(
+                    data_store_1.return_borrow(1).await,
+                    Err(FusionError::NotLeased(1))
+                ));
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn cached_subtree_is_executed_locally_until_written() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let sum = GraphReduce(Sum);
+                let (data_store_1, data_store_2) =
+                    in_memory_pair_serving((&sum, &AccumulateSubtree), build_graph_integer_data)
+                        .await;
+                let subtree_sum = || async {
+                    data_store_2
+                        .get_vertex_by_id(&4)
+                        .apply_function(&sum, data_store_2.clone(), None)
+                        .await
+                        .unwrap()
+                };
+                let executes = || {
+                    data_store_2
+                        .metrics_snapshot()
+                        .get(&1)
+                        .map_or(0, |metrics| metrics.executes)
+                };
+                let uncached = subtree_sum().await;
+                assert_eq!(executes(), 1);
+
+                // vertex 4 and its child 7 are borrowed from machine 1, 8 and 9 are already here
+                data_store_2.cache_subtree(4, 1).await.unwrap();
+                assert_eq!(subtree_sum().await, uncached);
+                assert_eq!(executes(), 1);
+
+                // the owner writing 7 drops it from the cache
+                data_store_1
+                    .get_vertex_by_id(&7)
+                    .update(Data(9))
+                    .await
+                    .unwrap();
+                tokio::time::timeout(Duration::from_secs(1), async {
+                    while !matches!(
+                        data_store_2.get_vertex_by_id(&7).v_type,
+                        VertexType::Remote(_)
+                    ) {
+                        tokio::task::yield_now().await;
+                    }
+                })
+                .await
+                .unwrap();
+                assert_eq!(subtree_sum().await, uncached + 1);
+                assert_eq!(executes(), 2);
+
+                data_store_2.invalidate_subtree(4).await.unwrap();
+                assert!(matches!(
+                    data_store_2.get_vertex_by_id(&4).v_type,
+                    VertexType::Remote(_)
+                ));
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn ingest_applies_events_in_order() {
+        let data_store = local_store(&[1, 2, 3]);
+        let events = stream::iter([
+            EdgeEvent::AddEdge(1, 2),
+            EdgeEvent::AddEdge(2, 3),
+            EdgeEvent::AddEdge(3, 2),
+            EdgeEvent::RemoveEdge(1, 2),
+            EdgeEvent::RemoveEdge(2, 3),
+            EdgeEvent::SetData(3, Data(30)),
+        ]);
+
+        assert_eq!(data_store.ingest(events).await.unwrap(), 6);
+        assert!(neighbors(&data_store, 1, Neighbors::Edges).is_empty());
+        assert_eq!(neighbors(&data_store, 2, Neighbors::Parents), [3]);
+        assert!(neighbors(&data_store, 2, Neighbors::Children).is_empty());
+        // still adjacent through 3 -> 2
+        assert_eq!(neighbors(&data_store, 2, Neighbors::Edges), [3]);
+        assert_eq!(neighbors(&data_store, 3, Neighbors::Children), [2]);
+        let data = data_store.get_vertex_by_id(&3).get_val_async().await;
+        assert_eq!(data.unwrap().unwrap().0, 30);
+    }
+
+    #[tokio::test]
+    async fn ingest_refuses_edges_to_a_vertex_lent_out() {
+        let data_store = local_store(&[1, 2]);
+        data_store
+            .get_vertex_by_id(&2)
+            .local()
+            .unwrap()
+            .lend(2, Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        let events = stream::iter([EdgeEvent::AddEdge(1, 2), EdgeEvent::AddEdge(2, 1)]);
+        assert!(matches!(
+            data_store.ingest(events).await,
+            Err(FusionError::LeasedOut(2))
+        ));
+        assert!(neighbors(&data_store, 1, Neighbors::Edges).is_empty());
+        assert!(neighbors(&data_store, 2, Neighbors::Edges).is_empty());
+    }
+
+    #[tokio::test]
+    async fn batch_runs_local_and_remote_roots_in_order() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let sum = GraphReduce(Sum);
+                let (data_store_1, _data_store_2) =
+                    in_memory_pair_serving((&sum, &AccumulateSubtree), build_graph_integer_data)
+                        .await;
+                // machine 2 holds 8 and 13, the others are local
+                let roots: Vec<VertexID> = vec![8, 1, 13, 0];
+
+                let mut expected = vec![];
+                for root in &roots {
+                    expected.push(
+                        data_store_1
+                            .get_vertex_by_id(root)
+                            .apply_function(&sum, data_store_1.clone(), None)
+                            .await
+                            .unwrap(),
+                    );
+                }
+                let batched = data_store_1
+                    .apply_function_batch(&sum, roots.iter().map(|&root| (root, None)).collect())
+                    .await
+                    .unwrap();
+                assert_eq!(batched, expected);
+
+                assert!(matches!(
+                    data_store_1
+                        .apply_function_batch(&sum, vec![(1, None), (99, None)])
+                        .await,
+                    Err(FusionError::UnknownVertex(99))
+                ));
+            })
+            .await;
+    }
+}
@@ -0,0 +1,196 @@
+/* error.rs
+
+   Contains the error type shared by the framework, for failures that should be reported back to the caller
+   instead of bringing the whole machine down
+
+   Author: Binghong(Leo) Li
+   Creation Date: 10/14/2026
+*/
+
+use core::fmt::{self, Display};
+use core::time::Duration;
+
+use crate::vertex::{MachineID, VertexID};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// serializable, so that remote machines can report errors back over rpc
+#[derive(Debug, Serialize, Deserialize)]
+pub enum FusionError {
+    RedirectLoop(VertexID), // following the redirects of a vertex never reached its owner
+    UnknownMachine(MachineID), // no communication channel has been set up towards the machine
+    Draining(MachineID),    // the machine is shutting down and accepts no new work
+    VersionEvicted(VertexID, u64), // the version is older than the retained history
+    UnknownVersion(VertexID, u64), // the version has not been written yet
+    NotLocal(VertexID, MachineID), // the vertex is only a reference to the machine owning it
+    QuotaExceeded(String),  // the UDF used up its execution time budget for the current window
+    BadSignature,           // the rpc command was not signed with the shared secret
+    IdCollision(VertexID),  // both sides of a merge hold the vertex locally
+    UnknownVertex(VertexID), // neither a local vertex nor a remote reference on this machine
+    MaxDepthExceeded(VertexID, usize), // executing the vertex would have taken the recursion past the cap
+    Checkpoint(String),                // the checkpoint file could not be read or written
+    BudgetExceeded, // the computation sent more bytes over rpc than its budget allows
+    FanoutExceeded(usize), // the computation contacted more distinct machines than allowed
+    Timeout(Duration), // the remote did not answer in time
+    ChannelClosed,  // the result channel was dropped before the result came in
+    Rpc(String),    // the rpc could not be sent (see RpcError)
+    LeasedOut(VertexID), // the vertex is lent out, its data can't be written here
+    NotLeased(VertexID), // returning a vertex that was not borrowed
+    NotOwned(VertexID), // the vertex is only borrowed here, it can't be lent out again
+    LeaseExpired(VertexID), // the lease the vertex was borrowed under has expired, the owner took it back
+    ReadOnly(VertexID), // the vertex is borrowed here under a read lease, its data can't be written
+    ForwardLoop(VertexID, Vec<MachineID>), // the machines kept forwarding the vertex's execute, none owning it
+    Udf(String),                           // returned by a TryUserDefinedFunction, with its reason
+    MissingAuxBase(Uuid), // an auxiliary delta arrived without the base it is against, see RPC::ExecuteDelta
+    Unreachable(Vec<(MachineID, FusionError)>), // the machines that failed a health check, with why
+    Context(Box<ErrorContext>), // where in the graph the wrapped error happened
+}
+
+/*
+    Where an error happened, accumulated as it propagates back up the recursion
+*/
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorContext {
+    pub vertex: VertexID,    // the vertex the failure was attributed to
+    pub machine: MachineID,  // the machine owning that vertex
+    pub path: Vec<VertexID>, // the ancestors it propagated through, closest first
+    pub source: FusionError,
+}
+
+impl FusionError {
+    /*
+       Attributing the error to the vertex where it happened and its owning machine,
+       an error already attributed (e.g. by the remote machine) keeps its more precise context
+    */
+    pub fn at(self, vertex: VertexID, machine: MachineID) -> Self {
+        match self {
+            FusionError::Context(_) => self,
+            source => FusionError::Context(Box::new(ErrorContext {
+                vertex,
+                machine,
+                path: vec![],
+                source,
+            })),
+        }
+    }
+
+    /*
+       Recording an ancestor the error propagated through, which it is attributed to if nothing closer was
+    */
+    pub fn via(self, ancestor: VertexID, machine: MachineID) -> Self {
+        match self {
+            FusionError::Context(mut context) => {
+                context.path.push(ancestor);
+                FusionError::Context(context)
+            }
+            source => source.at(ancestor, machine),
+        }
+    }
+
+    // the error without any context around it
+    pub fn root_cause(&self) -> &FusionError {
+        match self {
+            FusionError::Context(context) => context.source.root_cause(),
+            other => other,
+        }
+    }
+}
+
+impl Display for FusionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FusionError::RedirectLoop(v_id) => {
+                write!(f, "redirects for vertex {v_id} did not reach an owner")
+            }
+            FusionError::UnknownMachine(m_id) => write!(f, "no connection to machine {m_id}"),
+            FusionError::Draining(m_id) => write!(f, "machine {m_id} is shutting down"),
+            FusionError::VersionEvicted(v_id, version) => {
+                write!(
+                    f,
+                    "version {version} of vertex {v_id} is no longer retained"
+                )
+            }
+            FusionError::UnknownVersion(v_id, version) => {
+                write!(f, "version {version} of vertex {v_id} does not exist yet")
+            }
+            FusionError::NotLocal(v_id, m_id) => {
+                write!(f, "vertex {v_id} lives on machine {m_id}, not locally")
+            }
+            FusionError::QuotaExceeded(udf_id) => {
+                write!(f, "udf {udf_id} exceeded its execution time quota")
+            }
+            FusionError::BadSignature => write!(f, "rpc command failed authentication"),
+            FusionError::IdCollision(v_id) => {
+                write!(f, "vertex {v_id} is local on both sides of the merge")
+            }
+            FusionError::UnknownVertex(v_id) => {
+                write!(f, "vertex {v_id} is unknown to this machine")
+            }
+            FusionError::MaxDepthExceeded(v_id, max_depth) => {
+                write!(
+                    f,
+                    "executing vertex {v_id} exceeded the maximum depth of {max_depth}"
+                )
+            }
+            FusionError::BudgetExceeded => write!(f, "computation exceeded its send budget"),
+            FusionError::Timeout(timeout) => write!(f, "no answer within {timeout:?}"),
+            FusionError::ChannelClosed => write!(f, "result channel closed before the result"),
+            FusionError::Rpc(reason) => write!(f, "{reason}"),
+            FusionError::Udf(reason) => write!(f, "udf failed: {reason}"),
+            FusionError::MissingAuxBase(base_id) => {
+                write!(
+                    f,
+                    "auxiliary base {base_id} was not received before its delta"
+                )
+            }
+            FusionError::Unreachable(machines) => {
+                write!(f, "unreachable machines:")?;
+                for (hop, (m_id, e)) in machines.iter().enumerate() {
+                    write!(f, "{} {m_id} ({e})", if hop == 0 { "" } else { "," })?;
+                }
+                Ok(())
+            }
+            FusionError::LeasedOut(v_id) => write!(f, "vertex {v_id} is leased out"),
+            FusionError::NotLeased(v_id) => write!(f, "vertex {v_id} is not borrowed"),
+            FusionError::NotOwned(v_id) => write!(f, "vertex {v_id} is only borrowed here"),
+            FusionError::LeaseExpired(v_id) => write!(f, "lease on vertex {v_id} has expired"),
+            FusionError::ReadOnly(v_id) => write!(f, "vertex {v_id} is only borrowed for reading"),
+            FusionError::ForwardLoop(v_id, machines) => {
+                write!(f, "execute of vertex {v_id} was forwarded through machines")?;
+                for (hop, m_id) in machines.iter().enumerate() {
+                    write!(f, "{}{m_id}", if hop == 0 { " " } else { " -> " })?;
+                }
+                write!(f, " without reaching its owner")
+            }
+            FusionError::FanoutExceeded(max) => {
+                write!(f, "computation contacted more than {max} machines")
+            }
+            FusionError::Checkpoint(reason) => write!(f, "checkpoint failed: {reason}"),
+            FusionError::Context(context) => {
+                write!(
+                    f,
+                    "{} (at vertex {} on machine {}",
+                    context.source, context.vertex, context.machine
+                )?;
+                // from the root of the call down to the failing vertex
+                if !context.path.is_empty() {
+                    write!(f, ", reached through")?;
+                    for ancestor in context.path.iter().rev() {
+                        write!(f, " {ancestor}")?;
+                    }
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FusionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FusionError::Context(context) => Some(&context.source),
+            _ => None,
+        }
+    }
+}
@@ -1,9 +1,11 @@
-#![feature(get_mut_unchecked)]
 /* lib.rs
 
    Amalgamation of imports, to keep everything under the same crate root.
    Also includes all information users should need when constructing the custom function
 
+   There is no Graph type: the part of the graph a machine holds is its DataStore (see datastore.rs), from
+   which vertices are looked up, inserted and removed at runtime (DataStore::insert_vertex(), remove_vertex())
+
    Author: Binghong(Leo) Li
    Creation Date: 1/14/2024
 */
@@ -12,15 +14,25 @@ extern crate alloc;
 use alloc::sync::Arc;
 use core::fmt::Debug;
 
+use crate::codec::{BincodeCodec, Codec};
 use crate::datastore::DataStore;
-use crate::vertex::Vertex;
+use crate::error::FusionError;
+use crate::vertex::{Data, Vertex};
 
 use async_trait::async_trait;
+use futures::future::{BoxFuture, FutureExt};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use uuid::Uuid;
 
+pub mod auth;
+pub mod codec;
 pub mod datastore;
+pub mod error;
+pub mod partition;
 pub mod rpc;
+pub mod scheduler;
+pub mod store;
 pub mod udf;
 pub mod vertex;
 pub mod worker;
@@ -33,14 +45,207 @@ pub trait UserDefinedFunction<
     T: DeserializeOwned + Serialize + Debug + Default,
     U: DeserializeOwned + Serialize,
     V: Debug,
+    C: Codec = BincodeCodec,
+    P: Codec = C,
 >: Clone
 {
     async fn execute(
         &self,
-        vertex: &Vertex<T, V>,
-        data_store: Arc<DataStore<T, V>>,
+        vertex: &Vertex<T, V, C, P>,
+        data_store: Arc<DataStore<T, V, C, P>>,
         auxiliary_information: U,
     ) -> V;
+
+    /*
+       Executing the UDF the way its invokers do (see Vertex::try_apply_function()), an error being returned
+       instead of unwinding: the blanket implementation for TryUserDefinedFunction returns the one try_execute
+       failed with, the others can't fail
+    */
+    fn execute_checked<'a>(
+        &'a self,
+        vertex: &'a Vertex<T, V, C, P>,
+        data_store: Arc<DataStore<T, V, C, P>>,
+        auxiliary_information: U,
+    ) -> BoxFuture<'a, Result<V, FusionError>> {
+        self.execute(vertex, data_store, auxiliary_information)
+            .map(Ok)
+            .boxed()
+    }
+
+    // identifies the UDF for accounting (see DataStore::set_udf_quota()), its type name unless overridden
+    fn udf_id(&self) -> UdfId {
+        core::any::type_name::<Self>()
+    }
+}
+
+pub type UdfId = &'static str;
+
+/*
+   Trait requirement for user-defined functions that can fail, e.g. on data they can't make sense of
+
+   Every one is also a UserDefinedFunction, and applies as such: an error fails the whole call instead of
+   panicking the machine, attributed to the vertex it was returned for (see Vertex::try_apply_function()).
+   Errors of remote vertices travel back over rpc, so `?` on the children's try_apply_function() carries
+   them up the recursion as well.
+   Note: the invokers go through execute_checked(), only calling the blanket execute() directly panics on an error
+*/
+#[async_trait]
+pub trait TryUserDefinedFunction<
+    T: DeserializeOwned + Serialize + Debug + Default,
+    U: DeserializeOwned + Serialize,
+    V: Debug,
+    C: Codec = BincodeCodec,
+    P: Codec = C,
+>: Clone
+{
+    async fn try_execute(
+        &self,
+        vertex: &Vertex<T, V, C, P>,
+        data_store: Arc<DataStore<T, V, C, P>>,
+        auxiliary_information: U,
+    ) -> Result<V, FusionError>;
+}
+
+#[async_trait]
+impl<T, U, V, C, P, F> UserDefinedFunction<T, U, V, C, P> for F
+where
+    T: DeserializeOwned + Serialize + Debug + Default + Send + Sync,
+    U: DeserializeOwned + Serialize + Send + 'static,
+    V: Debug + Send + Sync,
+    C: Codec,
+    P: Codec,
+    F: TryUserDefinedFunction<T, U, V, C, P> + Sync,
+{
+    async fn execute(
+        &self,
+        vertex: &Vertex<T, V, C, P>,
+        data_store: Arc<DataStore<T, V, C, P>>,
+        auxiliary_information: U,
+    ) -> V {
+        self.execute_checked(vertex, data_store, auxiliary_information)
+            .await
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    fn execute_checked<'a>(
+        &'a self,
+        vertex: &'a Vertex<T, V, C, P>,
+        data_store: Arc<DataStore<T, V, C, P>>,
+        auxiliary_information: U,
+    ) -> BoxFuture<'a, Result<V, FusionError>> {
+        let machine_id = data_store.worker.machine_id;
+        self.try_execute(vertex, data_store, auxiliary_information)
+            .map(move |res| res.map_err(|e| e.at(vertex.id, machine_id)))
+            .boxed()
+    }
+}
+
+/*
+   Trait requirement for user-defined functions that write the data of the vertices back, e.g. PageRank or
+   label propagation
+
+   execute_mut works on a copy of the vertex's data (the default if it has none), which is written back once
+   it returns Ok, see Vertex::apply_function_mut()
+*/
+#[async_trait]
+pub trait MutableUserDefinedFunction<
+    T: DeserializeOwned + Serialize + Debug + Default,
+    U: DeserializeOwned + Serialize,
+    V: Debug,
+    C: Codec = BincodeCodec,
+    P: Codec = C,
+>: Clone
+{
+    async fn execute_mut(
+        &self,
+        vertex: &Vertex<T, V, C, P>,
+        data: &mut Data<T>,
+        data_store: Arc<DataStore<T, V, C, P>>,
+        auxiliary_information: U,
+    ) -> Result<V, FusionError>;
+}
+
+/*
+   Trait requirement for auxiliary information that can be shipped as a delta
+
+   When a UDF passes a growing context down (e.g. a path prefix), Vertex::apply_function_with_base() only sends
+   the change relative to a base the remote machine already has, instead of re-serializing the full aux per hop
+*/
+pub trait AuxDelta: Sized {
+    type Delta: Serialize + DeserializeOwned;
+
+    // the change from `base` to self
+    fn into_delta(self, base: &Self) -> Self::Delta;
+
+    // rebuilding self from the base it was diffed against
+    fn from_delta(base: Self, delta: Self::Delta) -> Self;
+}
+
+/*
+   Base the deltas of AuxDelta are taken against, usually the aux of the parent call
+*/
+pub struct AuxBase<U> {
+    pub(crate) id: Uuid,
+    pub(crate) aux: U,
+}
+
+impl<U> AuxBase<U> {
+    pub fn new(aux: U) -> Self {
+        AuxBase {
+            id: Uuid::new_v4(),
+            aux,
+        }
+    }
+
+    pub fn aux(&self) -> &U {
+        &self.aux
+    }
+}
+
+// no meaningful delta, the whole value replaces the base
+impl<X: Serialize + DeserializeOwned> AuxDelta for Option<X> {
+    type Delta = Self;
+
+    fn into_delta(self, _base: &Self) -> Self::Delta {
+        self
+    }
+
+    fn from_delta(_base: Self, delta: Self::Delta) -> Self {
+        delta
+    }
+}
+
+impl AuxDelta for bool {
+    type Delta = Self;
+
+    fn into_delta(self, _base: &Self) -> Self::Delta {
+        self
+    }
+
+    fn from_delta(_base: Self, delta: Self::Delta) -> Self {
+        delta
+    }
+}
+
+// growing prefix: (length of the prefix kept from the base, elements after it)
+impl<X: Serialize + DeserializeOwned + PartialEq> AuxDelta for Vec<X> {
+    type Delta = (usize, Vec<X>);
+
+    fn into_delta(mut self, base: &Self) -> Self::Delta {
+        let kept = self
+            .iter()
+            .zip(base.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let appended = self.split_off(kept);
+        (kept, appended)
+    }
+
+    fn from_delta(mut base: Self, (kept, appended): Self::Delta) -> Self {
+        base.truncate(kept);
+        base.extend(appended);
+        base
+    }
 }
 
 #[async_trait]
@@ -7,204 +7,32 @@
 */
 
 extern crate alloc;
+use alloc::rc::Rc;
 use alloc::sync::Arc;
-use core::fmt::Debug;
 
 use fusion_framework::datastore::{build_graph_integer_data, DataStore};
-use fusion_framework::rpc::{RPCResPayload, RPCResponseHeader, ResType, RPC};
-use fusion_framework::udf::{GraphSum, NMASInfo, NaiveMaxAdjacentSum, SwapLargestAndSmallest};
-use fusion_framework::vertex::{Data, MachineID, VertexID};
-use fusion_framework::worker::Worker;
-use fusion_framework::UserDefinedFunction;
+use fusion_framework::udf::{AccumulateSubtree, GraphSum};
+use fusion_framework::vertex::MachineID;
+use fusion_framework::worker::{accept_any, serve_rpc_stream, ExecuteQueue, RpcSendStream, Worker};
 
+use core::num::NonZeroUsize;
 use core::time::Duration;
-use hashbrown::{HashMap, HashSet};
-use serde::de::DeserializeOwned;
-use serde::Serialize;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use hashbrown::HashMap;
+use tokio::io::AsyncWriteExt;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc::{channel, Sender};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc::channel;
 use tokio::sync::Mutex;
-use uuid::Uuid;
-
-async fn handle_data_receiving_stream<
-    T: Serialize + DeserializeOwned + Default,
-    V: DeserializeOwned + Default + Debug,
->(
-    mut data_receiving_stream: TcpStream,
-    worker: Arc<Worker<T, V>>,
-    dummy_session_control_data_len: usize,
-) {
-    // construct the reception buffer for session_header
-    let mut session_header_bytes = vec![0u8; dummy_session_control_data_len];
-
-    // each time, start with receiving the header
-    while let Ok(_) = data_receiving_stream
-        .read_exact(&mut session_header_bytes)
-        .await
-    {
-        let session_header =
-            bincode::deserialize::<RPCResponseHeader>(&session_header_bytes).unwrap();
-
-        // depending on the type, do different things
-        match &session_header.session_type {
-            ResType::NotYetNeeded => {
-                unimplemented!()
-            }
-            ResType::ExecuteRes | ResType::UpdateRes => {
-                // get where the channel the result should go to
-                let res_channels = worker.result_multiplexing_channels.read().await;
-                let res_channel = res_channels
-                    .get(&session_header.session_id)
-                    .expect("session id not exist in result channel")
-                    .lock()
-                    .await;
-
-                // construct the data buffer and receive them
-                let mut res_bytes = vec![0u8; session_header.data_len];
-                data_receiving_stream
-                    .read_exact(&mut res_bytes)
-                    .await
-                    .unwrap();
-
-                // send over the result
-                let res = bincode::deserialize::<RPCResPayload<_, _>>(&res_bytes).unwrap();
-                res_channel.send(res).await.unwrap();
-            }
-        }
-    }
-}
+use tracing::{debug, warn};
 
-async fn handle_rpc_receiving_stream<
-    T: Serialize + DeserializeOwned + Send + Sync + 'static + Debug + Default,
-    U: Serialize + DeserializeOwned + Send + Sync + 'static,
-    X: UserDefinedFunction<T, U, V> + Send + Sync + 'static + Clone,
-    V: Serialize + Send + Sync + 'static + Debug,
->(
-    id: Arc<MachineID>,
-    mut stream: Arc<TcpStream>,
-    worker: Arc<Worker<T, V>>,
-    data_store: Arc<DataStore<T, V>>,
-    _type: &X,
-    dummy_rpc_len: usize,
-    tx_req: Sender<MachineID>,
-    tx_res: Sender<()>,
-) {
-    // construct the buffer to receive fixed size of bytes for RPC
-    let mut cmd = vec![0u8; dummy_rpc_len];
-
-    let mut stream = Arc::get_mut(&mut stream).unwrap();
-
-    while let Ok(_) = stream.read_exact(&mut cmd).await {
-        match bincode::deserialize::<RPC>(&cmd).expect("Incorrect RPC format") {
-            RPC::Execute(uuid, v_id, aux_info_len) => {
-                // println!("Arc counts: {:?}", Arc::strong_count(&data_store));
-
-                // construct the buffer for auxiliary information and receive it
-                let mut aux_info = vec![0u8; aux_info_len];
-                stream.read_exact(&mut aux_info).await.unwrap();
-                // it comes in the same RPC stream as noted in remote_execute()
-
-                let aux_info =
-                    bincode::deserialize::<U>(&aux_info).expect("Incorrect Auxiliary Info Format");
-
-                // construct variable to pass into the new thread, for non-blocking circular/recursive remote calls
-                let worker_clone = worker.clone();
-                let data_store_clone = data_store.clone();
-                let _type_clone = _type.clone();
-                let id_clone = id.clone();
-
-                tokio::task::spawn_local(async move {
-                    // calculate the result in a non-blocking manner, without holding onto locks prior to entrance
-                    let res = data_store_clone
-                        .get_vertex_by_id(&v_id)
-                        .apply_function(&_type_clone, data_store_clone.clone(), aux_info)
-                        .await;
-
-                    // construct result that is to be sent back
-                    let res: RPCResPayload<T, V> = RPCResPayload::ExecuteResPayload(res);
-
-                    // get sending_stream as mut
-                    let sending_streams = worker_clone.sending_streams.read().await;
-                    let mut sending_stream =
-                        sending_streams.get(id_clone.as_ref()).unwrap().lock().await;
-
-                    let res_bytes = bincode::serialize::<RPCResPayload<_, V>>(&res).unwrap();
-
-                    // construct session header
-                    let session_header_for_result = RPCResponseHeader {
-                        session_id: uuid,
-                        session_type: ResType::ExecuteRes,
-                        data_len: res_bytes.len(),
-                    };
-                    let session_header_for_result_bytes =
-                        bincode::serialize(&session_header_for_result).unwrap();
-
-                    // send all the data
-                    sending_stream
-                        .write_all(&[session_header_for_result_bytes, res_bytes].concat())
-                        .await
-                        .unwrap();
-                });
-            }
-            RPC::Relay(_, _, _) => {
-                unimplemented!()
-            }
-            RPC::RequestData(_, _, _) => {
-                unimplemented!()
-            }
-            RPC::ExecuteWithData(_, _, _) => {
-                unimplemented!()
-            }
-            RPC::Update(uuid, v_id, data_len) => {
-                // construct the buffer for data and receive it
-                let mut data = vec![0u8; data_len];
-                stream.read_exact(&mut data).await.unwrap();
-                // it comes in the same RPC stream as noted in remote_execute()
-
-                let data = bincode::deserialize::<Data<T>>(&data)
-                    .expect("Incorrect Auxiliary Info Format");
-
-                // Note: Different here, doesn't need to multi-thread here, as update is pure local (synchronous in
-                // another sense
-
-                let res = data_store.get_vertex_by_id(&v_id).update(data).await;
-
-                // construct the payload to be sent
-                let res: RPCResPayload<T, V> = RPCResPayload::UpdateResPayload(res);
-                let res_bytes = bincode::serialize::<RPCResPayload<T, _>>(&res).unwrap();
-
-                // get sending_stream as mut
-                let sending_streams = worker.sending_streams.read().await;
-                let mut sending_stream = sending_streams.get(id.as_ref()).unwrap().lock().await;
-
-                // construct session header
-                let session_header_for_result = RPCResponseHeader {
-                    session_id: uuid,
-                    session_type: ResType::UpdateRes,
-                    data_len: res_bytes.len(),
-                };
-
-                let session_header_for_result_bytes =
-                    bincode::serialize(&session_header_for_result).unwrap();
-
-                // send all the data
-                sending_stream
-                    .write_all(&[session_header_for_result_bytes, res_bytes].concat())
-                    .await
-                    .unwrap();
-            }
-            RPC::UpdateMap(_, _, _) => {
-                println!("received update request");
-                tx_req.send(id.as_ref().clone()).await.unwrap();
-            }
-            RPC::UpdateMapRes(_, _, _) => {
-                println!("received update resp");
-                tx_res.send(()).await.unwrap()
-            }
-        }
-    }
-}
+// executes served at the same time, the rest wait in priority order
+const MAX_CONCURRENT_EXECUTES: usize = 64;
+
+// how long each machine the graph points to gets to answer at startup, see DataStore::connect_all()
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// how long the work in flight gets to finish on SIGTERM, see Worker::shutdown()
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
 
 #[tokio::main]
 async fn main() {
@@ -212,9 +40,10 @@ async fn main() {
     let local = tokio::task::LocalSet::new();
 
     // a machine id needs to be provided
+    // further addresses can be given to also listen on, e.g. for other interfaces
     let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <machine_id>", args[0]);
+    if args.len() < 2 {
+        eprintln!("Usage: {} <machine_id> [extra_listen_address...]", args[0]);
         std::process::exit(1);
     }
     let machine_id: MachineID = args[1].parse().expect("Invalid machine ID");
@@ -231,30 +60,47 @@ async fn main() {
     let mut rpc_receiving_streams = HashMap::new();
     let mut data_receiving_streams = vec![];
 
-    // Start listening on the local port
-    let listener = TcpListener::bind(&local_address)
-        .await
-        .expect("Failed to bind local address");
-    println!("Listening on {}", local_address);
+    // Start listening on the local port, and every extra address
+    let mut listeners = vec![];
+    for address in std::iter::once(&local_address).chain(&args[2..]) {
+        let listener = TcpListener::bind(address)
+            .await
+            .unwrap_or_else(|_| panic!("Failed to bind {address}"));
+        println!("Listening on {}", address);
+        listeners.push(listener);
+    }
 
     // Create new worker instance
-    let worker = Worker::new();
+    let mut worker = Worker::new(machine_id);
+    // optional shared secret for signing rpc commands, has to be the same on every machine
+    worker.rpc_key = std::env::var("FUSION_RPC_KEY").ok().map(String::into_bytes);
+    // optional zstd level, peers with and without compression still understand each other
+    worker.compression = std::env::var("FUSION_COMPRESSION").ok().map(|level| {
+        level
+            .parse()
+            .expect("FUSION_COMPRESSION is not a zstd level")
+    });
+    // optional bound on the remote executes awaiting their result per machine, callers wait beyond it
+    // (0 would never let a request out)
+    worker.max_in_flight = std::env::var("FUSION_MAX_IN_FLIGHT").ok().map(|max| {
+        max.parse::<NonZeroUsize>()
+            .expect("FUSION_MAX_IN_FLIGHT is not a positive number of requests")
+            .get()
+    });
     // other communication channel
-    let (tx_update_req, mut rx_update_req) = channel::<MachineID>(100);
-    let (tx_update_res, mut rx_update_res) = channel::<()>(100);
+    let (tx_update_req, _rx_update_req) = channel::<MachineID>(100);
+    let (tx_update_res, _rx_update_res) = channel::<()>(100);
 
     match machine_id {
         // the order of communication is crucial for initial setup
         1 => {
             // (1) listens first, and needs to be launched first
-            let (incoming_stream, socket_addr) = listener
-                .accept()
+            let (incoming_stream, socket_addr) = accept_any(&listeners)
                 .await
                 .expect("Failed to accept connection");
             println!("New connection from {socket_addr}");
 
-            let (rpc_receiving_stream, socket_addr) = listener
-                .accept()
+            let (mut rpc_receiving_stream, socket_addr) = accept_any(&listeners)
                 .await
                 .expect("Failed to accept connection");
             println!("New connection from {socket_addr}");
@@ -262,61 +108,77 @@ async fn main() {
             // after accepting incoming connections, then initiate outgoing connections
             let outgoing_stream = TcpStream::connect(&remote_address)
                 .await
-                .expect(&*format!("Failed to connect to {remote_address}"));
-            let rpc_sending_stream = TcpStream::connect(&remote_address)
+                .unwrap_or_else(|_| panic!("Failed to connect to {remote_address}"));
+            let mut rpc_sending_stream = TcpStream::connect(&remote_address)
                 .await
-                .expect(&*format!("Failed to connect to {remote_address}"));
+                .unwrap_or_else(|_| panic!("Failed to connect to {remote_address}"));
+
+            // the hellos tell both ends they speak the same codecs
+            rpc_sending_stream
+                .write_all(&worker.hello().expect("Failed to encode the hello"))
+                .await
+                .expect("Failed to send the hello");
+            let hello = worker.read_hello(&mut rpc_receiving_stream).await;
+            assert_eq!(hello.expect("Refused the rpc stream"), 2);
 
             // fill in the data structures
-            rpc_receiving_streams.insert(Arc::new(2), Arc::new(rpc_receiving_stream));
-            data_receiving_streams.push(incoming_stream);
+            rpc_receiving_streams.insert(Arc::new(2), rpc_receiving_stream);
+            data_receiving_streams.push((2, incoming_stream));
             worker
                 .sending_streams
                 .write()
                 .await
-                .insert(2, Mutex::new(outgoing_stream));
+                .insert(2, Mutex::new(Box::new(outgoing_stream)));
             worker
                 .rpc_sending_streams
                 .write()
                 .await
-                .insert(2, Mutex::new(rpc_sending_stream));
+                .insert(2, Mutex::new(RpcSendStream::new(rpc_sending_stream)));
+            worker.peer_addresses.insert(2, remote_address.clone());
         }
         2 => {
             // (2) initiates outgoing connections first, needs to be launched second
             let outgoing_stream = TcpStream::connect(&remote_address)
                 .await
-                .expect(&*format!("Failed to connect to {remote_address}"));
+                .unwrap_or_else(|_| panic!("Failed to connect to {remote_address}"));
 
-            let rpc_sending_stream = TcpStream::connect(&remote_address)
+            let mut rpc_sending_stream = TcpStream::connect(&remote_address)
                 .await
-                .expect(&*format!("Failed to connect to {remote_address}"));
+                .unwrap_or_else(|_| panic!("Failed to connect to {remote_address}"));
 
             // after connecting, then listen
-            let (incoming_stream, socket_addr) = listener
-                .accept()
+            let (incoming_stream, socket_addr) = accept_any(&listeners)
                 .await
                 .expect("Failed to accept connection");
             println!("New connection from {socket_addr}");
 
-            let (rpc_receiving_stream, socket_addr) = listener
-                .accept()
+            let (mut rpc_receiving_stream, socket_addr) = accept_any(&listeners)
                 .await
                 .expect("Failed to accept connection");
             println!("New connection from {socket_addr}");
 
+            // the hellos tell both ends they speak the same codecs
+            rpc_sending_stream
+                .write_all(&worker.hello().expect("Failed to encode the hello"))
+                .await
+                .expect("Failed to send the hello");
+            let hello = worker.read_hello(&mut rpc_receiving_stream).await;
+            assert_eq!(hello.expect("Refused the rpc stream"), 1);
+
             // fill in the data structures
-            rpc_receiving_streams.insert(Arc::new(1), Arc::new(rpc_receiving_stream));
-            data_receiving_streams.push(incoming_stream);
+            rpc_receiving_streams.insert(Arc::new(1), rpc_receiving_stream);
+            data_receiving_streams.push((1, incoming_stream));
             worker
                 .sending_streams
                 .write()
                 .await
-                .insert(1, Mutex::new(outgoing_stream));
+                .insert(1, Mutex::new(Box::new(outgoing_stream)));
             worker
                 .rpc_sending_streams
                 .write()
                 .await
-                .insert(1, Mutex::new(rpc_sending_stream));
+                .insert(1, Mutex::new(RpcSendStream::new(rpc_sending_stream)));
+            worker.peer_addresses.insert(1, remote_address.clone());
         }
         _ => unimplemented!(),
     }
@@ -325,7 +187,7 @@ async fn main() {
 
     // constructing datastructures for multi-thread sharing
     let worker = Arc::new(worker);
-    let mut data_store = DataStore::new();
+    let mut data_store = DataStore::new(worker.clone());
 
     // use graph builder to build the graph based on machine_id
     build_graph_integer_data(&mut data_store, machine_id, worker.clone());
@@ -333,81 +195,136 @@ async fn main() {
 
     let data_store = Arc::new(data_store);
 
-    // getting fixed size for reception
-    let dummy_rpc = RPC::Execute(Uuid::default(), 0, 0);
-    let dummy_rpc_len = bincode::serialize(&dummy_rpc).unwrap().len();
+    // sends RPC::Cancel for the requests abandoned while waiting on their result
+    let canceller_worker = worker.clone();
+    local.spawn_local(async move {
+        canceller_worker.spawn_canceller().await.unwrap();
+    });
+
+    // sends RPC::LeaseRevoked for the read leases revoked by writes here
+    let revoker_worker = worker.clone();
+    local.spawn_local(async move {
+        revoker_worker.spawn_revoker().await.unwrap();
+    });
+
+    // writes the requests of remote executes highest priority first
+    let outbound_worker = worker.clone();
+    local.spawn_local(async move {
+        outbound_worker.spawn_outbound().await.unwrap();
+    });
+
+    // executes from all machines share one queue, so priorities hold across streams
+    let execute_queue = Rc::new(ExecuteQueue::new(MAX_CONCURRENT_EXECUTES));
 
     // handle rpc receiving streams
     for (id, stream) in rpc_receiving_streams.into_iter() {
+        let queue = execute_queue.clone();
         let worker = worker.clone();
         let data_store = data_store.clone();
         let tx_req = tx_update_req.clone();
         let tx_res = tx_update_res.clone();
 
         local.spawn_local(async move {
-            handle_rpc_receiving_stream(
+            serve_rpc_stream(
                 id,
                 stream,
                 worker,
                 data_store,
                 &GraphSum,
-                dummy_rpc_len,
+                &AccumulateSubtree,
                 tx_req,
                 tx_res,
+                queue,
             )
             .await;
 
-            // handle_rpc_receiving_stream(
+            // serve_rpc_stream(
             //     id,
             //     stream,
             //     worker,
             //     data_store,
             //     &NaiveMaxAdjacentSum,
-            //     dummy_rpc_len,
             //     tx_req,
             //     tx_res,
+            //     queue,
             // )
             // .await;
 
-            // handle_rpc_receiving_stream(
+            // serve_rpc_stream(
             //     id,
             //     stream,
             //     worker,
             //     data_store,
             //     &SwapLargestAndSmallest,
-            //     dummy_rpc_len,
             //     tx_req,
             //     tx_res,
+            //     queue,
             // )
             // .await;
         });
     }
 
-    // getting fixed size for reception
-    let dummy_session_control_data_len = bincode::serialize(&RPCResponseHeader {
-        session_id: Uuid::default(),
-        session_type: ResType::ExecuteRes,
-        data_len: 0,
-    })
-    .unwrap()
-    .len();
-    // handle data receiving streams
-    for data_receiving_stream in data_receiving_streams.into_iter() {
-        let worker = worker.clone();
-        local.spawn_local(
-            async move {
-                handle_data_receiving_stream(
-                    data_receiving_stream,
-                    worker,
-                    dummy_session_control_data_len,
-                )
-            }
-            .await,
-        );
+    // on SIGTERM, the work in flight gets to finish before exiting
+    let shutdown_worker = worker.clone();
+    let shutdown_queue = execute_queue.clone();
+    local.spawn_local(async move {
+        let mut sigterm = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        sigterm.recv().await;
+        if !shutdown_worker
+            .shutdown(&shutdown_queue, SHUTDOWN_GRACE)
+            .await
+        {
+            warn!("work still in flight after {SHUTDOWN_GRACE:?}, dropped it");
+        }
+        std::process::exit(0);
+    });
+
+    // rpc streams reconnected by peers after theirs broke (see Worker::send_rpc()), whose hello tells who is
+    // connecting since the order of the initial setup no longer does
+    let worker_reconnects = worker.clone();
+    let data_store_reconnects = data_store.clone();
+    local.spawn_local(async move {
+        loop {
+            let Ok((mut stream, socket_addr)) = accept_any(&listeners).await else {
+                continue;
+            };
+            let Ok(id) = worker_reconnects.read_hello(&mut stream).await else {
+                continue;
+            };
+            debug!(machine_id = id, %socket_addr, "rpc stream reconnected");
+
+            tokio::task::spawn_local(serve_rpc_stream(
+                Arc::new(id),
+                stream,
+                worker_reconnects.clone(),
+                data_store_reconnects.clone(),
+                &GraphSum,
+                &AccumulateSubtree,
+                tx_update_req.clone(),
+                tx_update_res.clone(),
+                execute_queue.clone(),
+            ));
+        }
+    });
+
+    // demultiplex the results coming back from each machine
+    for (location, data_receiving_stream) in data_receiving_streams.into_iter() {
+        let reader_worker = worker.clone();
+        local.spawn_local(async move {
+            reader_worker
+                .spawn_result_reader(location, data_receiving_stream)
+                .await
+                .unwrap();
+        });
     }
     println!("BEFORE!\n{:?}\n\n", data_store);
 
     local.spawn_local(async move {
+        // every machine the graph points to has to answer before anything runs
+        if let Err(e) = data_store.connect_all(CONNECT_TIMEOUT).await {
+            panic!("{e}");
+        }
+
         // Note: For testing, invoke functions on machine 1
         if machine_id == 1 {
             // Apply function and print result
@@ -417,11 +334,17 @@ async fn main() {
             let result = root
                 .apply_function(&GraphSum, data_store.clone(), None)
                 .await;
-            println!("[1]: The graph sum is: {result}");
+            match result {
+                Ok(sum) => println!("[1]: The graph sum is: {sum}"),
+                Err(e) => println!("[1]: The graph sum failed: {e}"),
+            }
             let result = root
                 .apply_function(&GraphSum, data_store.clone(), None)
                 .await;
-            println!("[2]: The graph sum is: {result}");
+            match result {
+                Ok(sum) => println!("[2]: The graph sum is: {sum}"),
+                Err(e) => println!("[2]: The graph sum failed: {e}"),
+            }
 
             // let result = root
             //     .apply_function(
@@ -0,0 +1,81 @@
+/* partition.rs
+
+   Contains the partitioners deciding which machine owns each vertex, so that building a distributed graph
+   doesn't require picking between local vertices and remote references by hand
+
+   Every machine of a deployment has to use the same partitioner, or they won't agree on where a vertex lives.
+
+   Author: Binghong(Leo) Li
+   Creation Date: 10/14/2026
+*/
+
+use crate::vertex::{MachineID, VertexID};
+
+/*
+   Trait requirement for partitioners
+
+   Any Fn(VertexID) -> MachineID is one, for one-off assignments
+*/
+pub trait Partitioner {
+    fn owner(&self, v_id: VertexID) -> MachineID;
+}
+
+impl<F: Fn(VertexID) -> MachineID> Partitioner for F {
+    fn owner(&self, v_id: VertexID) -> MachineID {
+        self(v_id)
+    }
+}
+
+/*
+   Spreads the vertices over the machines by id: vertex_id % machines.len() picks the machine
+*/
+#[derive(Debug, Clone)]
+pub struct HashPartitioner {
+    machines: Vec<MachineID>,
+}
+
+impl HashPartitioner {
+    pub fn new(machines: Vec<MachineID>) -> Self {
+        assert!(!machines.is_empty(), "no machine to partition over");
+        HashPartitioner { machines }
+    }
+}
+
+impl Partitioner for HashPartitioner {
+    fn owner(&self, v_id: VertexID) -> MachineID {
+        self.machines[v_id as usize % self.machines.len()]
+    }
+}
+
+/*
+   Assigns contiguous ranges of ids to the machines, keeping neighbors with close ids together
+
+   Ids below bounds[0] go to machines[0], ids from bounds[i - 1] up to below bounds[i] to machines[i],
+   and ids from the last bound on to the last machine
+*/
+#[derive(Debug, Clone)]
+pub struct RangePartitioner {
+    bounds: Vec<VertexID>,
+    machines: Vec<MachineID>,
+}
+
+impl RangePartitioner {
+    pub fn new(bounds: Vec<VertexID>, machines: Vec<MachineID>) -> Self {
+        assert_eq!(
+            bounds.len() + 1,
+            machines.len(),
+            "expected one machine more than bounds"
+        );
+        assert!(
+            bounds.windows(2).all(|pair| pair[0] < pair[1]),
+            "bounds must be increasing"
+        );
+        RangePartitioner { bounds, machines }
+    }
+}
+
+impl Partitioner for RangePartitioner {
+    fn owner(&self, v_id: VertexID) -> MachineID {
+        self.machines[self.bounds.partition_point(|bound| *bound <= v_id)]
+    }
+}
@@ -6,13 +6,30 @@
    Creation Date: 1/14/2024
 */
 
-use core::fmt::Debug;
+use core::fmt::{self, Debug, Display};
+use std::io;
 
+use crate::codec::{unframe, Codec, CodecError};
+use crate::error::FusionError;
 use crate::vertex::{Data, MachineID, VertexID};
 
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use hashbrown::HashSet;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
 use uuid::Uuid;
 
+// bytes of the length prefix in front of every command on the rpc stream, see RPC::frame()
+pub const COMMAND_PREFIX_LEN: usize = 4;
+
+// longest command accepted, a longer prefix means the stream lost its framing
+const MAX_COMMAND_LEN: usize = 1024;
+
+// set in the length prefix when the trailing data of the command is compressed (see codec::compress())
+const COMPRESSED_FLAG: u32 = 1 << 31;
+
+// most bytes of trailing data read at once, see read_trailing()
+pub const TRAILING_CHUNK_LEN: usize = 64 * 1024;
+
 /*
     The initial RPC Communication
 */
@@ -20,20 +37,354 @@ use uuid::Uuid;
 pub enum RPC {
     // the number of fields may keep on growing as we need to pass more information to accommodate
     // for other types of execution
-    Execute(Uuid, VertexID, usize), // usize for trailing data size
+    // Note: Execute's priority travels in its trailing data, since all commands share the same shape
+    Execute(Uuid, VertexID, usize), // usize for trailing data size: CallerContext, priority byte, then aux_info
     Relay(Uuid, VertexID, usize),
     RequestData(Uuid, VertexID, usize),
     ExecuteWithData(Uuid, VertexID, usize),
     Update(Uuid, VertexID, usize),
     UpdateMap(Uuid, VertexID, usize), // use usize to know where it came from
-    UpdateMapRes(Uuid, VertexID, usize) // filling 
+    UpdateMapRes(Uuid, VertexID, usize), // filling
+    Redirect(Uuid, VertexID, usize),  // asks where the vertex lives, answered with the next hop
+    ExecuteDelta(Uuid, VertexID, usize), // usize for trailing CallerContext, priority byte, then AuxDeltaPayload
+    Shutdown(Uuid, VertexID, usize),     // the sender is shutting down, no reply expected
+    SubtreeHash(Uuid, VertexID, usize),
+    FetchData(Uuid, VertexID, usize), // usize for trailing Option<u64> version size
+    ExecuteBatch(Uuid, VertexID, usize), // usize for trailing CallerContext + Vec<(VertexID, aux_info)> size, VertexID unused
+    Cancel(Uuid, VertexID, usize), // the sender stopped waiting on the request Uuid, no reply expected
+    GetNeighbors(Uuid, VertexID, usize), // usize for trailing Neighbors size
+    ExecuteMut(Uuid, VertexID, usize), // same trailing data as Execute, runs the MutableUserDefinedFunction
+    Borrow(Uuid, VertexID, usize), // usize for trailing LeaseMode size, answered with a LentVertex
+    ReturnBorrow(Uuid, VertexID, usize), // usize for trailing (lease Uuid, Option<Data<T>> written while borrowed) size
+    RenewLease(Uuid, VertexID, usize),   // usize for trailing lease Uuid size
+    Ping(Uuid, VertexID, usize), // health check, answered with a PongResPayload, VertexID unused
+    EndTraversal(Uuid, VertexID, usize), // usize for trailing traversal u64 size, no reply expected, VertexID unused
+    Offload(Uuid, VertexID, usize), // same trailing data as Execute, runs the udf on the subtree borrowed by the receiver
+    LeaseRevoked(Uuid, VertexID, usize), // usize for trailing lease Uuid size, the owner wrote the vertex, no reply expected
+}
+
+impl RPC {
+    // name and fields of the command, all commands share the same shape
+    fn parts(&self) -> (&'static str, Uuid, VertexID, usize) {
+        match *self {
+            RPC::Execute(id, v_id, n) => ("Execute", id, v_id, n),
+            RPC::Relay(id, v_id, n) => ("Relay", id, v_id, n),
+            RPC::RequestData(id, v_id, n) => ("RequestData", id, v_id, n),
+            RPC::ExecuteWithData(id, v_id, n) => ("ExecuteWithData", id, v_id, n),
+            RPC::Update(id, v_id, n) => ("Update", id, v_id, n),
+            RPC::UpdateMap(id, v_id, n) => ("UpdateMap", id, v_id, n),
+            RPC::UpdateMapRes(id, v_id, n) => ("UpdateMapRes", id, v_id, n),
+            RPC::Redirect(id, v_id, n) => ("Redirect", id, v_id, n),
+            RPC::ExecuteDelta(id, v_id, n) => ("ExecuteDelta", id, v_id, n),
+            RPC::Shutdown(id, v_id, n) => ("Shutdown", id, v_id, n),
+            RPC::SubtreeHash(id, v_id, n) => ("SubtreeHash", id, v_id, n),
+            RPC::FetchData(id, v_id, n) => ("FetchData", id, v_id, n),
+            RPC::ExecuteBatch(id, v_id, n) => ("ExecuteBatch", id, v_id, n),
+            RPC::Cancel(id, v_id, n) => ("Cancel", id, v_id, n),
+            RPC::GetNeighbors(id, v_id, n) => ("GetNeighbors", id, v_id, n),
+            RPC::ExecuteMut(id, v_id, n) => ("ExecuteMut", id, v_id, n),
+            RPC::Borrow(id, v_id, n) => ("Borrow", id, v_id, n),
+            RPC::ReturnBorrow(id, v_id, n) => ("ReturnBorrow", id, v_id, n),
+            RPC::RenewLease(id, v_id, n) => ("RenewLease", id, v_id, n),
+            RPC::Ping(id, v_id, n) => ("Ping", id, v_id, n),
+            RPC::EndTraversal(id, v_id, n) => ("EndTraversal", id, v_id, n),
+            RPC::Offload(id, v_id, n) => ("Offload", id, v_id, n),
+            RPC::LeaseRevoked(id, v_id, n) => ("LeaseRevoked", id, v_id, n),
+        }
+    }
+
+    // length of the data following the command on the rpc stream
+    pub fn trailing_len(&self) -> usize {
+        match self {
+            // their usize is not a length
+            RPC::UpdateMap(..) | RPC::UpdateMapRes(..) => 0,
+            RPC::Execute(_, _, len)
+            | RPC::Relay(_, _, len)
+            | RPC::RequestData(_, _, len)
+            | RPC::ExecuteWithData(_, _, len)
+            | RPC::Update(_, _, len)
+            | RPC::Redirect(_, _, len)
+            | RPC::ExecuteDelta(_, _, len)
+            | RPC::Shutdown(_, _, len)
+            | RPC::SubtreeHash(_, _, len)
+            | RPC::FetchData(_, _, len)
+            | RPC::ExecuteBatch(_, _, len)
+            | RPC::Cancel(_, _, len)
+            | RPC::GetNeighbors(_, _, len)
+            | RPC::ExecuteMut(_, _, len)
+            | RPC::Borrow(_, _, len)
+            | RPC::ReturnBorrow(_, _, len)
+            | RPC::RenewLease(_, _, len)
+            | RPC::Ping(_, _, len)
+            | RPC::EndTraversal(_, _, len)
+            | RPC::Offload(_, _, len)
+            | RPC::LeaseRevoked(_, _, len) => *len,
+        }
+    }
+
+    /*
+       The command as written on the rpc stream: its length as a little endian u32, then the command itself
+       encoded by C
+
+       The receiver reads exactly that many bytes before the trailing data, whatever the bytes of a
+       single read happen to be (see read_command()). The top bit of the length tells whether the trailing
+       data is compressed.
+    */
+    pub fn frame<C: Codec>(&self, compressed: bool) -> Result<Vec<u8>, CodecError> {
+        let command = C::encode(self)?;
+        let mut prefix = u32::try_from(command.len()).expect("commands are a few bytes long");
+        if compressed {
+            prefix |= COMPRESSED_FLAG;
+        }
+        Ok([prefix.to_le_bytes().to_vec(), command].concat())
+    }
+}
+
+/*
+   Reading the next command framed by RPC::frame(), returned along with its whole frame for the signature check
+   and whether its trailing data is compressed
+*/
+pub async fn read_command<C: Codec, R: AsyncRead + Unpin>(
+    stream: &mut R,
+) -> io::Result<(Vec<u8>, RPC, bool)> {
+    let mut frame = vec![0u8; COMMAND_PREFIX_LEN];
+    stream.read_exact(&mut frame).await?;
+    let prefix = u32::from_le_bytes(frame[..].try_into().unwrap());
+    let compressed = prefix & COMPRESSED_FLAG != 0;
+    let len = (prefix & !COMPRESSED_FLAG) as usize;
+    if len > MAX_COMMAND_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("command of {len} bytes, longer than {MAX_COMMAND_LEN}"),
+        ));
+    }
+
+    frame.resize(COMMAND_PREFIX_LEN + len, 0);
+    stream.read_exact(&mut frame[COMMAND_PREFIX_LEN..]).await?;
+    let rpc = C::decode(&frame[COMMAND_PREFIX_LEN..])
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok((frame, rpc, compressed))
+}
+
+/*
+   Reading the `len` bytes of trailing data announced by a command, TRAILING_CHUNK_LEN bytes at a time
+
+   The buffer only grows as the data comes in, so a large aux_info isn't allocated in full before it arrives,
+   nor is a length that is never sent. A stream ending within the trailing data fails with UnexpectedEof,
+   the partial data is never handed out to be deserialized.
+*/
+pub async fn read_trailing<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    len: usize,
+) -> io::Result<Vec<u8>> {
+    let mut trailing = Vec::with_capacity(len.min(TRAILING_CHUNK_LEN));
+    while trailing.len() < len {
+        let start = trailing.len();
+        trailing.resize(start + (len - start).min(TRAILING_CHUNK_LEN), 0);
+        stream.read_exact(&mut trailing[start..]).await?;
+    }
+    Ok(trailing)
+}
+
+/*
+    Trailing data of RPC::GetNeighbors, which of the vertex's neighbor sets is asked for
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum Neighbors {
+    Children,
+    Parents,
+    Edges,
+}
+
+/*
+    Leads the trailing data of Execute, ExecuteMut, ExecuteDelta and ExecuteBatch, for the callee to continue the caller's
+    recursion from where it is
+        depth: of the caller, see DataStore::set_max_exec_depth()
+        send_budget: bytes the computation may still send, see CallLimits
+        max_machines, contacted: the computation's fan-out limit, and the machines it contacted so far
+        forwarded: the vertex an Execute, ExecuteMut or ExecuteDelta targets, and the machines that forwarded
+                   it without executing it as it is only a remote reference there too, in order
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CallerContext {
+    pub depth: usize,
+    pub send_budget: Option<usize>,
+    pub max_machines: Option<usize>,
+    pub contacted: Vec<MachineID>,
+    pub forwarded: Option<(VertexID, Vec<MachineID>)>,
+}
+
+/*
+    What a computation used of its limits (see CallLimits), reported back by remote callees along with their
+    results, for the caller to count it against its own
+        sent: bytes sent over rpc, the result included
+        contacted: distinct machines contacted, the ones in the caller context included
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CallReport {
+    pub sent: usize,
+    pub contacted: Vec<MachineID>,
+}
+
+impl CallerContext {
+    // the context framed at the front of the trailing data (see codec::frame()), and the rest of it
+    pub fn split<C: Codec>(trailing: &[u8]) -> (CallerContext, &[u8]) {
+        unframe::<C, _>(trailing).expect("Incorrect Caller Context Format")
+    }
+}
+
+/*
+    Byte layout of a command as BincodeCodec puts it on the wire: variant tag, the offset and bytes of every
+    field, and the total length, for diffing what a sender produced against what the receiver expects
+*/
+pub fn describe(rpc: &RPC) -> String {
+    let bytes = bincode::serialize(rpc).unwrap();
+    let (name, id, v_id, n) = rpc.parts();
+
+    // bincode writes the variant tag as a u32, then the fields in order
+    let fields = [
+        (
+            "variant tag",
+            4,
+            format!(
+                "{} ({name})",
+                u32::from_le_bytes(bytes[..4].try_into().unwrap())
+            ),
+        ),
+        (
+            "Uuid",
+            bincode::serialized_size(&id).unwrap() as usize,
+            id.to_string(),
+        ),
+        (
+            "VertexID",
+            bincode::serialized_size(&v_id).unwrap() as usize,
+            v_id.to_string(),
+        ),
+        (
+            "usize",
+            bincode::serialized_size(&n).unwrap() as usize,
+            n.to_string(),
+        ),
+    ];
+
+    let mut description = format!("RPC::{name}, {} bytes\n", bytes.len());
+    let mut offset = 0;
+    for (field, len, value) in fields {
+        let field_bytes = &bytes[offset..offset + len];
+        description.push_str(&format!(
+            "  [{offset:>3}..{:>3}) {field:<11} = {value:<36} {}\n",
+            offset + len,
+            hex(field_bytes)
+        ));
+        offset += len;
+    }
+    description
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/*
+    Failures of sending an rpc, raised on the sending machine before the remote is involved
+*/
+#[derive(Debug)]
+pub enum RpcError {
+    Codec(CodecError), // the command or its trailing data could not be encoded, see codec.rs
+    UnknownMachine(MachineID), // no rpc stream towards the machine, e.g. it has not connected yet
+    Io(io::Error),     // writing to the rpc stream failed
+    ChannelClosed,     // the result channel was dropped before the result came in
+}
+
+impl Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Codec(e) => write!(f, "failed to encode the rpc: {e}"),
+            RpcError::UnknownMachine(m_id) => write!(f, "no rpc stream to machine {m_id}"),
+            RpcError::Io(e) => write!(f, "failed to send the rpc: {e}"),
+            RpcError::ChannelClosed => write!(f, "result channel closed before the result"),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl RpcError {
+    // whether sending again may succeed, i.e. the connection failed rather than the request itself
+    pub fn is_transient(&self) -> bool {
+        match self {
+            RpcError::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+}
+
+// FusionError travels over rpc, so the causes that can't are kept as their message
+impl From<RpcError> for FusionError {
+    fn from(e: RpcError) -> Self {
+        match e {
+            RpcError::UnknownMachine(m_id) => FusionError::UnknownMachine(m_id),
+            RpcError::ChannelClosed => FusionError::ChannelClosed,
+            RpcError::Codec(_) | RpcError::Io(_) => FusionError::Rpc(e.to_string()),
+        }
+    }
+}
+
+/*
+    Trailing data of RPC::ExecuteDelta
+
+    The sender ships the serialized base only the first time it is used towards a machine, the receiver keeps
+    it under base_id. The full auxiliary information is rebuilt with AuxDelta::from_delta(base, delta).
+    Bases and deltas travel in order on the same rpc stream, so a base always arrives before its deltas.
+*/
+#[derive(Serialize, Deserialize)]
+pub struct AuxDeltaPayload {
+    pub base_id: Uuid,
+    pub base: Option<Vec<u8>>, // serialized base, only present on first use
+    pub delta: Vec<u8>,        // serialized AuxDelta::Delta
+}
+
+/*
+    A vertex lent out by its owner (see DataStore::borrow()), with its data at the time
+        lease: identifies the loan when renewing or returning it, the owner refuses it once the lease expired
+*/
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LentVertex<T> {
+    pub lease: Uuid,
+    pub incoming: HashSet<VertexID>,
+    pub outgoing: HashSet<VertexID>,
+    pub edges: HashSet<VertexID>,
+    pub data: Option<Data<T>>,
+}
+
+/*
+    How a vertex is borrowed, see DataStore::borrow() and DataStore::borrow_read()
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LeaseMode {
+    #[default]
+    Exclusive, // the owner refuses writes until the vertex is returned
+    Read, // the borrowed copy is read-only, and revoked as soon as the owner writes
 }
 
 /*
     Subsequent commands that send data
 */
 #[derive(Serialize)]
-pub struct RPCRelay<T: Serialize + DeserializeOwned + Default> {
+pub struct RPCRelay<T> {
     machine_id: MachineID,
     rpc_data: RPCData<T>,
 }
@@ -43,29 +394,50 @@ pub struct RPCRelay<T: Serialize + DeserializeOwned + Default> {
        the vertex/vertices to be sent
 */
 #[derive(Serialize)]
-pub struct RPCData<T: Serialize + DeserializeOwned + Default> {
+pub struct RPCData<T> {
     id: VertexID,
     data: Data<T>,
 }
 
-// communication/session control
+// longest response header accepted, a longer length means the data stream lost its framing
+pub const MAX_HEADER_LEN: usize = 1024;
+
+// communication/session control, framed in front of its result by codec::frame()
 #[derive(Serialize, Deserialize)]
 pub struct RPCResponseHeader {
     pub session_id: Uuid,
     pub session_type: ResType,
     pub data_len: usize,
+    pub compressed: bool, // the result is compressed (see codec::compress())
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum ResType {
-    ExecuteRes,   // conveys data
-    UpdateRes,    // Ack
+    ExecuteRes,  // conveys data
+    UpdateRes,   // Ack
+    RedirectRes, // next hop towards the owner
+    SubtreeHashRes,
+    FetchDataRes,
+    ExecuteBatchRes,
+    NeighborsRes,
+    BorrowRes,
+    LeaseRes,
+    PongRes,
     NotYetNeeded, // Note: for later use
 }
 
 //
 #[derive(Serialize, Deserialize, Debug)]
-pub enum RPCResPayload<T: Default, V: Debug> {
-    ExecuteResPayload(V),
-    UpdateResPayload(Option<Data<T>>),
+pub enum RPCResPayload<T, V> {
+    ExecuteResPayload(V, CallReport),
+    UpdateResPayload(Result<Option<Data<T>>, FusionError>), // the data replaced by the update
+    RedirectResPayload(Option<MachineID>), // None when the queried machine owns the vertex
+    SubtreeHashResPayload(u64),
+    FetchDataResPayload(Result<Option<Data<T>>, FusionError>),
+    ExecuteBatchResPayload(Vec<V>, CallReport), // in the order of the batch
+    ErrorResPayload(FusionError),               // the request was refused or failed on the remote
+    NeighborsResPayload(Result<HashSet<VertexID>, FusionError>),
+    BorrowResPayload(Result<LentVertex<T>, FusionError>),
+    LeaseResPayload(Result<(), FusionError>), // returning or renewing a lease
+    PongResPayload(bool),                     // whether the machine is shutting down
 }
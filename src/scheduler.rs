@@ -0,0 +1,46 @@
+/* scheduler.rs
+
+   Contains the schedulers deciding which of the vertices ready to be executed go first, so that ordering
+   heuristics can be tried without touching the executors
+
+   Author: Binghong(Leo) Li
+   Creation Date: 10/14/2026
+*/
+
+use core::fmt::Debug;
+
+use crate::vertex::VertexID;
+
+/*
+   Trait requirement for schedulers
+
+   Given the ready vertices, reorders them into the order they should be executed in. `degree` looks up
+   the number of children of a vertex, 0 for the ones living on other machines.
+   Shared between every execution using it, hence Sync
+*/
+pub trait Scheduler: Debug + Sync {
+    fn order(&self, ready: &mut [VertexID], degree: &dyn Fn(VertexID) -> usize);
+}
+
+/*
+   Keeps the vertices in the order they became ready, the default
+*/
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fifo;
+
+impl Scheduler for Fifo {
+    fn order(&self, _ready: &mut [VertexID], _degree: &dyn Fn(VertexID) -> usize) {}
+}
+
+/*
+   Executes the vertices with the most children first, starting the largest subtrees early
+   Note: ties keep the order they became ready in
+*/
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DegreePriority;
+
+impl Scheduler for DegreePriority {
+    fn order(&self, ready: &mut [VertexID], degree: &dyn Fn(VertexID) -> usize) {
+        ready.sort_by_cached_key(|v_id| core::cmp::Reverse(degree(*v_id)));
+    }
+}
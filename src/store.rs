@@ -0,0 +1,184 @@
+/* store.rs
+
+   Contains the storage backend behind the DataStore's (vertex_id -> vertex) mapping, so that graphs larger
+   than memory can be kept elsewhere (e.g. on disk) without changing the UDF-facing interfaces
+
+   Author: Binghong(Leo) Li
+   Creation Date: 10/14/2026
+*/
+
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec;
+
+use crate::codec::{BincodeCodec, Codec};
+use crate::vertex::{Vertex, VertexID};
+
+use hashbrown::HashMap;
+use std::sync::RwLock;
+
+// a vertex handed out by a backend, along with its id
+pub type StoreEntry<T, V, C = BincodeCodec, P = C> = (VertexID, Arc<Vertex<T, V, C, P>>);
+
+/*
+   Trait requirement for vertex storage backends
+
+   Backends are shared between tasks (hence Send + Sync) and changed while executes run on them, so every
+   method takes &self and the vertices are handed out as shared handles: a vertex removed or replaced
+   stays alive for the executes still holding it, and a backend keeping vertices out of memory only has to
+   keep resident the ones whose handles are still around
+*/
+pub trait VertexStore<T, V, C = BincodeCodec, P = C>: Send + Sync {
+    fn get(&self, id: &VertexID) -> Option<Arc<Vertex<T, V, C, P>>>;
+
+    // returns the vertex previously stored under id, if any
+    fn insert(
+        &self,
+        id: VertexID,
+        vertex: Arc<Vertex<T, V, C, P>>,
+    ) -> Option<Arc<Vertex<T, V, C, P>>>;
+
+    fn remove(&self, id: &VertexID) -> Option<Arc<Vertex<T, V, C, P>>>;
+
+    // every vertex stored on this machine, remote references included, in no particular order
+    // Note: a snapshot, vertices inserted or removed while it is walked are not reflected
+    fn iter_local(&self) -> vec::IntoIter<StoreEntry<T, V, C, P>>;
+}
+
+type VertexMap<T, V, C, P> = HashMap<VertexID, Arc<Vertex<T, V, C, P>>>;
+
+/*
+   Default backend, keeping every vertex in memory
+*/
+pub struct InMemoryStore<T, V, C = BincodeCodec, P = C> {
+    map: RwLock<VertexMap<T, V, C, P>>,
+}
+
+impl<T, V, C, P> InMemoryStore<T, V, C, P> {
+    pub fn new() -> Self {
+        InMemoryStore {
+            map: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T, V, C, P> Default for InMemoryStore<T, V, C, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Send + Sync, V: Send + Sync, C: Codec, P: Codec> VertexStore<T, V, C, P>
+    for InMemoryStore<T, V, C, P>
+{
+    fn get(&self, id: &VertexID) -> Option<Arc<Vertex<T, V, C, P>>> {
+        self.map.read().unwrap().get(id).cloned()
+    }
+
+    fn insert(
+        &self,
+        id: VertexID,
+        vertex: Arc<Vertex<T, V, C, P>>,
+    ) -> Option<Arc<Vertex<T, V, C, P>>> {
+        self.map.write().unwrap().insert(id, vertex)
+    }
+
+    fn remove(&self, id: &VertexID) -> Option<Arc<Vertex<T, V, C, P>>> {
+        self.map.write().unwrap().remove(id)
+    }
+
+    fn iter_local(&self) -> vec::IntoIter<StoreEntry<T, V, C, P>> {
+        let vertices: Vec<_> = self
+            .map
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(id, vertex)| (*id, vertex.clone()))
+            .collect();
+        vertices.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::DataStore;
+    use crate::udf::GraphSum;
+    use crate::vertex::{Data, VertexKind};
+    use crate::worker::Worker;
+
+    use std::sync::Mutex;
+
+    type Entry<T, V> = (VertexID, Arc<Vertex<T, V>>);
+
+    // backend keeping the vertices in a plain list, recording the ids looked up through it
+    struct ListStore<T, V> {
+        vertices: Mutex<Vec<Entry<T, V>>>,
+        lookups: Arc<Mutex<Vec<VertexID>>>,
+    }
+
+    impl<T: Send + Sync, V: Send + Sync> VertexStore<T, V> for ListStore<T, V> {
+        fn get(&self, id: &VertexID) -> Option<Arc<Vertex<T, V>>> {
+            self.lookups.lock().unwrap().push(*id);
+            let vertices = self.vertices.lock().unwrap();
+            vertices
+                .iter()
+                .find(|(v_id, _)| v_id == id)
+                .map(|(_, vertex)| vertex.clone())
+        }
+
+        fn insert(&self, id: VertexID, vertex: Arc<Vertex<T, V>>) -> Option<Arc<Vertex<T, V>>> {
+            let previous = self.remove(&id);
+            self.vertices.lock().unwrap().push((id, vertex));
+            previous
+        }
+
+        fn remove(&self, id: &VertexID) -> Option<Arc<Vertex<T, V>>> {
+            let mut vertices = self.vertices.lock().unwrap();
+            let position = vertices.iter().position(|(v_id, _)| v_id == id)?;
+            Some(vertices.swap_remove(position).1)
+        }
+
+        fn iter_local(&self) -> vec::IntoIter<(VertexID, Arc<Vertex<T, V>>)> {
+            self.vertices.lock().unwrap().clone().into_iter()
+        }
+    }
+
+    #[tokio::test]
+    async fn data_store_runs_on_a_custom_backend() {
+        let lookups = Arc::new(Mutex::new(vec![]));
+        let store = ListStore {
+            vertices: Mutex::new(vec![]),
+            lookups: lookups.clone(),
+        };
+        let worker = Arc::new(Worker::new(1));
+        let data_store = Arc::new(DataStore::with_store(worker.clone(), Box::new(store)));
+        for (id, parents, children, data) in [
+            (0, vec![], vec![1, 2], 1),
+            (1, vec![0], vec![], 2),
+            (2, vec![0], vec![], 3),
+        ] {
+            let data = Some(Data(data));
+            data_store.add_new_vertex(
+                id,
+                &parents,
+                &children,
+                data,
+                VertexKind::Local,
+                None,
+                worker.clone(),
+            );
+        }
+
+        let sum = data_store
+            .get_vertex_by_id(&0)
+            .apply_function(&GraphSum, data_store.clone(), None)
+            .await
+            .unwrap();
+        assert_eq!(sum, 6);
+        // the children were looked up through the backend, and GraphSum's new leaves went into it
+        let lookups = lookups.lock().unwrap();
+        assert!(lookups.contains(&1) && lookups.contains(&2));
+        assert_eq!(data_store.map.iter_local().count(), 5);
+    }
+}
@@ -12,13 +12,17 @@
 
 extern crate alloc;
 use alloc::sync::Arc;
+use core::fmt::Debug;
 use core::ops::AddAssign;
 
+use crate::codec::Codec;
 use crate::datastore::DataStore;
+use crate::error::FusionError;
 use crate::vertex::*;
-use crate::UserDefinedFunction;
+use crate::{MutableUserDefinedFunction, TryUserDefinedFunction};
 
 use async_trait::async_trait;
+use futures::future::join_all;
 use hashbrown::HashSet;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
@@ -38,17 +42,23 @@ impl AddAssign<isize> for Data<isize> {
 
 /*
    GraphSum sums the entire graph recursively
+
+   A leaf contributes exactly its own value, a vertex without data the identity (0)
+
+   The children are summed concurrently, as they may live on different machines, in chunks of the invocation's
+   ExecOptions::child_chunk_size (see Vertex::apply_function_to_children()); the sum stays deterministic
+   since the order the child results are added in does not matter for isize
 */
 #[derive(Clone)]
 pub struct GraphSum;
 #[async_trait]
-impl UserDefinedFunction<isize, Option<u64>, isize> for GraphSum {
-    async fn execute(
+impl<C: Codec, P: Codec> TryUserDefinedFunction<isize, Option<u64>, isize, C, P> for GraphSum {
+    async fn try_execute(
         &self,
-        vertex: &Vertex<isize, isize>,
-        data_store: Arc<DataStore<isize, isize>>,
+        vertex: &Vertex<isize, isize, C, P>,
+        data_store: Arc<DataStore<isize, isize, C, P>>,
         aux_info: Option<u64>,
-    ) -> isize {
+    ) -> Result<isize, FusionError> {
         let mut count = Data(0);
 
         /* wrong placement of code, actually caught
@@ -59,20 +69,280 @@ impl UserDefinedFunction<isize, Option<u64>, isize> for GraphSum {
            }
         */
 
-        count += (*vertex.get_val().await).as_ref().unwrap().0;
+        count += (*vertex.get_val().await).as_ref().map_or(0, |data| data.0);
 
-        for sub_graph_root_id in vertex.children().iter() {
-            count += data_store
-                .get_vertex_by_id(sub_graph_root_id)
-                .apply_function(self, data_store.clone(), aux_info.clone())
-                .await;
-        }
+        count += vertex
+            .apply_function_to_children(
+                self,
+                data_store.clone(),
+                aux_info,
+                current_options(),
+                0,
+                |acc, sub_graph_sum| acc + sub_graph_sum,
+            )
+            .await?;
 
         if vertex.children().is_empty() {
             vertex.add_child(data_store.clone(), Data(10000)).await;
         }
 
-        count.0
+        Ok(count.0)
+    }
+}
+
+/*
+   StrictGraphSum sums the graph like GraphSum, but fails the call on a vertex without data instead of
+   counting it as 0, the error naming that vertex, and likewise on a child missing from the data store
+*/
+#[derive(Clone)]
+pub struct StrictGraphSum;
+#[async_trait]
+impl<C: Codec, P: Codec> TryUserDefinedFunction<isize, Option<u64>, isize, C, P>
+    for StrictGraphSum
+{
+    async fn try_execute(
+        &self,
+        vertex: &Vertex<isize, isize, C, P>,
+        data_store: Arc<DataStore<isize, isize, C, P>>,
+        aux_info: Option<u64>,
+    ) -> Result<isize, FusionError> {
+        let mut count = match (*vertex.get_val().await).as_ref() {
+            Some(data) => Data(data.0),
+            None => return Err(FusionError::Udf("vertex has no data".to_string())),
+        };
+
+        let data_store_ref = &data_store;
+        let sub_graph_sums = join_all(vertex.children().iter().map(
+            |sub_graph_root_id| async move {
+                data_store_ref
+                    .try_get_vertex_by_id(sub_graph_root_id)?
+                    .apply_function(self, data_store_ref.clone(), aux_info)
+                    .await
+            },
+        ))
+        .await;
+        for sub_graph_sum in sub_graph_sums {
+            count += sub_graph_sum?;
+        }
+
+        Ok(count.0)
+    }
+}
+
+/*
+   Combining operation of GraphReduce: combine should be associative and commutative, since the children
+   are combined in whatever order they finish, and identity leaves any value unchanged under it
+*/
+pub trait ReduceOp<T>: Clone + Send + Sync {
+    fn identity(&self) -> T;
+    fn combine(&self, acc: T, val: T) -> T;
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Sum;
+#[derive(Clone, Copy, Default)]
+pub struct Product;
+#[derive(Clone, Copy, Default)]
+pub struct Max;
+#[derive(Clone, Copy, Default)]
+pub struct Min;
+
+// the operations for the primitive integers, the identities of Max and Min being the bounds of the type
+macro_rules! impl_reduce_ops {
+    ($($t:ty),*) => {$(
+        impl ReduceOp<$t> for Sum {
+            fn identity(&self) -> $t {
+                0
+            }
+            fn combine(&self, acc: $t, val: $t) -> $t {
+                acc + val
+            }
+        }
+
+        impl ReduceOp<$t> for Product {
+            fn identity(&self) -> $t {
+                1
+            }
+            fn combine(&self, acc: $t, val: $t) -> $t {
+                acc * val
+            }
+        }
+
+        impl ReduceOp<$t> for Max {
+            fn identity(&self) -> $t {
+                <$t>::MIN
+            }
+            fn combine(&self, acc: $t, val: $t) -> $t {
+                acc.max(val)
+            }
+        }
+
+        impl ReduceOp<$t> for Min {
+            fn identity(&self) -> $t {
+                <$t>::MAX
+            }
+            fn combine(&self, acc: $t, val: $t) -> $t {
+                acc.min(val)
+            }
+        }
+    )*};
+}
+
+impl_reduce_ops!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+/*
+   One-off operation from an identity and a function, e.g. Combiner { identity: 0, combine: |acc, val| acc ^ val }
+   (non-capturing closures only, so that the operation stays Send + Sync)
+*/
+#[derive(Clone, Copy)]
+pub struct Combiner<T> {
+    pub identity: T,
+    pub combine: fn(T, T) -> T,
+}
+
+impl<T: Clone + Send + Sync> ReduceOp<T> for Combiner<T> {
+    fn identity(&self) -> T {
+        self.identity.clone()
+    }
+    fn combine(&self, acc: T, val: T) -> T {
+        (self.combine)(acc, val)
+    }
+}
+
+/*
+   GraphReduce folds the values of a vertex and all its descendants with Op, e.g. GraphReduce(Product)
+   or GraphReduce(Combiner { .. })
+
+   Same recursion as GraphSum: a vertex without data contributes the identity, and the children are reduced
+   concurrently, chunk by chunk. The operation itself isn't shipped over rpc, each machine combines the subtrees it owns with
+   the GraphReduce it serves, so every machine has to serve the same one.
+*/
+#[derive(Clone, Default)]
+pub struct GraphReduce<Op>(pub Op);
+#[async_trait]
+impl<T, Op, C, P> TryUserDefinedFunction<T, Option<u64>, T, C, P> for GraphReduce<Op>
+where
+    C: Codec,
+    P: Codec,
+    T: DeserializeOwned + Serialize + Debug + Default + Clone + Send + Sync,
+    Op: ReduceOp<T>,
+{
+    async fn try_execute(
+        &self,
+        vertex: &Vertex<T, T, C, P>,
+        data_store: Arc<DataStore<T, T, C, P>>,
+        aux_info: Option<u64>,
+    ) -> Result<T, FusionError> {
+        let own = (*vertex.get_val().await)
+            .as_ref()
+            .map_or_else(|| self.0.identity(), |data| data.0.clone());
+
+        vertex
+            .apply_function_to_children(
+                self,
+                data_store.clone(),
+                aux_info,
+                current_options(),
+                own,
+                |acc, val| self.0.combine(acc, val),
+            )
+            .await
+    }
+}
+
+/*
+   GraphMax and GraphMin find the largest and the smallest value of a vertex and all its descendants, e.g.
+   GraphMax::default() or GraphReduce(Max)
+
+   A vertex without data contributes the bound of the type (isize::MIN for the max, isize::MAX for the min),
+   which is also what a subgraph without any data returns
+*/
+pub type GraphMax = GraphReduce<Max>;
+pub type GraphMin = GraphReduce<Min>;
+
+/*
+   GraphSumOnce sums every vertex reachable from the root over the edges of a general graph exactly once,
+   so that neither cycles nor vertices reachable through several paths are counted more than once
+
+   aux_info is the traversal the call belongs to, None at the root to start a new one, which the root ends once
+   everything is summed
+*/
+#[derive(Clone)]
+pub struct GraphSumOnce;
+#[async_trait]
+impl<C: Codec, P: Codec> TryUserDefinedFunction<isize, Option<u64>, isize, C, P> for GraphSumOnce {
+    async fn try_execute(
+        &self,
+        vertex: &Vertex<isize, isize, C, P>,
+        data_store: Arc<DataStore<isize, isize, C, P>>,
+        aux_info: Option<u64>,
+    ) -> Result<isize, FusionError> {
+        let Some(traversal) = aux_info else {
+            let traversal = new_traversal();
+            let sum = self
+                .try_execute(vertex, data_store.clone(), Some(traversal))
+                .await;
+            data_store.end_traversal(traversal).await;
+            return sum;
+        };
+
+        // already counted through another path
+        if !data_store.first_visit(traversal, vertex.id) {
+            return Ok(0);
+        }
+
+        let mut count = Data(0);
+        count += (*vertex.get_val().await).as_ref().map_or(0, |data| data.0);
+
+        let data_store_ref = &data_store;
+        let neighbor_sums = join_all(vertex.edges().iter().map(|neighbor_id| async move {
+            data_store_ref
+                .get_vertex_by_id(neighbor_id)
+                .apply_function(self, data_store_ref.clone(), Some(traversal))
+                .await
+        }))
+        .await;
+        for neighbor_sum in neighbor_sums {
+            count += neighbor_sum?;
+        }
+
+        Ok(count.0)
+    }
+}
+
+/*
+   AccumulateSubtree replaces the data of every vertex with the sum of its subtree, which it also returns
+
+   A vertex without data counts as 0, same as in GraphSum
+*/
+#[derive(Clone)]
+pub struct AccumulateSubtree;
+#[async_trait]
+impl<C: Codec, P: Codec> MutableUserDefinedFunction<isize, Option<u64>, isize, C, P>
+    for AccumulateSubtree
+{
+    async fn execute_mut(
+        &self,
+        vertex: &Vertex<isize, isize, C, P>,
+        data: &mut Data<isize>,
+        data_store: Arc<DataStore<isize, isize, C, P>>,
+        aux_info: Option<u64>,
+    ) -> Result<isize, FusionError> {
+        let data_store_ref = &data_store;
+        let sub_graph_sums = join_all(vertex.children().iter().map(
+            |sub_graph_root_id| async move {
+                data_store_ref
+                    .get_vertex_by_id(sub_graph_root_id)
+                    .apply_function_mut(self, data_store_ref.clone(), aux_info)
+                    .await
+            },
+        ))
+        .await;
+        for sub_graph_sum in sub_graph_sums {
+            *data += sub_graph_sum?;
+        }
+
+        Ok(data.0)
     }
 }
 
@@ -94,13 +364,15 @@ pub struct NMASInfo {
 }
 
 #[async_trait]
-impl UserDefinedFunction<isize, Option<NMASInfo>, isize> for NaiveMaxAdjacentSum {
-    async fn execute(
+impl<C: Codec, P: Codec> TryUserDefinedFunction<isize, Option<NMASInfo>, isize, C, P>
+    for NaiveMaxAdjacentSum
+{
+    async fn try_execute(
         &self,
-        vertex: &Vertex<isize, isize>,
-        data_store: Arc<DataStore<isize, isize>>,
+        vertex: &Vertex<isize, isize, C, P>,
+        data_store: Arc<DataStore<isize, isize, C, P>>,
         aux_info: Option<NMASInfo>,
-    ) -> isize {
+    ) -> Result<isize, FusionError> {
         let mut count = Data(0);
         count += (*vertex.get_val().await).as_ref().unwrap().0;
 
@@ -108,17 +380,17 @@ impl UserDefinedFunction<isize, Option<NMASInfo>, isize> for NaiveMaxAdjacentSum
 
         // exhausted all steps
         if aux_info.distance == 0 {
-            return count.0;
+            return Ok(count.0);
         }
 
-        return match &aux_info.source {
+        return Ok(match &aux_info.source {
             None => {
                 // this is start, add to the "started" set
                 let mut aux_info_started = aux_info.started.unwrap();
                 aux_info_started.insert(vertex.id);
 
                 // travel to neighbors with distance - 1, "started" no longer needed
-                for neighbor_id in vertex.edges() {
+                for neighbor_id in vertex.edges().iter() {
                     count += data_store
                         .get_vertex_by_id(neighbor_id)
                         .apply_function(
@@ -130,7 +402,7 @@ impl UserDefinedFunction<isize, Option<NMASInfo>, isize> for NaiveMaxAdjacentSum
                                 started: None,
                             }),
                         )
-                        .await;
+                        .await?;
                 }
 
                 // gather all possible exploration results and taking the max
@@ -152,17 +424,17 @@ impl UserDefinedFunction<isize, Option<NMASInfo>, isize> for NaiveMaxAdjacentSum
                                         started: Some(aux_info_started.clone()),
                                     }),
                                 )
-                                .await,
+                                .await?,
                         );
                     }
                 }
 
                 // return the max of all explorations
-                vec_of_res.iter().max().unwrap().clone()
+                *vec_of_res.iter().max().unwrap()
             }
             Some(source) => {
                 // This is not the start, travel to neighbors with distance - 1, "started" not needed
-                for neighbor_id in vertex.edges() {
+                for neighbor_id in vertex.edges().iter() {
                     if source.ne(neighbor_id) {
                         count += data_store
                             .get_vertex_by_id(neighbor_id)
@@ -175,13 +447,13 @@ impl UserDefinedFunction<isize, Option<NMASInfo>, isize> for NaiveMaxAdjacentSum
                                     started: None,
                                 }),
                             )
-                            .await;
+                            .await?;
                     }
                 }
                 // return sum
                 count.0
             }
-        };
+        });
     }
 }
 
@@ -222,48 +494,50 @@ pub struct SLASInfo {
     pub min_id: VertexID,
 }
 
-impl<T: DeserializeOwned + Ord + Default> PartialEq for Data<T> {
+impl<T: Ord> PartialEq for Data<T> {
     fn eq(&self, other: &Self) -> bool {
         self.0 == other.0
     }
 }
-impl<T: DeserializeOwned + Ord + Default> Eq for Data<T> {}
-impl<T: DeserializeOwned + Ord + Default> PartialOrd for Data<T> {
+impl<T: Ord> Eq for Data<T> {}
+impl<T: Ord> PartialOrd for Data<T> {
     fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
         Some(self.cmp(other))
     }
 }
-impl<T: DeserializeOwned + Ord + Default> Ord for Data<T> {
+impl<T: Ord> Ord for Data<T> {
     fn cmp(&self, other: &Self) -> core::cmp::Ordering {
         self.0.cmp(&other.0)
     }
 }
 
 #[async_trait]
-impl UserDefinedFunction<isize, bool, SLASInfo> for SwapLargestAndSmallest {
-    async fn execute(
+impl<C: Codec, P: Codec> TryUserDefinedFunction<isize, bool, SLASInfo, C, P>
+    for SwapLargestAndSmallest
+{
+    async fn try_execute(
         &self,
-        vertex: &Vertex<isize, SLASInfo>,
-        data_store: Arc<DataStore<isize, SLASInfo>>,
+        vertex: &Vertex<isize, SLASInfo, C, P>,
+        data_store: Arc<DataStore<isize, SLASInfo, C, P>>,
         aux_info: bool,
-    ) -> SLASInfo {
+    ) -> Result<SLASInfo, FusionError> {
         let val = vertex.get_val().await;
 
         if vertex.children().is_empty() {
             // I am the leaf
-            return SLASInfo {
+            return Ok(SLASInfo {
                 max_val: (*val).clone().unwrap(),
                 min_val: (*val).clone().unwrap(),
                 max_id: vertex.id,
                 min_id: vertex.id,
-            };
+            });
         }
         let mut results = vec![];
         for children_id in vertex.children().iter() {
             let result = data_store
                 .get_vertex_by_id(children_id)
                 .apply_function(self, data_store.clone(), false)
-                .await;
+                .await?;
             results.push(result);
         }
 
@@ -287,7 +561,7 @@ impl UserDefinedFunction<isize, bool, SLASInfo> for SwapLargestAndSmallest {
 
         // not the root
         if !aux_info {
-            return res;
+            return Ok(res);
         }
 
         // also send out the command to update the nodes' values
@@ -314,6 +588,162 @@ impl UserDefinedFunction<isize, bool, SLASInfo> for SwapLargestAndSmallest {
                 .await
         );
 
-        return res;
+        return Ok(res);
+    }
+}
+
+/*
+   SumInts sums the Int values of a graph of DynValue, the vertices holding other kinds of values (or none)
+   counting as 0, while their children are still visited
+*/
+#[derive(Clone)]
+pub struct SumInts;
+
+#[async_trait]
+impl<C: Codec, P: Codec> TryUserDefinedFunction<DynValue, Option<u64>, i64, C, P> for SumInts {
+    async fn try_execute(
+        &self,
+        vertex: &Vertex<DynValue, i64, C, P>,
+        data_store: Arc<DataStore<DynValue, i64, C, P>>,
+        aux_info: Option<u64>,
+    ) -> Result<i64, FusionError> {
+        let own = (*vertex.get_val().await)
+            .as_ref()
+            .and_then(|data| data.0.as_int())
+            .unwrap_or(0);
+
+        vertex
+            .apply_function_to_children(
+                self,
+                data_store.clone(),
+                aux_info,
+                current_options(),
+                own,
+                |acc, sum| acc + sum,
+            )
+            .await
+    }
+}
+
+/*
+   CollectAtLeast collects the ids of every vertex in the subtree whose data is at least the threshold
+   (all of them without one), a collection-valued result of varying length
+
+   A vertex without data is below every threshold
+*/
+#[derive(Clone)]
+pub struct CollectAtLeast;
+
+#[async_trait]
+impl<C: Codec, P: Codec> TryUserDefinedFunction<isize, Option<isize>, Vec<VertexID>, C, P>
+    for CollectAtLeast
+{
+    async fn try_execute(
+        &self,
+        vertex: &Vertex<isize, Vec<VertexID>, C, P>,
+        data_store: Arc<DataStore<isize, Vec<VertexID>, C, P>>,
+        aux_info: Option<isize>,
+    ) -> Result<Vec<VertexID>, FusionError> {
+        let mut matching = vec![];
+        // a vertex without data only matches without a threshold
+        let val = (*vertex.get_val().await).as_ref().map(|data| data.0);
+        if aux_info.is_none_or(|threshold| val.is_some_and(|val| val >= threshold)) {
+            matching.push(vertex.id);
+        }
+
+        for children_id in vertex.children().iter() {
+            matching.extend(
+                data_store
+                    .get_vertex_by_id(children_id)
+                    .apply_function(self, data_store.clone(), aux_info)
+                    .await?,
+            );
+        }
+        Ok(matching)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::build_graph_integer_data;
+    use crate::vertex::tests::local_graph;
+    use crate::worker::tests::in_memory_pair_serving;
+
+    #[tokio::test]
+    async fn graph_max_and_min_find_the_extrema_of_the_subtree() {
+        //       0
+        //      / \
+        //     1   2
+        //    / \
+        //   3   4
+        let data_store = local_graph(&[
+            (0, &[1, 2], Some(5)),
+            (1, &[3, 4], Some(-3)),
+            (2, &[], Some(8)),
+            (3, &[], None),
+            (4, &[], Some(12)),
+        ]);
+        let extrema = |id| {
+            let data_store = data_store.clone();
+            async move {
+                let vertex = data_store.get_vertex_by_id(&id);
+                let max = vertex
+                    .apply_function(&GraphMax::default(), data_store.clone(), None)
+                    .await
+                    .unwrap();
+                let min = vertex
+                    .apply_function(&GraphReduce(Min), data_store.clone(), None)
+                    .await
+                    .unwrap();
+                (max, min)
+            }
+        };
+
+        assert_eq!(extrema(0).await, (12, -3));
+        assert_eq!(extrema(1).await, (12, -3));
+        assert_eq!(extrema(2).await, (8, 8));
+        // a subtree without any data gives the bounds of the type
+        assert_eq!(extrema(3).await, (isize::MIN, isize::MAX));
+    }
+
+    #[tokio::test]
+    async fn collect_at_least_skips_the_vertices_without_data() {
+        let data_store = local_graph(&[(0, &[1, 2], Some(5)), (1, &[], None), (2, &[], Some(9))]);
+        let root = data_store.get_vertex_by_id(&0);
+
+        let mut all = root
+            .apply_function(&CollectAtLeast, data_store.clone(), None)
+            .await
+            .unwrap();
+        all.sort_unstable();
+        assert_eq!(all, [0, 1, 2]);
+
+        let large = root
+            .apply_function(&CollectAtLeast, data_store.clone(), Some(isize::MIN))
+            .await
+            .unwrap();
+        assert!(!large.contains(&1));
+    }
+
+    #[tokio::test]
+    async fn graph_max_across_machines() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                // the largest value, 600 under vertex 13, lives on machine 2
+                let (data_store_1, _data_store_2) = in_memory_pair_serving(
+                    (&GraphMax::default(), &AccumulateSubtree),
+                    build_graph_integer_data,
+                )
+                .await;
+                let max = data_store_1
+                    .get_vertex_by_id(&0)
+                    .apply_function(&GraphMax::default(), data_store_1.clone(), None)
+                    .await
+                    .unwrap();
+                assert_eq!(max, 600);
+            })
+            .await;
     }
 }
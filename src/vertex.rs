@@ -11,44 +11,332 @@ extern crate alloc;
 use alloc::sync::Arc;
 use core::marker::PhantomData;
 use core::ops::Deref;
-use core::sync::atomic::Ordering;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
 
+use crate::codec::{frame, BincodeCodec, Codec};
 use crate::datastore::DataStore;
-use crate::rpc::{RPCResPayload, RPC};
-use crate::{worker::Worker, UserDefinedFunction};
+use crate::error::FusionError;
+use crate::rpc::{
+    AuxDeltaPayload, CallReport, CallerContext, LeaseMode, LentVertex, Neighbors, RPCResPayload,
+    RpcError, RPC,
+};
+use crate::scheduler::{Fifo, Scheduler};
+use crate::worker::Worker;
+use crate::{AuxBase, AuxDelta, MutableUserDefinedFunction, UserDefinedFunction};
 
+use futures::future::join_all;
 use hashbrown::hash_map::Entry;
 use hashbrown::{HashMap, HashSet};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::future::Future;
 use std::thread;
 use std::thread::ThreadId;
-use tokio::io::AsyncWriteExt;
+use std::time::Instant;
 use tokio::sync::{mpsc, Mutex};
 use tokio_condvar::Condvar;
+use tracing::{debug, field, Span};
 use uuid::Uuid;
 
 /* *********** Type Aliases *********** */
 pub type VertexID = u32;
 pub type MachineID = u32;
 
+// number of past versions of its data each local vertex retains
+// Note: costs up to this many serialized copies of Data<T> per vertex, on top of the current one
+pub const VERSION_HISTORY_LEN: usize = 8;
+
+tokio::task_local! {
+    // depth of the vertex whose UDF is being executed, the root of a call being at depth 1
+    static EXEC_DEPTH: usize;
+    // what the computation used of its limits, only tracked when it has limits
+    static CALL_USAGE: Arc<CallUsage>;
+    // memoized call the execution is part of, see DataStore::apply_function_memoized()
+    static MEMO_CALL: u64;
+    // vertex a remote caller asked this machine to execute, and the machines that forwarded it here
+    static FORWARDED: Option<(VertexID, Vec<MachineID>)>;
+    // options of the invocation the execution is part of, see current_options()
+    static EXEC_OPTIONS: ExecOptions;
+}
+
+// id of a new traversal, to be passed along with its calls for DataStore::first_visit()
+pub fn new_traversal() -> u64 {
+    Uuid::new_v4().as_u64_pair().0
+}
+
+// depth of the execution calling this, 0 outside of any UDF
+pub fn current_depth() -> usize {
+    EXEC_DEPTH.try_with(|depth| *depth).unwrap_or(0)
+}
+
+/*
+   Options of the invocation the calling UDF runs under, for it to pass on to its children (e.g. to
+   Vertex::apply_function_to_children()), the default ones outside of any
+
+   Set by Vertex::try_apply_function() for the whole recursion below it, apply_function() carrying them on.
+   Across machines only the priority travels, the serving machine running the rest of the recursion with
+   its own defaults.
+*/
+pub fn current_options() -> ExecOptions {
+    EXEC_OPTIONS
+        .try_with(|options| *options)
+        .unwrap_or_default()
+}
+
+// running `f` as the recursion of an invocation under `options`
+pub(crate) async fn run_with_options<F: Future>(options: ExecOptions, f: F) -> F::Output {
+    EXEC_OPTIONS.scope(options, f).await
+}
+
+/*
+   Limits of a single computation, counted over all the machines working on it (None for no limit)
+       send_bytes: bytes it may send over rpc, commands and results
+       max_machines: distinct machines it may contact
+   Crossing them fails the whole call with FusionError::BudgetExceeded, respectively FanoutExceeded
+*/
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CallLimits {
+    pub send_bytes: Option<usize>,
+    pub max_machines: Option<usize>,
+}
+
+/*
+   What a computation used of its limits so far
+*/
+#[derive(Debug)]
+struct CallUsage {
+    limits: CallLimits,
+    sent: AtomicUsize,
+    contacted: std::sync::Mutex<HashSet<MachineID>>,
+}
+
+impl CallUsage {
+    fn charge(&self, used: &CallReport) -> Result<(), FusionError> {
+        let sent = self.sent.fetch_add(used.sent, Ordering::Relaxed) + used.sent;
+        if matches!(self.limits.send_bytes, Some(limit) if sent > limit) {
+            return Err(FusionError::BudgetExceeded);
+        }
+
+        let mut contacted = self.contacted.lock().unwrap();
+        contacted.extend(&used.contacted);
+        match self.limits.max_machines {
+            Some(max) if contacted.len() > max => Err(FusionError::FanoutExceeded(max)),
+            _ => Ok(()),
+        }
+    }
+
+    fn report(&self) -> CallReport {
+        CallReport {
+            sent: self.sent.load(Ordering::Relaxed),
+            contacted: self.contacted.lock().unwrap().iter().copied().collect(),
+        }
+    }
+}
+
+// running `f` against `limits`, with `contacted` already counted, returning what it used along with its output
+pub(crate) async fn run_with_limits<F: Future>(
+    limits: CallLimits,
+    contacted: HashSet<MachineID>,
+    f: F,
+) -> (F::Output, CallReport) {
+    let usage = Arc::new(CallUsage {
+        limits,
+        sent: AtomicUsize::new(0),
+        contacted: std::sync::Mutex::new(contacted),
+    });
+    let res = CALL_USAGE.scope(usage.clone(), f).await;
+    (res, usage.report())
+}
+
+// counting a request of `bytes` towards `location` against the limits of the current computation
+pub(crate) fn charge_request(location: MachineID, bytes: usize) -> Result<(), FusionError> {
+    charge_report(&CallReport {
+        sent: bytes,
+        contacted: vec![location],
+    })
+}
+
+// counting what a remote callee reported it used
+pub(crate) fn charge_report(used: &CallReport) -> Result<(), FusionError> {
+    CALL_USAGE
+        .try_with(|usage| usage.charge(used))
+        .unwrap_or(Ok(()))
+}
+
+pub(crate) fn memo_call() -> Option<u64> {
+    MEMO_CALL.try_with(|call| *call).ok()
+}
+
+pub(crate) async fn run_memoized<F: Future>(call: u64, f: F) -> F::Output {
+    MEMO_CALL.scope(call, f).await
+}
+
+/*
+   The machines the execute of `vertex_id` went through without being executed, `machine_id` included, when
+   `machine_id` is forwarding the execute it was asked for (empty for a new execute)
+
+   Fails with ForwardLoop once the execute comes back to a machine that already forwarded it, or takes more
+   than max_hops forwards, as the machines then don't agree on which of them owns the vertex
+*/
+pub(crate) fn forwarded_path(
+    vertex_id: VertexID,
+    machine_id: MachineID,
+    max_hops: usize,
+) -> Result<Vec<MachineID>, FusionError> {
+    let forwarded = FORWARDED.try_with(|forwarded| match forwarded {
+        Some((target, path)) if *target == vertex_id => Some(path.clone()),
+        _ => None,
+    });
+    let Ok(Some(mut path)) = forwarded else {
+        return Ok(vec![]);
+    };
+    let looped = path.contains(&machine_id);
+    path.push(machine_id);
+    if looped || path.len() > max_hops {
+        return Err(FusionError::ForwardLoop(vertex_id, path));
+    }
+    Ok(path)
+}
+
+// where the current computation is at, for a remote callee to continue from
+pub(crate) fn caller_context<C: Codec>(forwarded: Option<(VertexID, Vec<MachineID>)>) -> Vec<u8> {
+    let mut caller = CallerContext {
+        depth: current_depth(),
+        forwarded,
+        ..Default::default()
+    };
+    let _ = CALL_USAGE.try_with(|usage| {
+        let sent = usage.sent.load(Ordering::Relaxed);
+        caller.send_budget = usage
+            .limits
+            .send_bytes
+            .map(|limit| limit.saturating_sub(sent));
+        caller.max_machines = usage.limits.max_machines;
+        caller.contacted = usage.contacted.lock().unwrap().iter().copied().collect();
+    });
+    frame::<C, _>(&caller).unwrap()
+}
+
+/*
+   Running `f` as part of the recursion of a remote caller, returning what it used along with its output
+*/
+pub async fn continue_call<F: Future>(caller: CallerContext, f: F) -> (F::Output, CallReport) {
+    let limits = CallLimits {
+        send_bytes: caller.send_budget,
+        max_machines: caller.max_machines,
+    };
+    run_with_limits(
+        limits,
+        caller.contacted.into_iter().collect(),
+        FORWARDED.scope(caller.forwarded, EXEC_DEPTH.scope(caller.depth, f)),
+    )
+    .await
+}
+
+// the remote children of a chunk by machine, see Vertex::apply_function_to_children()
+type RemoteBatches<'a, T, V, C, P> =
+    HashMap<MachineID, (&'a RemoteVertex<T, V, C, P>, Vec<VertexID>)>;
+
 /* *********** struct definitions *********** */
 
+// children executed at once by Vertex::apply_function_to_children(), unless configured otherwise
+pub const DEFAULT_CHILD_CHUNK_SIZE: usize = 64;
+
+/*
+   Options of a single UDF invocation, the whole recursion below it running under them (see current_options())
+       priority: higher runs first when the serving machine has executes waiting, only carried by remote calls
+       child_chunk_size: children executed at once by apply_function_to_children(), through which the built-in
+                         folds (GraphSum, GraphReduce, SumInts) recurse, bounding its pending futures and
+                         results by this instead of the fan-out
+       scheduler: order in which apply_function_to_children() starts the children (see Scheduler), any fold
+                  recursing through it consulting it at every vertex. The children are ordered before they
+                  are chunked, so it decides which ones make the first chunks: child_chunk_size bounds how many
+                  run at once, and all of a chunk run concurrently, so it only matters once the fan-out exceeds
+                  a chunk. Remote children of a chunk then queue for the max_in_flight slots in that order
+       timeout: longest a remote vertex is waited on before giving up with FusionError::Timeout, None falls
+                back to Worker::remote_timeout
+       batch_remote: apply_function_to_children() groups the remote children of a chunk by machine, and sends
+                     each group as one RPC::ExecuteBatch instead of an Execute per child. Priority, timeout
+                     and memoization then don't apply to those children
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct ExecOptions {
+    pub priority: u8,
+    pub child_chunk_size: usize,
+    pub scheduler: &'static dyn Scheduler,
+    pub timeout: Option<Duration>,
+    pub batch_remote: bool,
+}
+
+impl Default for ExecOptions {
+    fn default() -> Self {
+        ExecOptions {
+            priority: 0,
+            child_chunk_size: DEFAULT_CHILD_CHUNK_SIZE,
+            scheduler: &Fifo,
+            timeout: None,
+            batch_remote: false,
+        }
+    }
+}
+
 /*
    Data Wrapper
 */
 #[derive(Serialize, Debug, Clone, Default, Deserialize)]
-pub struct Data<T: Default>(pub T);
+pub struct Data<T>(pub T);
+
+/*
+   Tagged value, for a single graph whose vertices hold different kinds of data, as Data<DynValue>
+
+   The variant travels along with the value over rpc, so remote machines get back the same kind of value.
+   The default (for vertices written without data, see MutableUserDefinedFunction) is Int(0).
+*/
+#[derive(Serialize, Debug, Clone, PartialEq, Deserialize)]
+pub enum DynValue {
+    Int(i64),
+    Float(f64),
+    Bytes(Vec<u8>),
+}
+
+impl Default for DynValue {
+    fn default() -> Self {
+        DynValue::Int(0)
+    }
+}
+
+impl DynValue {
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            DynValue::Int(n) => Some(*n),
+            _ => None,
+        }
+    }
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            DynValue::Float(x) => Some(*x),
+            _ => None,
+        }
+    }
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            DynValue::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+}
 
 /* VertexType
    A vertex is either
         1)  local:      local data
         2)  remote:     remote reference of vertex that lives on another machine/core/node
-        3)  borrowed:   brought to local, original copy resides in remote (protected when leased?)
+        3)  borrowed:   brought to local, original copy resides in remote (leased out there, see DataStore::borrow())
 */
 #[derive(Debug)]
-pub enum VertexType<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> {
+pub enum VertexType<T, V, C = BincodeCodec, P = C> {
     Local(LocalVertex<T, V>),
-    Remote(RemoteVertex<T, V>),
+    Remote(RemoteVertex<T, V, C, P>),
     Borrowed(LocalVertex<T, V>),
     // Note: maybe a (Leased) variant for the future?
 }
@@ -57,45 +345,360 @@ pub enum VertexType<T: DeserializeOwned + Serialize + Debug + Default, V: Debug>
    Vertex
 */
 #[derive(Debug)]
-pub struct Vertex<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> {
+pub struct Vertex<T, V, C = BincodeCodec, P = C> {
     pub id: VertexID,
-    pub v_type: VertexType<T, V>,
+    pub v_type: VertexType<T, V, C, P>,
 }
-impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> Vertex<T, V> {
+impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug, C: Codec, P: Codec>
+    Vertex<T, V, C, P>
+{
     /*
         User-Defined_Function Invoker
 
             T: the output of the UDF, needs to be deserializable for rpc
             F: UDF that defines the execute function
+
+            Same as try_apply_function() with the options of the invocation this is part of (see
+            current_options()), the default ones for a new one
     */
     pub async fn apply_function<
-        F: UserDefinedFunction<T, U, V>,
+        F: UserDefinedFunction<T, U, V, C, P>,
         U: Serialize + DeserializeOwned,
     >(
         &self,
         udf: &F,
-        data_store: Arc<DataStore<T, V>>,
+        data_store: Arc<DataStore<T, V, C, P>>,
         auxiliary_information: U,
-    ) -> V {
-        match &self.v_type {
+    ) -> Result<V, FusionError> {
+        self.try_apply_function(udf, data_store, auxiliary_information, current_options())
+            .await
+    }
+
+    /*
+        User-Defined_Function Invoker, with per-invocation options (see ExecOptions)
+
+            An error anywhere in the subtree (e.g. a remote vertex timing out, see ExecOptions::timeout, or a
+            TryUserDefinedFunction failing) is returned to the UDF of the vertex calling into it, which carries
+            it further up with `?`
+    */
+    pub async fn try_apply_function<
+        F: UserDefinedFunction<T, U, V, C, P>,
+        U: Serialize + DeserializeOwned,
+    >(
+        &self,
+        udf: &F,
+        data_store: Arc<DataStore<T, V, C, P>>,
+        auxiliary_information: U,
+        options: ExecOptions,
+    ) -> Result<V, FusionError> {
+        // within DataStore::apply_function_memoized(), a vertex reached again is not executed again
+        let memo = data_store.clone();
+        if let Some(res) = memo.memoized(self.id) {
+            return Ok(res);
+        }
+
+        let res = match &self.v_type {
             VertexType::Local(_) | VertexType::Borrowed(_) => {
-                udf.execute(&self, data_store, auxiliary_information).await
+                // the recursion below goes on under the same options
+                run_with_options(
+                    options,
+                    self.execute_local(udf, data_store, auxiliary_information),
+                )
+                .await?
             }
             VertexType::Remote(remote_vertex) => {
-                // Delegate to the remote machine: rpc here
+                // Delegate to the remote machine: rpc here, unless it already ran with the same aux_info
+                let key = memo.remote_result_key(self.id, &auxiliary_information);
+                match key.and_then(|key| memo.cached_remote_result(key)) {
+                    Some(res) => res,
+                    None => {
+                        let res = remote_vertex
+                            .remote_execute(
+                                self.id,
+                                auxiliary_information,
+                                options.priority,
+                                options.timeout,
+                                RPC::Execute,
+                                true,
+                            )
+                            .await?;
+                        if let Some(key) = key {
+                            memo.cache_remote_result(key, &res);
+                        }
+                        res
+                    }
+                }
+            }
+        };
+        memo.memoize(self.id, &res);
+        Ok(res)
+    }
+
+    /*
+        Mutable User-Defined_Function Invoker, writing the data the UDF leaves behind back to the vertex
+
+            A local vertex is executed in place on a copy of its data, which then replaces the data like
+            LocalVertex::set_data() (so writes to the vertex while the UDF runs are overwritten). A vertex lent
+            out can't be written, and fails with LeasedOut before executing. A remote vertex is executed by
+            its owner, with the MutableUserDefinedFunction it serves.
+    */
+    pub async fn apply_function_mut<
+        F: MutableUserDefinedFunction<T, U, V, C, P>,
+        U: Serialize + DeserializeOwned,
+    >(
+        &self,
+        udf: &F,
+        data_store: Arc<DataStore<T, V, C, P>>,
+        auxiliary_information: U,
+    ) -> Result<V, FusionError> {
+        match &self.v_type {
+            VertexType::Local(local_v) | VertexType::Borrowed(local_v) => {
+                if local_v.is_leased_out() {
+                    return Err(FusionError::LeasedOut(self.id));
+                }
+                let mut data = local_v
+                    .get_data_at(self.id, None)
+                    .await?
+                    .unwrap_or_default();
 
+                data_store.pace_local_execute().await;
+                let depth = current_depth() + 1;
+                data_store.enter_depth(self.id, depth)?;
+                let res = EXEC_DEPTH
+                    .scope(
+                        depth,
+                        udf.execute_mut(self, &mut data, data_store, auxiliary_information),
+                    )
+                    .await?;
+                // the vertex may have been lent out while the UDF ran
+                local_v
+                    .set_data(data)
+                    .await
+                    .map_err(|e| e.for_vertex(self.id))?;
+                Ok(res)
+            }
+            VertexType::Remote(remote_vertex) => {
                 remote_vertex
-                    .remote_execute(self.id, auxiliary_information)
+                    .remote_execute(
+                        self.id,
+                        auxiliary_information,
+                        ExecOptions::default().priority,
+                        None,
+                        RPC::ExecuteMut,
+                        false,
+                    )
                     .await
             }
         }
     }
 
+    /*
+        Applying the UDF to every child and folding the results into `init` with `combine`
+
+            Children run concurrently in chunks of options.child_chunk_size, each chunk is completed and combined
+            before the next starts. options.scheduler orders the children before they are chunked, so it decides
+            which ones run in the first chunks, all of a chunk still run at the same time. Children on other
+            machines additionally wait in the serving machine's queue, ordered by options.priority there.
+            Combine should be associative and commutative (e.g. a sum) for the result not to depend on the chunking.
+            A leaf has no chunk to run, combine is never called and `init` comes back as is.
+            The first child failing fails the whole fold, the rest of its chunk is still awaited.
+    */
+    pub async fn apply_function_to_children<
+        F: UserDefinedFunction<T, U, V, C, P>,
+        U: Serialize + DeserializeOwned + Clone,
+        A,
+    >(
+        &self,
+        udf: &F,
+        data_store: Arc<DataStore<T, V, C, P>>,
+        auxiliary_information: U,
+        options: ExecOptions,
+        init: A,
+        mut combine: impl FnMut(A, V) -> A,
+    ) -> Result<A, FusionError> {
+        let mut children: Vec<VertexID> = self.children().iter().copied().collect();
+        options
+            .scheduler
+            .order(
+                &mut children,
+                &|child| match &data_store.get_vertex_by_id(&child).v_type {
+                    VertexType::Local(local_v) | VertexType::Borrowed(local_v) => {
+                        local_v.children().len()
+                    }
+                    VertexType::Remote(_) => 0,
+                },
+            );
+        let mut acc = init;
+        for chunk in children.chunks(options.child_chunk_size.max(1)) {
+            let vertices: Vec<_> = chunk
+                .iter()
+                .map(|child| data_store.get_vertex_by_id(child))
+                .collect();
+            // one round trip per machine rather than per child
+            let mut batches: RemoteBatches<T, V, C, P> = HashMap::new();
+            let mut executes = vec![];
+            for vertex in &vertices {
+                match &vertex.v_type {
+                    VertexType::Remote(remote_v) if options.batch_remote => batches
+                        .entry(remote_v.location())
+                        .or_insert_with(|| (remote_v, vec![]))
+                        .1
+                        .push(vertex.id),
+                    _ => executes.push(vertex.try_apply_function(
+                        udf,
+                        data_store.clone(),
+                        auxiliary_information.clone(),
+                        options,
+                    )),
+                }
+            }
+            let batched = join_all(batches.into_values().map(|(remote_v, batch)| {
+                let auxiliary_information = &auxiliary_information;
+                async move {
+                    remote_v
+                        .remote_execute_batch(&batch, auxiliary_information)
+                        .await
+                }
+            }));
+
+            let (results, batched) = futures::join!(join_all(executes), batched);
+            for res in results {
+                acc = combine(acc, res?);
+            }
+            for batch in batched {
+                for res in batch? {
+                    acc = combine(acc, res);
+                }
+            }
+        }
+        Ok(acc)
+    }
+
+    /*
+        Same as apply_function_to_children(), combining the results of the children as a balanced tree
+
+            Neighbouring results are combined pairwise, level by level, then the one left with `init`, so a
+            vertex with n children nests log2(n) combines instead of n (e.g. for floating point sums, or a
+            combine whose cost grows with the size of its inputs). Combine has to be associative.
+            A leaf has nothing to combine, `init` comes back as is.
+    */
+    pub async fn apply_function_to_children_balanced<
+        F: UserDefinedFunction<T, U, V, C, P>,
+        U: Serialize + DeserializeOwned + Clone,
+    >(
+        &self,
+        udf: &F,
+        data_store: Arc<DataStore<T, V, C, P>>,
+        auxiliary_information: U,
+        options: ExecOptions,
+        init: V,
+        mut combine: impl FnMut(V, V) -> V,
+    ) -> Result<V, FusionError> {
+        let mut level = self
+            .apply_function_to_children(
+                udf,
+                data_store,
+                auxiliary_information,
+                options,
+                vec![],
+                |mut results, res| {
+                    results.push(res);
+                    results
+                },
+            )
+            .await?;
+        while level.len() > 1 {
+            let mut results = level.into_iter();
+            let mut next = vec![];
+            while let Some(left) = results.next() {
+                next.push(match results.next() {
+                    Some(right) => combine(left, right),
+                    None => left,
+                });
+            }
+            level = next;
+        }
+        Ok(match level.pop() {
+            Some(children) => combine(init, children),
+            None => init,
+        })
+    }
+
+    /*
+        User-Defined_Function Invoker, for auxiliary information derived from `base`
+
+            Same as try_apply_function(), except that a remote vertex only receives the delta of
+            auxiliary_information against base (see AuxDelta)
+    */
+    pub async fn apply_function_with_base<
+        F: UserDefinedFunction<T, U, V, C, P>,
+        U: Serialize + DeserializeOwned + AuxDelta,
+    >(
+        &self,
+        udf: &F,
+        data_store: Arc<DataStore<T, V, C, P>>,
+        auxiliary_information: U,
+        base: &AuxBase<U>,
+    ) -> Result<V, FusionError> {
+        let memo = data_store.clone();
+        if let Some(res) = memo.memoized(self.id) {
+            return Ok(res);
+        }
+
+        let res = match &self.v_type {
+            VertexType::Local(_) | VertexType::Borrowed(_) => {
+                self.execute_local(udf, data_store, auxiliary_information)
+                    .await?
+            }
+            VertexType::Remote(remote_vertex) => {
+                remote_vertex
+                    .remote_execute_delta(
+                        self.id,
+                        auxiliary_information,
+                        base,
+                        current_options().priority,
+                    )
+                    .await?
+            }
+        };
+        memo.memoize(self.id, &res);
+        Ok(res)
+    }
+
+    // executing the UDF on this machine, one level deeper than the caller
+    async fn execute_local<
+        F: UserDefinedFunction<T, U, V, C, P>,
+        U: Serialize + DeserializeOwned,
+    >(
+        &self,
+        udf: &F,
+        data_store: Arc<DataStore<T, V, C, P>>,
+        auxiliary_information: U,
+    ) -> Result<V, FusionError> {
+        data_store.pace_local_execute().await;
+        let depth = current_depth() + 1;
+        data_store.enter_depth(self.id, depth)?;
+        EXEC_DEPTH
+            .scope(
+                depth,
+                udf.execute_checked(self, data_store, auxiliary_information),
+            )
+            .await
+    }
+
+    // the LocalVertex behind a vertex served here, local or borrowed, None for a remote reference
+    pub fn local(&self) -> Option<&LocalVertex<T, V>> {
+        match &self.v_type {
+            VertexType::Local(local_v) | VertexType::Borrowed(local_v) => Some(local_v),
+            VertexType::Remote(_) => None,
+        }
+    }
+
     /* Vertex Interfaces
        To allow local_vertex type functions to be called by the outer vertex struct
        Note: these are doable because the functions should never be invoked by a remote_vertex, or there are bugs
     */
-    pub fn children(&self) -> &HashSet<VertexID> {
+    pub fn children(&self) -> Arc<HashSet<VertexID>> {
         match &self.v_type {
             VertexType::Local(local_v) | VertexType::Borrowed(local_v) => local_v.children(),
             VertexType::Remote(_) => {
@@ -104,16 +707,7 @@ impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> Vertex<T, V> {
             }
         }
     }
-    pub fn children_mut(&mut self) -> &mut HashSet<VertexID> {
-        match &mut self.v_type {
-            VertexType::Local(local_v) | VertexType::Borrowed(local_v) => local_v.children_mut(),
-            VertexType::Remote(_) => {
-                // this should never be reached
-                panic!("Remote Node should not invoke children() function")
-            }
-        }
-    }
-    pub fn parents(&self) -> &HashSet<VertexID> {
+    pub fn parents(&self) -> Arc<HashSet<VertexID>> {
         match &self.v_type {
             VertexType::Local(local_v) | VertexType::Borrowed(local_v) => local_v.parents(),
             VertexType::Remote(_) => {
@@ -122,16 +716,7 @@ impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> Vertex<T, V> {
             }
         }
     }
-    pub fn parents_mut(&mut self) -> &mut HashSet<VertexID> {
-        match &mut self.v_type {
-            VertexType::Local(local_v) | VertexType::Borrowed(local_v) => local_v.parents_mut(),
-            VertexType::Remote(_) => {
-                // this should never be reached
-                panic!("Remote Node should not invoke parents() function")
-            }
-        }
-    }
-    pub fn edges(&self) -> &HashSet<VertexID> {
+    pub fn edges(&self) -> Arc<HashSet<VertexID>> {
         match &self.v_type {
             VertexType::Local(local_v) | VertexType::Borrowed(local_v) => local_v.edges(),
             VertexType::Remote(_) => {
@@ -140,38 +725,63 @@ impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> Vertex<T, V> {
             }
         }
     }
-    pub fn edges_mut(&mut self) -> &mut HashSet<VertexID> {
-        match &mut self.v_type {
-            VertexType::Local(local_v) | VertexType::Borrowed(local_v) => local_v.edges_mut(),
-            VertexType::Remote(_) => {
-                // this should never be reached
-                panic!("Remote Node should not invoke edges() function")
+
+    /* Async Vertex Interfaces
+       Same as the ones above, but also for a remote vertex, whose owner is asked for a copy of the set
+    */
+    pub async fn children_async(&self) -> Result<HashSet<VertexID>, FusionError> {
+        self.neighbors_async(Neighbors::Children).await
+    }
+    pub async fn parents_async(&self) -> Result<HashSet<VertexID>, FusionError> {
+        self.neighbors_async(Neighbors::Parents).await
+    }
+    pub async fn edges_async(&self) -> Result<HashSet<VertexID>, FusionError> {
+        self.neighbors_async(Neighbors::Edges).await
+    }
+    async fn neighbors_async(&self, which: Neighbors) -> Result<HashSet<VertexID>, FusionError> {
+        match &self.v_type {
+            VertexType::Local(local_v) | VertexType::Borrowed(local_v) => {
+                Ok(local_v.neighbors(which).as_ref().clone())
             }
+            VertexType::Remote(remote_v) => remote_v.remote_neighbors(self.id, which).await,
         }
     }
-    pub async fn get_val(&self) -> SafeDataReference<T, V> {
+
+    pub async fn get_val(&self) -> SafeDataReference<'_, '_, T, V> {
         match &self.v_type {
             VertexType::Local(local_v) | VertexType::Borrowed(local_v) => local_v.get_data().await,
             VertexType::Remote(_) => {
                 // this should never be reached
 
-                // sure one could do this, but you really shouldn't
+                // sure one could do this, but you really shouldn't, see get_val_async()
                 panic!("Remote Node should not invoke get_val() function")
             }
         }
     }
-    pub async fn update(&self, data: Data<T>) -> Option<Data<T>> {
+    // owned copy of the current data wherever the vertex lives, one rpc for a remote vertex
+    // Note: a vertex without data reads as None, not as an error
+    pub async fn get_val_async(&self) -> Result<Option<Data<T>>, FusionError> {
+        self.fetch_data(None).await
+    }
+    // returns the data it replaced, wherever the vertex lives, failing with LeasedOut while it is lent out
+    pub async fn update(&self, data: Data<T>) -> Result<Option<Data<T>>, FusionError> {
+        match &self.v_type {
+            VertexType::Local(local_v) | VertexType::Borrowed(local_v) => local_v
+                .set_data(data)
+                .await
+                .map_err(|e| e.for_vertex(self.id)),
+            VertexType::Remote(remote_v) => remote_v.remote_set_data(data, self.id).await,
+        }
+    }
+    pub async fn fetch_data(&self, version: Option<u64>) -> Result<Option<Data<T>>, FusionError> {
         match &self.v_type {
             VertexType::Local(local_v) | VertexType::Borrowed(local_v) => {
-                local_v.set_data(data).await
-            }
-            VertexType::Remote(remote_v) => {
-                // TODO: add some meaningful return later
-                remote_v.remote_update(data, self.id).await
+                local_v.get_data_at(self.id, version).await
             }
+            VertexType::Remote(remote_v) => remote_v.remote_fetch_data(self.id, version).await,
         }
     }
-    pub async fn add_child(&self, data_store: Arc<DataStore<T, V>>, data: Data<T>) {
+    pub async fn add_child(&self, data_store: Arc<DataStore<T, V, C, P>>, data: Data<T>) {
         match &self.v_type {
             VertexType::Local(local_v) | VertexType::Borrowed(local_v) => {
                 local_v.add_child(data_store, self.id, data).await;
@@ -182,7 +792,7 @@ impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> Vertex<T, V> {
             }
         }
     }
-    pub async fn remove_self(&self, data_store: &DataStore<T, V>) {
+    pub async fn remove_self(&self, data_store: &DataStore<T, V, C, P>) {
         match &self.v_type {
             VertexType::Local(local_v) | VertexType::Borrowed(local_v) => {
                 local_v.remove_self(data_store, self.id).await;
@@ -195,28 +805,89 @@ impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> Vertex<T, V> {
     }
 }
 
+/*
+   Why LocalVertex::set_data() refused a write, the vertex it was refused for is up to the caller
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaseError {
+    LeasedOut, // the vertex is lent out, see LocalVertex::lend()
+    ReadOnly,  // the vertex is borrowed under a read lease, see LocalVertex::lend_read()
+}
+
+impl LeaseError {
+    // attributing the refusal to the vertex `v_id`
+    pub fn for_vertex(self, v_id: VertexID) -> FusionError {
+        match self {
+            LeaseError::LeasedOut => FusionError::LeasedOut(v_id),
+            LeaseError::ReadOnly => FusionError::ReadOnly(v_id),
+        }
+    }
+}
+
+impl fmt::Display for LeaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LeaseError::LeasedOut => write!(f, "vertex is leased out"),
+            LeaseError::ReadOnly => write!(f, "vertex is only borrowed for reading"),
+        }
+    }
+}
+
+impl std::error::Error for LeaseError {}
+
+// (borrower, vertex, lease) of a read lease revoked by a write, for the owner's worker to notify the borrower
+pub(crate) type Revocation = (MachineID, VertexID, Uuid);
+
+// a read lease handed out by LocalVertex::lend_read()
+struct ReadLease {
+    v_id: VertexID,
+    borrower: MachineID,
+    until: Instant,
+    revocations: mpsc::UnboundedSender<Revocation>,
+}
+
 /*
    Vertex that resides locally, or borrowed to be temporarily locally
 */
-pub struct LocalVertex<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> {
-    incoming_edges: HashSet<VertexID>, // for simulating trees, or DAGs
-    outgoing_edges: HashSet<VertexID>, // for simulating trees, or DAGs
-    edges: HashSet<VertexID>,          // for simulating general graphs
+pub struct LocalVertex<T, V> {
+    adjacency: std::sync::RwLock<Adjacency>, // edges, changed in place while the vertex is in the graph
     data: Arc<MyUnsafeCell<Option<Data<T>>>>, // Using option to return the previous value (for error checking, etc.)
     borrowed_in: bool,                        // When a node is a borrowed node
-    leased_out: bool,                         // When the current node is lent out
+    read_only: bool,                          // When borrowed under a read lease
+    lease: std::sync::Mutex<Option<(Uuid, Instant)>>, // When the current node is lent out: the lease and until when
+    read_leases: std::sync::Mutex<HashMap<Uuid, ReadLease>>, // the read leases handed out, see lend_read()
     vertex_lock: Mutex<VertexAccessor>,
     vertex_lock_cv: Condvar,
+    version: AtomicU64,                       // bumped on every data write
+    history: Mutex<VecDeque<(u64, Vec<u8>)>>, // (version, serialized data) of the latest past versions
     _marker: PhantomData<V>,
 }
 
-impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> Debug for LocalVertex<T, V> {
+/*
+   Edges of a LocalVertex
+
+   Every set is shared with the callers of children(), parents() and edges() it was handed to, and copied
+   when written to while one of them still holds it, so they keep seeing the edges as they were
+*/
+#[derive(Default)]
+struct Adjacency {
+    incoming_edges: Arc<HashSet<VertexID>>, // for simulating trees, or DAGs
+    outgoing_edges: Arc<HashSet<VertexID>>, // for simulating trees, or DAGs
+    edges: Arc<HashSet<VertexID>>,          // for simulating general graphs
+}
+
+impl<T: Debug, V> Debug for LocalVertex<T, V> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let adjacency = self.adjacency.read().unwrap();
         f.debug_struct("LocalVertex")
-            .field("incoming_edges", &self.incoming_edges)
-            .field("outgoing_edges", &self.outgoing_edges)
-            .field("edges", &self.edges)
+            .field("incoming_edges", &adjacency.incoming_edges)
+            .field("outgoing_edges", &adjacency.outgoing_edges)
+            .field("edges", &adjacency.edges)
             .field("data", unsafe { &*self.data.0.get() })
+            .field("borrowed_in", &self.borrowed_in)
+            .field("read_only", &self.read_only)
+            .field("lease", &self.lease)
+            .field("read_leases", &self.read_leases.lock().unwrap().len())
             .field("accessor", &self.vertex_lock)
             .finish()
     }
@@ -233,14 +904,20 @@ impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> LocalVertex<T,
         data: Option<Data<T>>,
     ) -> Self {
         LocalVertex {
-            incoming_edges: incoming,
-            outgoing_edges: outgoing,
-            edges,
+            adjacency: std::sync::RwLock::new(Adjacency {
+                incoming_edges: Arc::new(incoming),
+                outgoing_edges: Arc::new(outgoing),
+                edges: Arc::new(edges),
+            }),
             data: Arc::new(MyUnsafeCell::new(data)),
             borrowed_in: false,
-            leased_out: false,
+            read_only: false,
+            lease: std::sync::Mutex::new(None),
+            read_leases: std::sync::Mutex::new(HashMap::new()),
             vertex_lock: Mutex::new(VertexAccessor::default()),
             vertex_lock_cv: Condvar::new(),
+            version: AtomicU64::new(0),
+            history: Mutex::new(VecDeque::new()),
             _marker: PhantomData,
         }
     }
@@ -262,26 +939,84 @@ impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> LocalVertex<T,
         )
     }
 
-    // getters and setters
-    pub fn children(&self) -> &HashSet<VertexID> {
-        &self.outgoing_edges
+    /*
+       Creator for undirected graphs, every neighbor being both a parent and a child, so that UDFs walking
+       children() or parents() see the same neighbors as the ones walking edges()
+    */
+    pub fn create_undirected(neighbors: &[VertexID], data: Data<T>) -> Self {
+        LocalVertex::create_vertex(neighbors, neighbors, data)
+    }
+
+    /*
+       Adding an edge to `neighbor`, as a child, a parent, or both with Neighbors::Edges, keeping
+       edges() the union of children() and parents()
+
+       Returns whether the edge is new
+    */
+    pub fn add_edge(&self, neighbor: VertexID, which: Neighbors) -> bool {
+        let adjacency = &mut *self.adjacency.write().unwrap();
+        Arc::make_mut(&mut adjacency.edges).insert(neighbor);
+        let child = matches!(which, Neighbors::Children | Neighbors::Edges)
+            && Arc::make_mut(&mut adjacency.outgoing_edges).insert(neighbor);
+        let parent = matches!(which, Neighbors::Parents | Neighbors::Edges)
+            && Arc::make_mut(&mut adjacency.incoming_edges).insert(neighbor);
+        child || parent
+    }
+
+    /*
+       Removing the edge to `neighbor` as a child, a parent, or both with Neighbors::Edges, it stays in edges()
+       as long as it is still one of the two
+
+       Returns whether there was such an edge
+    */
+    pub fn remove_edge(&self, neighbor: VertexID, which: Neighbors) -> bool {
+        let adjacency = &mut *self.adjacency.write().unwrap();
+        let child = matches!(which, Neighbors::Children | Neighbors::Edges)
+            && adjacency.outgoing_edges.contains(&neighbor)
+            && Arc::make_mut(&mut adjacency.outgoing_edges).remove(&neighbor);
+        let parent = matches!(which, Neighbors::Parents | Neighbors::Edges)
+            && adjacency.incoming_edges.contains(&neighbor)
+            && Arc::make_mut(&mut adjacency.incoming_edges).remove(&neighbor);
+        if !adjacency.outgoing_edges.contains(&neighbor)
+            && !adjacency.incoming_edges.contains(&neighbor)
+            && adjacency.edges.contains(&neighbor)
+        {
+            Arc::make_mut(&mut adjacency.edges).remove(&neighbor);
+        }
+        child || parent
+    }
+
+    // getters and setters, the sets as of the call (see Adjacency)
+    pub fn children(&self) -> Arc<HashSet<VertexID>> {
+        self.adjacency.read().unwrap().outgoing_edges.clone()
+    }
+    pub fn parents(&self) -> Arc<HashSet<VertexID>> {
+        self.adjacency.read().unwrap().incoming_edges.clone()
     }
-    pub fn children_mut(&mut self) -> &mut HashSet<VertexID> {
-        &mut self.outgoing_edges
+    pub fn edges(&self) -> Arc<HashSet<VertexID>> {
+        self.adjacency.read().unwrap().edges.clone()
     }
-    pub fn parents(&self) -> &HashSet<VertexID> {
-        &self.incoming_edges
+    // an expired lease no longer counts, see lend()
+    pub fn is_leased_out(&self) -> bool {
+        self.lease
+            .lock()
+            .unwrap()
+            .is_some_and(|(_, until)| Instant::now() < until)
     }
-    pub fn parents_mut(&mut self) -> &mut HashSet<VertexID> {
-        &mut self.incoming_edges
+    pub fn is_borrowed_in(&self) -> bool {
+        self.borrowed_in
     }
-    pub fn edges(&self) -> &HashSet<VertexID> {
-        &self.edges
+    pub fn neighbors(&self, which: Neighbors) -> Arc<HashSet<VertexID>> {
+        match which {
+            Neighbors::Children => self.children(),
+            Neighbors::Parents => self.parents(),
+            Neighbors::Edges => self.edges(),
+        }
     }
-    pub fn edges_mut(&mut self) -> &mut HashSet<VertexID> {
-        &mut self.edges
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
     }
-    pub async fn get_data(&self) -> SafeDataReference<T, V> {
+    pub async fn get_data(&self) -> SafeDataReference<'_, '_, T, V> {
         // this basically means that no one is writing
         let mut accessor = self.vertex_lock.lock().await;
 
@@ -298,9 +1033,13 @@ impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> LocalVertex<T,
             parent: Some(self),
         }
     }
-    pub async fn set_data(&self, data: Data<T>) -> Option<Data<T>> {
-        if self.leased_out {
-            None
+    // the data it replaced, unless the write was refused
+    // (a write revokes the read leases handed out, see lend_read())
+    pub async fn set_data(&self, data: Data<T>) -> Result<Option<Data<T>>, LeaseError> {
+        if self.is_leased_out() {
+            Err(LeaseError::LeasedOut)
+        } else if self.read_only {
+            Err(LeaseError::ReadOnly)
         } else {
             let old_val;
 
@@ -329,60 +1068,258 @@ impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> LocalVertex<T,
             // Note: The CondVar is not "Cancellation Safe", yet CondVar would be the most appropriate construct here
 
             // now we have passed the filter
+            // the history lock is held so that versioned reads see the data and its version change together
+            let mut history = self.history.lock().await;
             unsafe {
                 old_val = self.data.get().replace(data);
             };
 
-            old_val
+            // retain the replaced value under the version it had
+            if history.len() == VERSION_HISTORY_LEN {
+                history.pop_front();
+            }
+            history.push_back((self.version(), bincode::serialize(&old_val).unwrap()));
+            self.version.fetch_add(1, Ordering::Release);
+            // once written, so that a copy lent out from now on has the new data
+            self.revoke_read_leases();
+
+            Ok(old_val)
         }
     }
 
-    pub async fn add_child(
+    /*
+       Lending the vertex out (see DataStore::borrow()), its data can't be written here until taken back
+
+       The lease lasts for `duration` unless renewed, after which the vertex is writable here again and the
+       borrower can't return or renew it anymore. Expiry is checked whenever the lease is looked at, so a
+       borrower that crashed holds the vertex no longer than that.
+       The borrower may write, so the read leases handed out are revoked as for a write here.
+    */
+    pub async fn lend(
         &self,
-        mut data_store: Arc<DataStore<T, V>>,
         self_id: VertexID,
-        data: Data<T>,
-    ) {
-        // this is sound, due to the single-threaded behavior
-        // Discussion: https://github.com/rust-lang/rust/issues/63292
-        let data_store = unsafe { Arc::get_mut_unchecked(&mut data_store) };
-        let new_id = data_store.next_id.fetch_add(1, Ordering::Relaxed);
-        data_store.add_vertex(
-            new_id,
-            Vertex {
-                id: new_id,
-                v_type: VertexType::Local(LocalVertex {
-                    incoming_edges: HashSet::from([self_id]),
-                    outgoing_edges: Default::default(),
-                    edges: HashSet::from([self_id]),
-                    data: Arc::new(MyUnsafeCell::new(Some(data))),
-                    borrowed_in: false,
-                    leased_out: false,
-                    vertex_lock: Default::default(),
-                    vertex_lock_cv: Default::default(),
-                    _marker: Default::default(),
-                }),
-            },
-        );
-        let temp = data_store.map.get_mut(&self_id).unwrap();
-        if let VertexType::Local(v) = &mut temp.v_type {
-            v.edges_mut().insert(new_id);
-            v.children_mut().insert(new_id);
+        duration: Duration,
+    ) -> Result<LentVertex<T>, FusionError> {
+        if self.borrowed_in {
+            return Err(FusionError::NotOwned(self_id));
+        }
+        let lease = Uuid::new_v4();
+        {
+            let mut current = self.lease.lock().unwrap();
+            if current.is_some_and(|(_, until)| Instant::now() < until) {
+                return Err(FusionError::LeasedOut(self_id));
+            }
+            *current = Some((lease, Instant::now() + duration));
+        }
+        self.revoke_read_leases();
+        self.lent(self_id, lease).await
+    }
+
+    /*
+       Lending a read-only copy of the vertex out to `borrower` (see DataStore::borrow_read())
+
+       Read leases are shared and don't hold back writes here: the first write revokes them all, each borrower
+       being notified through `revocations`. Refused while the vertex is lent out exclusively.
+    */
+    pub async fn lend_read(
+        &self,
+        self_id: VertexID,
+        borrower: MachineID,
+        duration: Duration,
+        revocations: &mpsc::UnboundedSender<Revocation>,
+    ) -> Result<LentVertex<T>, FusionError> {
+        if self.borrowed_in {
+            return Err(FusionError::NotOwned(self_id));
+        }
+        if self.is_leased_out() {
+            return Err(FusionError::LeasedOut(self_id));
+        }
+        let lease = Uuid::new_v4();
+        {
+            let mut read_leases = self.read_leases.lock().unwrap();
+            read_leases.retain(|_, read_lease| Instant::now() < read_lease.until);
+            read_leases.insert(
+                lease,
+                ReadLease {
+                    v_id: self_id,
+                    borrower,
+                    until: Instant::now() + duration,
+                    revocations: revocations.clone(),
+                },
+            );
+        }
+        self.lent(self_id, lease).await
+    }
+
+    // the copy handed out under `lease`
+    async fn lent(&self, self_id: VertexID, lease: Uuid) -> Result<LentVertex<T>, FusionError> {
+        Ok(LentVertex {
+            lease,
+            incoming: self.parents().as_ref().clone(),
+            outgoing: self.children().as_ref().clone(),
+            edges: self.edges().as_ref().clone(),
+            data: self.get_data_at(self_id, None).await?,
+        })
+    }
+
+    // notifying the borrowers of every read lease still running that the vertex changed, see lend_read()
+    fn revoke_read_leases(&self) {
+        let mut read_leases = self.read_leases.lock().unwrap();
+        for (lease, read_lease) in read_leases.drain() {
+            if Instant::now() < read_lease.until {
+                // only fails once the worker is gone, and the borrower with it
+                let _ = read_lease
+                    .revocations
+                    .send((read_lease.borrower, read_lease.v_id, lease));
+            }
+        }
+    }
+
+    // extending the lease by another `duration` from now, if it hasn't expired yet
+    pub fn renew(
+        &self,
+        self_id: VertexID,
+        lease: Uuid,
+        duration: Duration,
+    ) -> Result<(), FusionError> {
+        let mut current = self.lease.lock().unwrap();
+        match &mut *current {
+            Some((id, until)) if *id == lease && Instant::now() < *until => {
+                *until = Instant::now() + duration;
+                return Ok(());
+            }
+            _ => {}
+        }
+        match self.read_leases.lock().unwrap().get_mut(&lease) {
+            Some(read_lease) if Instant::now() < read_lease.until => {
+                read_lease.until = Instant::now() + duration;
+                Ok(())
+            }
+            // a revoked read lease is gone
+            _ => Err(FusionError::LeaseExpired(self_id)),
+        }
+    }
+
+    // taking the vertex back from its borrower, with the data written while borrowed if any
+    pub async fn take_back(
+        &self,
+        self_id: VertexID,
+        lease: Uuid,
+        data: Option<Data<T>>,
+    ) -> Result<(), FusionError> {
+        {
+            let mut current = self.lease.lock().unwrap();
+            match *current {
+                Some((id, until)) if id == lease && Instant::now() < until => *current = None,
+                _ => {
+                    // a read lease comes back without data, and is only gone once revoked
+                    return match self.read_leases.lock().unwrap().remove(&lease) {
+                        Some(_) if data.is_some() => Err(FusionError::ReadOnly(self_id)),
+                        Some(read_lease) if Instant::now() < read_lease.until => Ok(()),
+                        // a late return, the vertex may have been written or lent out again since
+                        _ => Err(FusionError::LeaseExpired(self_id)),
+                    };
+                }
+            }
+        }
+        if let Some(data) = data {
+            self.set_data(data)
+                .await
+                .map_err(|e| e.for_vertex(self_id))?;
+        }
+        Ok(())
+    }
+
+    // the copy of a vertex lent out to this machine
+    fn borrowed(lent: LentVertex<T>) -> Self {
+        LocalVertex::new(lent.incoming, lent.outgoing, lent.edges, lent.data).into_borrowed()
+    }
+
+    // marking the vertex as borrowed in, so that it isn't lent out again from here
+    pub(crate) fn into_borrowed(mut self) -> Self {
+        self.borrowed_in = true;
+        self
+    }
+
+    // the read-only copy of a vertex lent out to this machine under a read lease
+    fn borrowed_read(lent: LentVertex<T>) -> Self {
+        let mut borrowed = LocalVertex::borrowed(lent);
+        borrowed.read_only = true;
+        borrowed
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /*
+       Point-in-time read: the data as of `version`, or the current data if None
+    */
+    pub async fn get_data_at(
+        &self,
+        self_id: VertexID,
+        version: Option<u64>,
+    ) -> Result<Option<Data<T>>, FusionError> {
+        // holding the history keeps writers from replacing the data in between (see set_data())
+        let history = self.history.lock().await;
+        let current = self.version();
+        let version = version.unwrap_or(current);
+
+        if version == current {
+            // owned copy of the current data, without requiring T: Clone
+            let bytes = bincode::serialize(unsafe { &*self.data.0.get() }).unwrap();
+            return Ok(bincode::deserialize(&bytes).unwrap());
         }
+        if version > current {
+            return Err(FusionError::UnknownVersion(self_id, version));
+        }
+        match history.iter().find(|(v, _)| *v == version) {
+            Some((_, bytes)) => Ok(bincode::deserialize(bytes).unwrap()),
+            None => Err(FusionError::VersionEvicted(self_id, version)),
+        }
+    }
+
+    pub async fn add_child<C: Codec, P: Codec>(
+        &self,
+        data_store: Arc<DataStore<T, V, C, P>>,
+        self_id: VertexID,
+        data: Data<T>,
+    ) {
+        let new_id = data_store.next_vertex_id();
+        let child = LocalVertex::new(
+            HashSet::from([self_id]),
+            HashSet::new(),
+            HashSet::from([self_id]),
+            Some(data),
+        );
+        // recording the edge on this vertex as well, see DataStore::insert_vertex()
+        data_store
+            .insert_vertex(
+                new_id,
+                Vertex {
+                    id: new_id,
+                    v_type: VertexType::Local(child),
+                },
+            )
+            .expect("a fresh vertex id is neither taken nor missing its parent");
     }
 
-    pub async fn remove_self(&self, data_store: &DataStore<T, V>, self_id: VertexID) {
+    pub async fn remove_self<C: Codec, P: Codec>(
+        &self,
+        _data_store: &DataStore<T, V, C, P>,
+        _self_id: VertexID,
+    ) {
         unimplemented!()
     }
 }
 
-pub struct RemoteVertex<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> {
+pub struct RemoteVertex<T, V, C = BincodeCodec, P = C> {
     location: MachineID,
-    worker: Arc<Worker<T, V>>,
+    worker: Arc<Worker<T, V, C, P>>,
     _marker: PhantomData<T>,
 }
 
-impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> Debug for RemoteVertex<T, V> {
+impl<T, V, C, P> Debug for RemoteVertex<T, V, C, P> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("RemoteVertex")
             .field("location", &self.location)
@@ -392,11 +1329,13 @@ impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> Debug for Remo
 /*
    Remote References to other vertices
 */
-impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> RemoteVertex<T, V> {
+impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug, C: Codec, P: Codec>
+    RemoteVertex<T, V, C, P>
+{
     /*
        Constructor
     */
-    pub fn new(location: MachineID, worker: Arc<Worker<T, V>>) -> Self {
+    pub fn new(location: MachineID, worker: Arc<Worker<T, V, C, P>>) -> Self {
         Self {
             location,
             worker,
@@ -404,117 +1343,561 @@ impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> RemoteVertex<T
         }
     }
 
+    // getter
+    pub fn location(&self) -> MachineID {
+        self.location
+    }
+
+    // dropping the result channel of a request that will not be waited on
+    async fn forget(&self, id: Uuid) {
+        self.worker.forget_result_channel(&id).await;
+    }
+
+    /*
+       Waiting on the result of the request `id`, at most `timeout`
+
+       The remote is told to stop if the wait is abandoned, whether it timed out or the caller was dropped.
+       However the wait ends, the request's result channel is deregistered, as it only ever carries one result
+    */
+    async fn await_result(
+        &self,
+        id: Uuid,
+        rx: &mut mpsc::Receiver<RPCResPayload<T, V>>,
+        timeout: Option<Duration>,
+    ) -> Result<RPCResPayload<T, V>, FusionError> {
+        let cancel_on_drop = self.worker.cancel_on_drop(self.location, id);
+        let res = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, rx.recv()).await {
+                Ok(res) => res,
+                Err(_) => {
+                    // the cancellation goes out as cancel_on_drop is dropped
+                    self.forget(id).await;
+                    return Err(FusionError::Timeout(timeout));
+                }
+            },
+            None => rx.recv().await,
+        };
+        cancel_on_drop.disarm();
+        self.forget(id).await;
+        // e.g. reaped as orphaned before the result came in
+        Ok(res.ok_or(RpcError::ChannelClosed)?)
+    }
+
     // TODO: should all of these be non-blocking? In the sense that within the udf they can proceed without waiting for a response?
 
     /*
-       RPC for execute
+       RPC for execute, `execute` being the command to send (RPC::Execute or RPC::ExecuteMut)
+
+       Attempted again under the worker's RetryPolicy when failing transiently, timeouts included only for
+       idempotent commands, since the remote may have executed one that timed out
     */
-    async fn remote_execute<U: Serialize + DeserializeOwned>(
+    #[tracing::instrument(
+        skip_all,
+        fields(request_id = field::Empty, vertex_id = vertex_id, machine_id = self.location)
+    )]
+    pub(crate) async fn remote_execute<U: Serialize + DeserializeOwned>(
         &self,
         vertex_id: VertexID,
         auxiliary_information: U,
-    ) -> V {
+        priority: u8,
+        timeout: Option<Duration>,
+        execute: fn(Uuid, VertexID, usize) -> RPC,
+        idempotent: bool,
+    ) -> Result<V, FusionError> {
         // The remote machine executes the function and returns the result.
 
+        // Step 0: Stop targeting machines that are shutting down
+        self.worker
+            .check_health(self.location)
+            .await
+            .map_err(|e| e.at(vertex_id, self.location))?;
+
+        // Step 1: Construct the aux_info byte array and the forwarding path, shared by all attempts
+        let aux_info = P::encode(&auxiliary_information)
+            .map_err(|e| FusionError::from(RpcError::Codec(e)).at(vertex_id, self.location))?;
+        let forwarded = forwarded_path(
+            vertex_id,
+            self.worker.machine_id,
+            self.worker.max_forward_hops,
+        )
+        .map_err(|e| e.at(vertex_id, self.worker.machine_id))?;
+
+        // Step 2: Send the request and wait on its result, as often as the retry policy allows
+        let policy = self.worker.retry_policy;
+        let mut attempt = 1;
+        let rpc_result = loop {
+            match self
+                .attempt_execute(vertex_id, &aux_info, &forwarded, priority, timeout, execute)
+                .await
+            {
+                Ok(rpc_result) => break rpc_result,
+                Err((e, transient))
+                    if attempt < policy.max_attempts
+                        && (transient || idempotent && matches!(e, FusionError::Timeout(_))) =>
+                {
+                    debug!(error = %e, attempt, "execute failed transiently, retrying");
+                    tokio::time::sleep(policy.delay(attempt)).await;
+                    attempt += 1;
+                }
+                Err((e, _)) => return Err(e.at(vertex_id, self.location)),
+            }
+        };
+        debug!("got result");
+        match rpc_result {
+            RPCResPayload::ExecuteResPayload(res, used) => {
+                charge_report(&used)?;
+                Ok(res)
+            }
+            RPCResPayload::ErrorResPayload(e) => Err(e.at(vertex_id, self.location)),
+            other => {
+                panic!(
+                    "received other rpc payload than execute response: {:?}",
+                    other
+                )
+            }
+        }
+    }
+
+    /*
+       One attempt of remote_execute(), as a new request with a fresh id
+
+       Fails with whether the request failed to be sent transiently (see RpcError::is_transient()), the
+       channel of the request being torn down either way
+    */
+    async fn attempt_execute(
+        &self,
+        vertex_id: VertexID,
+        aux_info: &[u8],
+        forwarded: &[MachineID],
+        priority: u8,
+        timeout: Option<Duration>,
+        execute: fn(Uuid, VertexID, usize) -> RPC,
+    ) -> Result<RPCResPayload<T, V>, (FusionError, bool)> {
+        // Step 0: Wait for a slot among the requests in flight towards the machine, until the result is in
+        let _in_flight = self.worker.in_flight_permit(self.location).await;
+
         // Step 1: Construct channels and id
-        let (tx, mut rx) = mpsc::channel::<RPCResPayload<_, V>>(1000);
+        let (tx, mut rx) = mpsc::channel::<RPCResPayload<_, V>>(self.worker.result_buffer);
         let id = Uuid::new_v4();
+        Span::current().record("request_id", field::display(id));
 
         // Step 2: Add id to the worker's (id -> sending channel) mapping
+        //      (released right away, before the request is queued)
         self.worker
-            .result_multiplexing_channels
-            .write()
+            .register_result_channel(self.location, id, tx)
+            .await;
+
+        // Steps 3 and 4, as long as nothing went out the request can just be forgotten on failure
+        let mut transient = false;
+        let sent: Result<(), FusionError> = async {
+            // Step 3: Construct the rpc command with the trailing data len
+            //      (caller context + priority byte + aux_info, compressed under Worker::compression)
+            let caller = caller_context::<C>(Some((vertex_id, forwarded.to_vec())));
+            let trailing = [&caller[..], &[priority], aux_info].concat();
+            let bytes = self
+                .worker
+                .frame_request(|len| execute(id, vertex_id, len), trailing)?;
+            charge_request(self.location, bytes.len())?;
+
+            // Step 4: Send the RPC Command, caller context, priority and auxiliary information
+            //      (through the outbound queue, so that more urgent requests go out first)
+            let len = bytes.len();
+            self.worker
+                .send_prioritized(self.location, priority, bytes)
+                .await
+                .inspect_err(|e| transient = e.is_transient())?;
+            self.worker.record_execute_sent(self.location, len);
+            Ok(())
+        }
+        .await;
+        if let Err(e) = sent {
+            debug!(error = %e, "failed to send execute");
+            self.forget(id).await;
+            return Err((e, transient));
+        }
+        debug!("sent execute, waiting on result");
+
+        // Step 5: Wait on the receiver and return result
+        let sent_at = Instant::now();
+        self.await_result(id, &mut rx, timeout.or(self.worker.remote_timeout))
             .await
-            .insert(id, Mutex::new(tx));
+            .inspect(|_| {
+                self.worker
+                    .record_execute_answered(self.location, sent_at.elapsed())
+            })
+            .map_err(|e| (e, false))
+    }
 
-        // Step 3: get lock on the sending stream so that all messages are sent in order, as expected
-        //      (using the same rpc stream, send command and the data if necessary)
-        let rpc_sending_streams = self.worker.rpc_sending_streams.read().await;
-        let mut rpc_sending_stream = rpc_sending_streams
-            .get(&self.location)
-            .unwrap()
-            .lock()
+    /*
+       RPC for execute, sending the auxiliary information as a delta against base
+    */
+    #[tracing::instrument(
+        skip_all,
+        fields(request_id = field::Empty, vertex_id = vertex_id, machine_id = self.location)
+    )]
+    async fn remote_execute_delta<U: Serialize + DeserializeOwned + AuxDelta>(
+        &self,
+        vertex_id: VertexID,
+        auxiliary_information: U,
+        base: &AuxBase<U>,
+        priority: u8,
+    ) -> Result<V, FusionError> {
+        // Step 0: Stop targeting machines that are shutting down
+        self.worker
+            .check_health(self.location)
+            .await
+            .map_err(|e| e.at(vertex_id, self.location))?;
+        let forwarded = forwarded_path(
+            vertex_id,
+            self.worker.machine_id,
+            self.worker.max_forward_hops,
+        )
+        .map_err(|e| e.at(vertex_id, self.worker.machine_id))?;
+        // (and wait for a slot among the requests in flight towards it, until the result is in)
+        let _in_flight = self.worker.in_flight_permit(self.location).await;
+
+        // Step 1: Construct channels and id
+        let (tx, mut rx) = mpsc::channel::<RPCResPayload<_, V>>(self.worker.result_buffer);
+        let id = Uuid::new_v4();
+        Span::current().record("request_id", field::display(id));
+
+        // Step 2: Add id to the worker's (id -> sending channel) mapping
+        //      (released right away, before the stream locks below)
+        self.worker
+            .register_result_channel(self.location, id, tx)
             .await;
 
-        // Step 4: Construct the aux_info byte array, the rpc command with aux_info len
-        let aux_info = bincode::serialize(&auxiliary_information).unwrap();
-        let aux_info_len = aux_info.len();
-        let command = bincode::serialize(&RPC::Execute(id, vertex_id, aux_info_len)).unwrap();
+        // Steps 3 to 6, a failure forgets the request, and leaves the base to be shipped by the next one
+        let sent: Result<(), FusionError> = async {
+            // Step 3: Encode the base and the delta, before taking the stream
+            let base_bytes = P::encode(&base.aux).map_err(RpcError::Codec)?;
+            let delta =
+                P::encode(&auxiliary_information.into_delta(&base.aux)).map_err(RpcError::Codec)?;
 
-        // Step 5: Send the RPC Command and auxiliary information
-        rpc_sending_stream
-            .write_all(&[command, aux_info].concat())
-            .await
-            .unwrap();
+            // Step 4: get lock on the sending stream so that all messages are sent in order, as expected
+            //      (the base has to be sent before any delta against it on this stream, so whether this
+            //      request ships it is only decided while holding the lock)
+            let rpc_sending_streams = self.worker.rpc_sending_streams.read().await;
+            let mut rpc_sending_stream = rpc_sending_streams
+                .get(&self.location)
+                .ok_or(RpcError::UnknownMachine(self.location))?
+                .lock()
+                .await;
+            let first_use = !self
+                .worker
+                .shipped_aux_bases
+                .read()
+                .await
+                .contains(&(self.location, base.id));
+            let payload = AuxDeltaPayload {
+                base_id: base.id,
+                base: first_use.then_some(base_bytes),
+                delta,
+            };
+            // caller context and priority first, like Execute's
+            let payload = [
+                caller_context::<C>(Some((vertex_id, forwarded))),
+                vec![priority],
+                C::encode(&payload).map_err(RpcError::Codec)?,
+            ]
+            .concat();
+            let bytes = self
+                .worker
+                .frame_request(|len| RPC::ExecuteDelta(id, vertex_id, len), payload)?;
+            charge_request(self.location, bytes.len())?;
+
+            // Step 5: Send the RPC Command, the caller context and the payload
+            //      (the base only counts as shipped once it went out, still under the lock)
+            self.worker
+                .send_rpc(self.location, &mut rpc_sending_stream, &bytes)
+                .await?;
+            if first_use {
+                self.worker
+                    .shipped_aux_bases
+                    .write()
+                    .await
+                    .insert((self.location, base.id));
+            }
 
-        // Step 6: Drop the sender before waiting/blocking/yielding
-        drop(rpc_sending_stream);
-        drop(rpc_sending_streams);
+            // Step 6: Drop the sender before waiting/blocking/yielding
+            Ok(())
+        }
+        .await;
+        if let Err(e) = sent {
+            debug!(error = %e, "failed to send execute");
+            self.forget(id).await;
+            return Err(e.at(vertex_id, self.location));
+        }
+        debug!("sent execute, waiting on result");
 
         // Step 7: Wait on the receiver and return result
-        let rpc_result = rx.recv().await.unwrap();
-        return match rpc_result {
-            RPCResPayload::ExecuteResPayload(res) => res,
+        let rpc_result = self
+            .await_result(id, &mut rx, self.worker.remote_timeout)
+            .await
+            .map_err(|e| e.at(vertex_id, self.location))?;
+        debug!("got result");
+        match rpc_result {
+            RPCResPayload::ExecuteResPayload(res, used) => {
+                charge_report(&used)?;
+                Ok(res)
+            }
+            RPCResPayload::ErrorResPayload(e) => {
+                // the remote lost the base, so the next request against it ships it again
+                if let FusionError::MissingAuxBase(base_id) = e {
+                    self.worker
+                        .shipped_aux_bases
+                        .write()
+                        .await
+                        .remove(&(self.location, base_id));
+                }
+                Err(e.at(vertex_id, self.location))
+            }
             other => {
                 panic!(
                     "received other rpc payload than execute response: {:?}",
                     other
                 )
             }
-        };
+        }
     }
 
     /*
-       RPC for update
+       RPCs for the neighbor sets of the remote vertex, see Vertex::children_async()
     */
-    // Note: maybe refactor later for DRY principle
-    async fn remote_update(&self, data: Data<T>, v_id: VertexID) -> Option<Data<T>> {
-        // Step 1: Construct channels and id
-        let (tx, mut rx) = mpsc::channel::<RPCResPayload<T, _>>(1000);
-        let id = Uuid::new_v4();
+    pub async fn remote_children(&self, v_id: VertexID) -> Result<HashSet<VertexID>, FusionError> {
+        self.remote_neighbors(v_id, Neighbors::Children).await
+    }
+    pub async fn remote_parents(&self, v_id: VertexID) -> Result<HashSet<VertexID>, FusionError> {
+        self.remote_neighbors(v_id, Neighbors::Parents).await
+    }
+    pub async fn remote_edges(&self, v_id: VertexID) -> Result<HashSet<VertexID>, FusionError> {
+        self.remote_neighbors(v_id, Neighbors::Edges).await
+    }
+    /*
+       RPC executing the UDF on many vertices of this machine in one ExecuteBatch round trip, with the same auxiliary
+       information, results are returned in the order of `vertex_ids`
+    */
+    pub async fn remote_execute_batch<U: Serialize>(
+        &self,
+        vertex_ids: &[VertexID],
+        aux_info: U,
+    ) -> Result<Vec<V>, FusionError> {
+        let batch: Vec<_> = vertex_ids.iter().map(|v_id| (*v_id, &aux_info)).collect();
+        self.execute_batch(&batch).await
+    }
 
-        // Step 2: Add id to the worker's (id -> sending channel) mapping
-        self.worker
-            .result_multiplexing_channels
-            .write()
+    // shipping (vertex, auxiliary information) pairs in one RPC::ExecuteBatch, see DataStore::apply_function_batch()
+    pub(crate) async fn execute_batch<U: Serialize>(
+        &self,
+        batch: &[(VertexID, U)],
+    ) -> Result<Vec<V>, FusionError> {
+        let batch = [
+            caller_context::<C>(None),
+            P::encode(&batch).map_err(RpcError::Codec)?,
+        ]
+        .concat();
+        let res = self
+            .worker
+            .request(
+                self.location,
+                |id, len| RPC::ExecuteBatch(id, 0, len),
+                batch,
+            )
+            .await?;
+        match res {
+            RPCResPayload::ExecuteBatchResPayload(values, used) => {
+                charge_report(&used)?;
+                Ok(values)
+            }
+            RPCResPayload::ErrorResPayload(e) => Err(e),
+            other => {
+                panic!(
+                    "received other rpc payload than execute batch response: {:?}",
+                    other
+                )
+            }
+        }
+    }
+
+    async fn remote_neighbors(
+        &self,
+        v_id: VertexID,
+        which: Neighbors,
+    ) -> Result<HashSet<VertexID>, FusionError> {
+        let res = self
+            .worker
+            .request(
+                self.location,
+                |id, len| RPC::GetNeighbors(id, v_id, len),
+                C::encode(&which).unwrap(),
+            )
             .await
-            .insert(id, Mutex::new(tx));
+            .map_err(|e| e.at(v_id, self.location))?;
+        match res {
+            RPCResPayload::NeighborsResPayload(res) => res.map_err(|e| e.at(v_id, self.location)),
+            other => {
+                panic!(
+                    "received other rpc payload than neighbors response: {:?}",
+                    other
+                )
+            }
+        }
+    }
 
-        // Step 3: get lock on the sending stream so that all messages are sent in order, as expected
-        //      (using the same rpc stream, send command and the data if necessary)
-        let rpc_sending_streams = self.worker.rpc_sending_streams.read().await;
-        let mut rpc_sending_stream = rpc_sending_streams
-            .get(&self.location)
-            .unwrap()
-            .lock()
-            .await;
+    /*
+       RPC for borrowing the vertex, its owner lends it out under the returned lease until return_borrow()
+    */
+    pub async fn borrow(
+        &self,
+        v_id: VertexID,
+        mode: LeaseMode,
+    ) -> Result<(LocalVertex<T, V>, Uuid), FusionError> {
+        let res = self
+            .worker
+            .request(
+                self.location,
+                |id, len| RPC::Borrow(id, v_id, len),
+                C::encode(&mode).unwrap(),
+            )
+            .await
+            .map_err(|e| e.at(v_id, self.location))?;
+        match res {
+            RPCResPayload::BorrowResPayload(res) => res
+                .map(|lent| {
+                    let lease = lent.lease;
+                    match mode {
+                        LeaseMode::Exclusive => (LocalVertex::borrowed(lent), lease),
+                        LeaseMode::Read => (LocalVertex::borrowed_read(lent), lease),
+                    }
+                })
+                .map_err(|e| e.at(v_id, self.location)),
+            other => {
+                panic!(
+                    "received other rpc payload than borrow response: {:?}",
+                    other
+                )
+            }
+        }
+    }
 
-        // Step 4: Construct the data byte array, the rpc command with data len
-        let data_bytes = bincode::serialize(&data).unwrap();
-        let size = data_bytes.len();
-        let command = bincode::serialize(&RPC::Update(id, v_id, size)).unwrap();
+    /*
+       RPC for handing the borrowed copy of the vertex back to its owner
 
-        // Step 5: Send the RPC Command and auxiliary information
-        rpc_sending_stream
-            .write_all(&[command, data_bytes].concat())
+       Only its data goes back, and only if it was written while borrowed (a borrowed copy starts at version 0)
+       Note: changes to the edges of the borrowed copy are not sent back
+    */
+    pub async fn return_borrow(
+        &self,
+        v_id: VertexID,
+        lease: Uuid,
+        borrowed: &LocalVertex<T, V>,
+    ) -> Result<(), FusionError> {
+        let data = match borrowed.version() {
+            0 => None,
+            _ => borrowed.get_data_at(v_id, None).await?,
+        };
+        let data_bytes = P::encode(&(lease, data))
+            .map_err(|e| FusionError::from(RpcError::Codec(e)).at(v_id, self.location))?;
+        let res = self
+            .worker
+            .request(
+                self.location,
+                |id, len| RPC::ReturnBorrow(id, v_id, len),
+                data_bytes,
+            )
             .await
-            .unwrap();
+            .map_err(|e| e.at(v_id, self.location))?;
+        match res {
+            RPCResPayload::LeaseResPayload(res) => res.map_err(|e| e.at(v_id, self.location)),
+            other => {
+                panic!(
+                    "received other rpc payload than return borrow response: {:?}",
+                    other
+                )
+            }
+        }
+    }
 
-        // Step 6: Drop the sender before waiting/blocking/yielding
-        drop(rpc_sending_stream);
-        drop(rpc_sending_streams);
+    /*
+       RPC for keeping the vertex borrowed under `lease` for another lease duration
+    */
+    pub async fn renew_lease(&self, v_id: VertexID, lease: Uuid) -> Result<(), FusionError> {
+        let res = self
+            .worker
+            .request(
+                self.location,
+                |id, len| RPC::RenewLease(id, v_id, len),
+                C::encode(&lease).unwrap(),
+            )
+            .await
+            .map_err(|e| e.at(v_id, self.location))?;
+        match res {
+            RPCResPayload::LeaseResPayload(res) => res.map_err(|e| e.at(v_id, self.location)),
+            other => {
+                panic!(
+                    "received other rpc payload than lease response: {:?}",
+                    other
+                )
+            }
+        }
+    }
 
-        // Step 7: Wait on the receiver and return result
-        let rpc_result = rx.recv().await.unwrap();
-        return match rpc_result {
-            RPCResPayload::UpdateResPayload(res) => res,
+    /*
+       RPC for point-in-time reads
+    */
+    async fn remote_fetch_data(
+        &self,
+        v_id: VertexID,
+        version: Option<u64>,
+    ) -> Result<Option<Data<T>>, FusionError> {
+        let res = self
+            .worker
+            .request(
+                self.location,
+                |id, len| RPC::FetchData(id, v_id, len),
+                C::encode(&version).unwrap(),
+            )
+            .await
+            .map_err(|e| e.at(v_id, self.location))?;
+        match res {
+            RPCResPayload::FetchDataResPayload(res) => res.map_err(|e| e.at(v_id, self.location)),
+            other => {
+                panic!(
+                    "received other rpc payload than fetch data response: {:?}",
+                    other
+                )
+            }
+        }
+    }
+
+    /*
+       RPC for setting the data of a remote vertex, the previous data comes back like with LocalVertex::set_data()
+    */
+    async fn remote_set_data(
+        &self,
+        data: Data<T>,
+        v_id: VertexID,
+    ) -> Result<Option<Data<T>>, FusionError> {
+        let data_bytes = P::encode(&data)
+            .map_err(|e| FusionError::from(RpcError::Codec(e)).at(v_id, self.location))?;
+        let res = self
+            .worker
+            .request(
+                self.location,
+                |uuid, len| RPC::Update(uuid, v_id, len),
+                data_bytes,
+            )
+            .await
+            .map_err(|e| e.at(v_id, self.location))?;
+        match res {
+            RPCResPayload::UpdateResPayload(res) => res.map_err(|e| e.at(v_id, self.location)),
             other => {
                 panic!(
                     "received other rpc payload than update response: {:?}",
                     other
                 )
             }
-        };
+        }
     }
 }
 
@@ -524,7 +1907,7 @@ impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> RemoteVertex<T
 pub enum VertexKind {
     Local,
     Remote,
-    Borrowed,
+    Borrowed, // a copy owned elsewhere, see DataStore::borrow() for fetching one from its owner
 }
 
 /*
@@ -544,6 +1927,7 @@ impl<T> MyUnsafeCell<T> {
         MyUnsafeCell(UnsafeCell::new(data))
     }
 
+    #[allow(clippy::mut_from_ref)]
     unsafe fn get(&self) -> &mut T {
         &mut *self.0.get()
     }
@@ -562,7 +1946,7 @@ impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> Deref
 
     // Implement the dereference function
     fn deref(&self) -> &Self::Target {
-        &self.data.unwrap() // Return a reference to the inner value
+        self.data.unwrap() // Return a reference to the inner value
     }
 }
 
@@ -570,7 +1954,8 @@ impl<T: DeserializeOwned + Serialize + Debug + Default, V: Debug> Drop
     for SafeDataReference<'_, '_, T, V>
 {
     fn drop(&mut self) {
-        futures::executor::block_on(self.async_drop());
+        // outside of tokio's budget: past it, the lock would wake this task through the runtime blocked here
+        futures::executor::block_on(tokio::task::unconstrained(self.async_drop()));
     }
 }
 
@@ -624,3 +2009,389 @@ pub enum VertexState {
     Free,
     Read,
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::udf::{GraphMax, GraphReduce, Sum};
+    use crate::worker::tests::in_memory_pair;
+
+    // a machine of its own holding `vertices`, given as (id, children, data)
+    pub(crate) fn local_graph<V: Debug + Send + Sync + 'static>(
+        vertices: &[(VertexID, &[VertexID], Option<isize>)],
+    ) -> Arc<DataStore<isize, V>> {
+        let data_store = DataStore::new(Arc::new(Worker::new(1)));
+        for &(id, children, data) in vertices {
+            let parents: HashSet<VertexID> = vertices
+                .iter()
+                .filter(|(_, of, _)| of.contains(&id))
+                .map(|(parent, ..)| *parent)
+                .collect();
+            let children: HashSet<VertexID> = children.iter().copied().collect();
+            let edges = parents.union(&children).copied().collect();
+            let local_v = LocalVertex::new(parents, children, edges, data.map(Data));
+            data_store.add_vertex(
+                id,
+                Vertex {
+                    id,
+                    v_type: VertexType::Local(local_v),
+                },
+            );
+        }
+        Arc::new(data_store)
+    }
+
+    // (sequential, chunked, balanced) sums of the subtree under `id`, the last two folding the children into
+    // the value of the vertex itself
+    async fn sums(
+        data_store: &Arc<DataStore<isize, isize>>,
+        id: VertexID,
+    ) -> (isize, isize, isize) {
+        let vertex = data_store.get_vertex_by_id(&id);
+        let own = vertex
+            .get_val_async()
+            .await
+            .unwrap()
+            .map_or(0, |data| data.0);
+        let options = ExecOptions {
+            child_chunk_size: 1,
+            ..ExecOptions::default()
+        };
+        let sequential = vertex
+            .apply_function(&GraphReduce(Sum), data_store.clone(), None)
+            .await
+            .unwrap();
+        let chunked = vertex
+            .apply_function_to_children(
+                &GraphReduce(Sum),
+                data_store.clone(),
+                None,
+                options,
+                own,
+                |acc, sum| acc + sum,
+            )
+            .await
+            .unwrap();
+        let balanced = vertex
+            .apply_function_to_children_balanced(
+                &GraphReduce(Sum),
+                data_store.clone(),
+                None,
+                options,
+                own,
+                |left, right| left + right,
+            )
+            .await
+            .unwrap();
+        (sequential, chunked, balanced)
+    }
+
+    // neither Debug, Default nor the other half of serde, which Data itself doesn't need
+    #[derive(Serialize)]
+    struct SerializeOnly(u32);
+
+    #[derive(Deserialize)]
+    struct DeserializeOnly(u32);
+
+    #[test]
+    fn data_only_needs_the_serde_half_it_is_used_for() {
+        let bytes = bincode::serialize(&Data(SerializeOnly(7))).unwrap();
+        assert_eq!(bytes, bincode::serialize(&7u32).unwrap());
+
+        let Data(DeserializeOnly(value)) = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(value, 7);
+    }
+
+    #[tokio::test]
+    async fn a_single_vertex_folds_to_its_own_value() {
+        let data_store = local_graph(&[(0, &[], Some(7))]);
+        assert_eq!(sums(&data_store, 0).await, (7, 7, 7));
+
+        // without data, the identity of the operation
+        let data_store = local_graph(&[(0, &[], None)]);
+        assert_eq!(sums(&data_store, 0).await, (0, 0, 0));
+        let max = data_store
+            .get_vertex_by_id(&0)
+            .apply_function(&GraphMax::default(), data_store.clone(), None)
+            .await
+            .unwrap();
+        assert_eq!(max, isize::MIN);
+    }
+
+    #[tokio::test]
+    async fn leaves_fold_to_their_own_value_within_a_graph() {
+        //      0
+        //    / | \
+        //   1  2  3
+        //     / \
+        //    4   5
+        let data_store = local_graph(&[
+            (0, &[1, 2, 3], Some(1)),
+            (1, &[], Some(10)),
+            (2, &[4, 5], Some(100)),
+            (3, &[], None),
+            (4, &[], Some(1000)),
+            (5, &[], Some(10000)),
+        ]);
+
+        assert_eq!(sums(&data_store, 1).await, (10, 10, 10));
+        assert_eq!(sums(&data_store, 3).await, (0, 0, 0));
+        assert_eq!(sums(&data_store, 5).await, (10000, 10000, 10000));
+        assert_eq!(sums(&data_store, 2).await, (11100, 11100, 11100));
+        assert_eq!(sums(&data_store, 0).await, (11111, 11111, 11111));
+    }
+
+    // the child_chunk_size each vertex of the subtree ran under, its children recursed into by apply_function()
+    #[derive(Clone)]
+    struct ChunkSizes;
+
+    #[async_trait::async_trait]
+    impl crate::TryUserDefinedFunction<isize, Option<u64>, Vec<usize>> for ChunkSizes {
+        async fn try_execute(
+            &self,
+            vertex: &Vertex<isize, Vec<usize>>,
+            data_store: Arc<DataStore<isize, Vec<usize>>>,
+            aux_info: Option<u64>,
+        ) -> Result<Vec<usize>, FusionError> {
+            let mut seen = vec![current_options().child_chunk_size];
+            for child in vertex.children().iter() {
+                let child = data_store.try_get_vertex_by_id(child)?;
+                seen.extend(
+                    child
+                        .apply_function(self, data_store.clone(), aux_info)
+                        .await?,
+                );
+            }
+            Ok(seen)
+        }
+    }
+
+    #[tokio::test]
+    async fn the_options_of_an_invocation_carry_through_its_recursion() {
+        let data_store = local_graph::<Vec<usize>>(&[
+            (0, &[1, 2], None),
+            (1, &[3], None),
+            (2, &[], None),
+            (3, &[], None),
+        ]);
+        let root = data_store.get_vertex_by_id(&0);
+
+        let options = ExecOptions {
+            child_chunk_size: 3,
+            ..ExecOptions::default()
+        };
+        let seen = root
+            .try_apply_function(&ChunkSizes, data_store.clone(), None, options)
+            .await
+            .unwrap();
+        assert_eq!(seen, vec![3; 4]);
+
+        // a new invocation starts from the defaults
+        let seen = root
+            .apply_function(&ChunkSizes, data_store.clone(), None)
+            .await
+            .unwrap();
+        assert_eq!(seen, vec![DEFAULT_CHILD_CHUNK_SIZE; 4]);
+    }
+
+    // the ids of the subtree in the order they were executed in, recursing through apply_function_to_children()
+    #[derive(Clone)]
+    struct Visits(Arc<std::sync::Mutex<Vec<VertexID>>>);
+
+    #[async_trait::async_trait]
+    impl crate::TryUserDefinedFunction<isize, Option<u64>, ()> for Visits {
+        async fn try_execute(
+            &self,
+            vertex: &Vertex<isize, ()>,
+            data_store: Arc<DataStore<isize, ()>>,
+            aux_info: Option<u64>,
+        ) -> Result<(), FusionError> {
+            self.0.lock().unwrap().push(vertex.id);
+            vertex
+                .apply_function_to_children(
+                    self,
+                    data_store.clone(),
+                    aux_info,
+                    current_options(),
+                    (),
+                    |(), ()| (),
+                )
+                .await
+        }
+    }
+
+    #[derive(Debug)]
+    struct Descending;
+
+    impl Scheduler for Descending {
+        fn order(&self, ready: &mut [VertexID], _degree: &dyn Fn(VertexID) -> usize) {
+            ready.sort_by(|a, b| b.cmp(a));
+        }
+    }
+
+    #[tokio::test]
+    async fn the_scheduler_orders_the_children_of_every_vertex_of_the_recursion() {
+        //      0
+        //    / | \
+        //   1  2  3
+        //     / \
+        //    4   5
+        let data_store = local_graph::<()>(&[
+            (0, &[1, 2, 3], None),
+            (1, &[], None),
+            (2, &[4, 5], None),
+            (3, &[], None),
+            (4, &[], None),
+            (5, &[], None),
+        ]);
+        let options = ExecOptions {
+            child_chunk_size: 1,
+            scheduler: &Descending,
+            ..ExecOptions::default()
+        };
+        let visits = Visits(Arc::default());
+        data_store
+            .get_vertex_by_id(&0)
+            .try_apply_function(&visits, data_store.clone(), None, options)
+            .await
+            .unwrap();
+        // a chunk of one runs each subtree to completion before the next
+        assert_eq!(*visits.0.lock().unwrap(), vec![0, 3, 2, 5, 4, 1]);
+    }
+
+    #[tokio::test]
+    async fn remote_update_returns_the_replaced_data() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                // vertex 1 lives on machine 2, machine 1 only holds a reference to it
+                let (data_store_1, data_store_2) =
+                    in_memory_pair(|data_store, machine_id, worker| {
+                        let (kind, data, location) = match machine_id {
+                            1 => (VertexKind::Remote, None, Some(2)),
+                            _ => (VertexKind::Local, Some(Data(10)), None),
+                        };
+                        data_store.add_new_vertex(1, &[], &[], data, kind, location, worker);
+                    })
+                    .await;
+
+                let remote = data_store_1.get_vertex_by_id(&1);
+                let replaced = remote.update(Data(20)).await.unwrap();
+                assert_eq!(replaced.map(|data| data.0), Some(10));
+                let replaced = remote.update(Data(30)).await.unwrap();
+                assert_eq!(replaced.map(|data| data.0), Some(20));
+
+                let owned = data_store_2.get_vertex_by_id(&1).get_val_async().await;
+                assert_eq!(owned.unwrap().map(|data| data.0), Some(30));
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn data_read_past_the_budget_is_released() {
+        let data_store = local_graph::<isize>(&[(0, &[], Some(1))]);
+        let vertex = data_store.get_vertex_by_id(&0);
+
+        // one budget unit spent elsewhere, so that a release rather than a read is the one running out of it
+        drop(tokio::sync::Mutex::new(()).lock().await);
+        for _ in 0..256 {
+            assert_eq!(vertex.get_val().await.as_ref().map(|data| data.0), Some(1));
+        }
+    }
+
+    // a vertex `root` of data 0 with `leaves` of data 1 as its children
+    pub(crate) fn add_star<V: Debug>(
+        data_store: &DataStore<isize, V>,
+        root: VertexID,
+        leaves: core::ops::Range<VertexID>,
+    ) {
+        let parents: HashSet<VertexID> = [root].into_iter().collect();
+        for leaf in leaves.clone() {
+            let local_v = LocalVertex::new(
+                parents.clone(),
+                HashSet::new(),
+                parents.clone(),
+                Some(Data(1)),
+            );
+            data_store.add_vertex(
+                leaf,
+                Vertex {
+                    id: leaf,
+                    v_type: VertexType::Local(local_v),
+                },
+            );
+        }
+        let children: HashSet<VertexID> = leaves.collect();
+        let local_v = LocalVertex::new(HashSet::new(), children.clone(), children, Some(Data(0)));
+        data_store.add_vertex(
+            root,
+            Vertex {
+                id: root,
+                v_type: VertexType::Local(local_v),
+            },
+        );
+    }
+
+    // (current, peak) resident kB of this process, the peak since the last reset_peak_rss()
+    fn rss_kb() -> (u64, u64) {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        let field = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .and_then(|kb| kb.trim().trim_end_matches("kB").trim().parse().ok())
+                .unwrap()
+        };
+        (field("VmRSS:"), field("VmHWM:"))
+    }
+
+    fn reset_peak_rss() {
+        std::fs::write("/proc/self/clear_refs", "5").unwrap();
+    }
+
+    /*
+       Peak memory of folding a million-child star through try_apply_function(), its children chunked by default
+       and all at once, run with
+       `cargo test --release folding_a_million_child_star -- --ignored --nocapture` (Linux only)
+    */
+    #[tokio::test]
+    #[ignore]
+    async fn folding_a_million_child_star() {
+        const FAN_OUT: VertexID = 1_000_000;
+        let data_store = DataStore::new(Arc::new(Worker::new(1)));
+        add_star(&data_store, 0, 1..FAN_OUT + 1);
+        let data_store = Arc::new(data_store);
+        let root = data_store.get_vertex_by_id(&0);
+
+        for (mode, child_chunk_size) in [
+            ("chunked", DEFAULT_CHILD_CHUNK_SIZE),
+            ("unchunked", FAN_OUT as usize),
+        ] {
+            let options = ExecOptions {
+                child_chunk_size,
+                ..ExecOptions::default()
+            };
+            reset_peak_rss();
+            let (before, _) = rss_kb();
+            let start = Instant::now();
+            // outside of tokio's budget, under which only so many of the children done at once complete per
+            // poll of the fold, making it quadratic in the chunk size
+            let sum = tokio::task::unconstrained(root.try_apply_function(
+                &GraphReduce(Sum),
+                data_store.clone(),
+                None,
+                options,
+            ))
+            .await
+            .unwrap();
+            let elapsed = start.elapsed();
+            let (_, peak) = rss_kb();
+            assert_eq!(sum, FAN_OUT as isize);
+            println!(
+                "{mode} ({child_chunk_size} at once): {elapsed:?}, peak {} MiB above the {} MiB of the graph",
+                (peak - before) / 1024,
+                before / 1024,
+            );
+        }
+    }
+}
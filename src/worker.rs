@@ -7,40 +7,2709 @@
    Major Revision: 1/30/2024
 */
 
+extern crate alloc;
+use alloc::collections::BinaryHeap;
+use alloc::rc::Rc;
+use alloc::sync::Arc;
+use core::cell::{Cell, RefCell};
 use core::fmt::Debug;
+use core::future::Future;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Instant;
 
-use crate::rpc::RPCResPayload;
+use crate::auth::{sign, verify, TAG_LEN};
+use crate::codec::{
+    compress, decompress, frame, read_framed, BincodeCodec, Codec, CodecError, MAX_DECOMPRESSED_LEN,
+};
+use crate::datastore::DataStore;
+use crate::error::FusionError;
+use crate::rpc::{
+    read_command, read_trailing, AuxDeltaPayload, CallerContext, LeaseMode, Neighbors,
+    RPCResPayload, RPCResponseHeader, ResType, RpcError, MAX_HEADER_LEN, RPC,
+};
 use crate::vertex::*;
+use crate::{AuxDelta, MutableUserDefinedFunction, UserDefinedFunction};
 
-use hashbrown::HashMap;
+use futures::future::{
+    join_all, select_all, try_join_all, AbortHandle, Abortable, FutureExt, LocalBoxFuture,
+};
+use hashbrown::{HashMap, HashSet};
 use serde::{de::DeserializeOwned, Serialize};
-use tokio::net::TcpStream;
-use tokio::sync::mpsc::Sender;
-use tokio::sync::{Mutex, RwLock};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter, DuplexStream};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, Sender, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{oneshot, Mutex, Notify, OwnedSemaphorePermit, RwLock, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
 use uuid::Uuid;
 
+/*
+    Health of a remote machine, as last learned from it
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MachineHealth {
+    #[default]
+    Healthy,
+    Draining, // announced its shutdown, only in-flight results are still expected from it
+}
+
+/*
+    What the remote executes issued towards one machine amounted to, see Worker::metrics_snapshot()
+*/
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MachineRpcMetrics {
+    pub executes: u64, // Execute and ExecuteMut requests written to the rpc stream, retries included
+    pub bytes_sent: u64, // what those requests took on the stream
+    pub answered: u64, // of those requests, the ones a result came back for
+    pub total_latency: Duration, // from written to result received, over the answered requests
+    pub max_latency: Duration,
+}
+
+impl MachineRpcMetrics {
+    pub fn mean_latency(&self) -> Option<Duration> {
+        (self.answered > 0).then(|| self.total_latency.div_f64(self.answered as f64))
+    }
+}
+
+/*
+    Result channel of a pending request, with when it was registered to spot the ones never answered
+    and the machine answering it, whose channels are dropped once its data stream closes
+*/
+pub struct ResultChannel<T, V> {
+    pub sender: Mutex<Sender<RPCResPayload<T, V>>>,
+    pub registered_at: Instant,
+    pub location: MachineID,
+}
+
+impl<T, V> ResultChannel<T, V> {
+    pub fn new(location: MachineID, sender: Sender<RPCResPayload<T, V>>) -> Self {
+        ResultChannel {
+            sender: Mutex::new(sender),
+            registered_at: Instant::now(),
+            location,
+        }
+    }
+}
+
+/*
+    When commands written to an rpc stream are pushed out to the machine
+
+    Note: a request is only answered once its command is flushed, under EveryN the caller waits until enough
+          other commands are buffered or someone flushes
+*/
+#[derive(Debug, Clone, Copy, Default)]
+pub enum FlushPolicy {
+    #[default]
+    Immediate, // after every command
+    EveryN(usize), // once this many commands are buffered, or on Worker::flush()
+    EveryDuration(Duration), // by the flusher task (Worker::spawn_flusher()), or on Worker::flush()
+}
+
+/*
+    How often a remote execute is attempted again after failing transiently (see RpcError::is_transient()),
+    waiting base_delay * backoff^(n - 1) before the n-th retry
+
+    Note: every attempt is a new request, with its own id, so a late result of an abandoned attempt finds no
+          channel to be delivered to
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32, // including the first one, 1 never retries
+    pub base_delay: Duration,
+    pub backoff: u32, // factor the delay grows by with each retry
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            base_delay: Duration::from_millis(50),
+            backoff: 2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // how long to wait before the attempt following `attempt`, counting from 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(self.backoff.saturating_pow(attempt.saturating_sub(1)))
+    }
+}
+
+/*
+    Byte stream the rpc commands and results towards another machine are written to
+
+    A TcpStream between machines, or one end of a tokio::io::duplex() between workers in the same process
+    (see Worker::connect_in_memory())
+*/
+pub trait Transport: AsyncWrite + Unpin + Send + Sync {
+    // the socket underneath, for diagnostics (see Worker::with_stream())
+    fn as_tcp(&mut self) -> Option<&mut TcpStream> {
+        None
+    }
+}
+
+impl Transport for TcpStream {
+    fn as_tcp(&mut self) -> Option<&mut TcpStream> {
+        Some(self)
+    }
+}
+
+impl Transport for DuplexStream {}
+
+/*
+    Rpc stream towards one machine, buffering commands until the worker's FlushPolicy pushes them out
+*/
+pub struct RpcSendStream {
+    stream: BufWriter<Box<dyn Transport>>,
+    unflushed: usize, // commands buffered since the last flush
+}
+
+impl RpcSendStream {
+    pub fn new(stream: impl Transport + 'static) -> Self {
+        RpcSendStream {
+            stream: BufWriter::new(Box::new(stream)),
+            unflushed: 0,
+        }
+    }
+
+    // writing one command, with its trailing data and, given a key, its tag (see auth.rs)
+    pub(crate) async fn send(
+        &mut self,
+        bytes: &[u8],
+        policy: FlushPolicy,
+        key: Option<&[u8]>,
+    ) -> io::Result<()> {
+        self.stream.write_all(bytes).await?;
+        if let Some(key) = key {
+            self.stream.write_all(&sign(key, &[bytes])).await?;
+        }
+        self.unflushed += 1;
+        match policy {
+            FlushPolicy::Immediate => self.flush().await,
+            FlushPolicy::EveryN(n) if self.unflushed >= n => self.flush().await,
+            FlushPolicy::EveryN(_) | FlushPolicy::EveryDuration(_) => Ok(()),
+        }
+    }
+
+    // pushing out what is buffered and closing the connection, the remote reads the end of the stream
+    pub async fn close(&mut self) -> io::Result<()> {
+        self.flush().await?;
+        self.stream.shutdown().await
+    }
+
+    pub async fn flush(&mut self) -> io::Result<()> {
+        if self.unflushed > 0 {
+            self.stream.flush().await?;
+            self.unflushed = 0;
+        }
+        Ok(())
+    }
+}
+
+// slots of the result channel of a remote execute, unless configured otherwise
+pub const DEFAULT_RESULT_BUFFER: usize = 1000;
+
+// results larger than this many bytes are decoded on a blocking thread unless configured otherwise, see
+// spawn_result_reader()
+pub const DEFAULT_BLOCKING_DECODE_THRESHOLD: usize = 1 << 20;
+
+// how often shutdown() checks whether the work in flight is done
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// how long a vertex lent out stays leased without being renewed, unless configured otherwise
+pub const DEFAULT_LEASE_DURATION: Duration = Duration::from_secs(30);
+
+// times the execute of a vertex may be forwarded between machines holding only a reference to it, unless configured otherwise
+pub const DEFAULT_MAX_FORWARD_HOPS: usize = 8;
+
 /*
     Worker Struct that stores the (vertex_id -> vertex) mapping, acting as pointers to vertices
         as well as the communication channels
 
+    Lock order, for everything holding more than one of these at a time:
+        rpc_sending_streams / sending_streams -> the stream's Mutex -> shipped_aux_bases
+    result_multiplexing_channels is taken on its own, never held while acquiring another lock or awaiting a
+    send. Requests register and forget their channels through register_result_channel() and
+    forget_result_channel(), whose guards can't outlive the call.
+
+    A stream's Mutex is only held while writing a request (see write_request()), never while its result is
+    waited on: the results come back on the data streams, each read by one spawn_result_reader() task.
+
     TODO: Add weights to edges
 */
-pub struct Worker<T: DeserializeOwned + Serialize + Default, V: Debug> {
+pub struct Worker<T, V, C = BincodeCodec, P = C> {
     // pub graph: HashMap<VertexID, Vertex<T>>, // vertex_id -> vertex mapping
-    pub sending_streams: RwLock<HashMap<MachineID, Mutex<TcpStream>>>,
-    pub rpc_sending_streams: RwLock<HashMap<MachineID, Mutex<TcpStream>>>,
-    pub result_multiplexing_channels: RwLock<HashMap<Uuid, Mutex<Sender<RPCResPayload<T, V>>>>>, // Note: maybe make the result either (V, or Data<T>, or T)
+    pub machine_id: MachineID, // the machine this worker runs on
+    pub sending_streams: RwLock<HashMap<MachineID, Mutex<Box<dyn Transport>>>>,
+    pub rpc_sending_streams: RwLock<HashMap<MachineID, Mutex<RpcSendStream>>>,
+    pub peer_addresses: HashMap<MachineID, String>, // where to reconnect a broken rpc stream to, see Worker::send_rpc()
+    pub flush_policy: FlushPolicy,                  // for the commands on rpc_sending_streams
+    pub compression: Option<i32>, // zstd level the rpc payloads and results are compressed at, None sends them as is
+    pub rpc_key: Option<Vec<u8>>, // shared secret signing the rpc commands, the same on every machine
+    pub remote_timeout: Option<Duration>, // longest a remote execute is waited on, unless its ExecOptions say otherwise
+    pub retry_policy: RetryPolicy, // for remote executes failing transiently, never retrying by default
+    pub result_buffer: usize,      // slots of each remote execute's result channel, at least 1
+    pub blocking_decode_threshold: usize, // results larger than this many bytes are decoded on a blocking thread
+    pub lease_duration: Duration, // how long the vertices lent out by this machine stay leased, see LocalVertex::lend()
+    pub max_forward_hops: usize,  // before a vertex nobody owns fails with FusionError::ForwardLoop
+    pub max_in_flight: Option<usize>, // remote executes awaiting their result per machine, at least 1, None for no limit
+    pub result_multiplexing_channels: RwLock<HashMap<Uuid, ResultChannel<T, V>>>, // Note: maybe make the result either (V, or Data<T>, or T)
+    pub aux_bases: RwLock<HashMap<Uuid, Vec<u8>>>, // serialized bases received for RPC::ExecuteDelta
+    pub shipped_aux_bases: RwLock<HashSet<(MachineID, Uuid)>>, // bases already sent to each machine
+    pub machine_health: RwLock<HashMap<MachineID, MachineHealth>>, // machines missing are Healthy
+    payload_bytes: AtomicU64, // encoded payload bytes handed to compression so far, see compression_stats()
+    compressed_bytes: AtomicU64, // what they were sent as
+    shutting_down: AtomicBool, // set by shutdown(), new executes are refused from then on
+    reaped_channels: AtomicU64, // result channels removed by the reaper so far
+    rpc_metrics: std::sync::Mutex<HashMap<MachineID, MachineRpcMetrics>>, // see metrics_snapshot()
+    in_flight: std::sync::Mutex<HashMap<MachineID, Arc<Semaphore>>>, // slots of max_in_flight, by machine
+    cancellations: UnboundedSender<(MachineID, Uuid)>, // requests abandoned while waiting on their result
+    pending_cancellations: std::sync::Mutex<Option<UnboundedReceiver<(MachineID, Uuid)>>>, // until spawn_canceller()
+    pub(crate) revocations: UnboundedSender<Revocation>, // read leases revoked by writes here, see LocalVertex::lend_read()
+    pending_revocations: std::sync::Mutex<Option<UnboundedReceiver<Revocation>>>, // until spawn_revoker()
+    outbound: UnboundedSender<(MachineID, OutboundRequest)>, // requests for the outbound senders
+    pending_outbound: std::sync::Mutex<Option<UnboundedReceiver<(MachineID, OutboundRequest)>>>, // until spawn_outbound()
+    outbound_seq: AtomicU64, // order the outbound requests were queued in
+    _codec: PhantomData<fn() -> (C, P)>, // the command and payload codecs, see codec.rs
+}
+
+/*
+    Request waiting to be written to the rpc stream towards its machine, see Worker::spawn_outbound()
+*/
+pub(crate) struct OutboundRequest {
+    priority: u8,
+    seq: u64, // queueing order, FIFO among the same priority
+    bytes: Vec<u8>,
+    sent: oneshot::Sender<Result<(), RpcError>>,
+}
+
+impl PartialEq for OutboundRequest {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority && self.seq == other.seq
+    }
+}
+
+impl Eq for OutboundRequest {}
+
+impl PartialOrd for OutboundRequest {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OutboundRequest {
+    // max-heap: higher priority first, then queued earlier
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+// the requests towards one machine, drained by its sender (Worker::send_outbound())
+#[derive(Default)]
+struct OutboundQueue {
+    pending: RefCell<BinaryHeap<OutboundRequest>>,
+    ready: Notify,
+}
+
+/*
+    Armed while a request waits on its result, so that abandoning the wait (e.g. an aborted spawn_apply())
+    tells the remote to stop working on it through RPC::Cancel
+
+    Drop can't await, so the cancellation is handed to the worker's canceller task (Worker::spawn_canceller())
+*/
+pub(crate) struct CancelOnDrop {
+    cancellations: UnboundedSender<(MachineID, Uuid)>,
+    location: MachineID,
+    id: Uuid,
+    armed: bool,
+}
+
+impl CancelOnDrop {
+    // the result arrived, nothing to cancel
+    pub(crate) fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if self.armed {
+            // only fails once the worker is gone, along with every request
+            let _ = self.cancellations.send((self.location, self.id));
+        }
+    }
 }
 
 impl<T: DeserializeOwned + Serialize + Default, V: Debug> Worker<T, V> {
     /*
-       Constructor
+       Constructor, for a worker speaking BincodeCodec
     */
-    pub fn new() -> Self {
+    pub fn new(machine_id: MachineID) -> Self {
+        Self::with_codec(machine_id)
+    }
+}
+
+impl<T: DeserializeOwned + Serialize + Default, V: Debug, C: Codec, P: Codec> Worker<T, V, C, P> {
+    /*
+       Constructor, for a worker speaking C for its commands and P for its payloads, which every machine it
+       talks to has to speak as well (e.g. Worker::<T, V, MyCodec>::with_codec() for both, or
+       Worker::<T, V, BincodeCodec, MyCodec>::with_codec() for the payloads only, see codec.rs)
+    */
+    pub fn with_codec(machine_id: MachineID) -> Self {
+        let (cancellations, pending_cancellations) = mpsc::unbounded_channel();
+        let (revocations, pending_revocations) = mpsc::unbounded_channel();
+        let (outbound, pending_outbound) = mpsc::unbounded_channel();
         Worker {
+            machine_id,
             sending_streams: RwLock::new(HashMap::new()),
             rpc_sending_streams: RwLock::new(HashMap::new()),
+            peer_addresses: HashMap::new(),
+            flush_policy: FlushPolicy::default(),
+            compression: None,
+            rpc_key: None,
+            remote_timeout: None,
+            retry_policy: RetryPolicy::default(),
+            result_buffer: DEFAULT_RESULT_BUFFER,
+            blocking_decode_threshold: DEFAULT_BLOCKING_DECODE_THRESHOLD,
+            lease_duration: DEFAULT_LEASE_DURATION,
+            max_forward_hops: DEFAULT_MAX_FORWARD_HOPS,
+            max_in_flight: None,
             result_multiplexing_channels: RwLock::new(HashMap::new()),
+            aux_bases: RwLock::new(HashMap::new()),
+            shipped_aux_bases: RwLock::new(HashSet::new()),
+            machine_health: RwLock::new(HashMap::new()),
+            payload_bytes: AtomicU64::new(0),
+            compressed_bytes: AtomicU64::new(0),
+            shutting_down: AtomicBool::new(false),
+            reaped_channels: AtomicU64::new(0),
+            rpc_metrics: std::sync::Mutex::new(HashMap::new()),
+            in_flight: std::sync::Mutex::new(HashMap::new()),
+            cancellations,
+            pending_cancellations: std::sync::Mutex::new(Some(pending_cancellations)),
+            revocations,
+            pending_revocations: std::sync::Mutex::new(Some(pending_revocations)),
+            outbound,
+            pending_outbound: std::sync::Mutex::new(Some(pending_outbound)),
+            outbound_seq: AtomicU64::new(0),
+            _codec: PhantomData,
+        }
+    }
+
+    /*
+       Compressing an encoded payload (trailing data of a command, or a result) under Worker::compression,
+       returns the bytes to send and whether they are compressed
+    */
+    pub fn compress_payload(&self, payload: Vec<u8>) -> (Vec<u8>, bool) {
+        let Some(level) = self.compression else {
+            return (payload, false);
+        };
+        self.payload_bytes
+            .fetch_add(payload.len() as u64, Ordering::Relaxed);
+        let (payload, compressed) = match compress(&payload, level) {
+            Some(compressed) => (compressed, true),
+            None => (payload, false),
+        };
+        self.compressed_bytes
+            .fetch_add(payload.len() as u64, Ordering::Relaxed);
+        (payload, compressed)
+    }
+
+    // (encoded, sent) bytes of the payloads that went through compression so far, to tell whether it pays off
+    pub fn compression_stats(&self) -> (u64, u64) {
+        (
+            self.payload_bytes.load(Ordering::Relaxed),
+            self.compressed_bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    // the framed command followed by its trailing data, compressed under Worker::compression
+    pub(crate) fn frame_request(
+        &self,
+        command: impl FnOnce(usize) -> RPC,
+        trailing: Vec<u8>,
+    ) -> Result<Vec<u8>, RpcError> {
+        let (trailing, compressed) = self.compress_payload(trailing);
+        let frame = command(trailing.len())
+            .frame::<C>(compressed)
+            .map_err(RpcError::Codec)?;
+        Ok([frame, trailing].concat())
+    }
+
+    /*
+       Sending on the rpc stream towards `location`, reconnecting and retrying once if the connection broke
+
+       The new connection replaces the broken one inside the stream's lock rather than in rpc_sending_streams,
+       so the commands of other requests stay queued behind it and the caller's result channel stays registered
+       throughout. The remote tells it apart from the initial connections by the hello sent first.
+
+       Note: commands still buffered on the broken connection (see FlushPolicy) are lost
+    */
+    pub(crate) async fn send_rpc(
+        &self,
+        location: MachineID,
+        stream: &mut RpcSendStream,
+        bytes: &[u8],
+    ) -> Result<(), RpcError> {
+        let key = self.rpc_key.as_deref();
+        let Err(e) = stream.send(bytes, self.flush_policy, key).await else {
+            return Ok(());
+        };
+        let Some(address) = self.peer_addresses.get(&location) else {
+            return Err(RpcError::Io(e));
+        };
+        debug!(error = %e, location, "rpc stream broke, reconnecting");
+
+        let mut reconnected = TcpStream::connect(address).await.map_err(RpcError::Io)?;
+        reconnected
+            .write_all(&self.hello().map_err(RpcError::Codec)?)
+            .await
+            .map_err(RpcError::Io)?;
+        *stream = RpcSendStream::new(reconnected);
+        stream
+            .send(bytes, self.flush_policy, key)
+            .await
+            .map_err(RpcError::Io)
+    }
+
+    /*
+       What this machine sends first on the rpc streams it opens: its machine id, and the names of its command
+       and payload codecs, framed in the command codec
+    */
+    pub fn hello(&self) -> Result<Vec<u8>, CodecError> {
+        frame::<C, _>(&(self.machine_id, C::name(), P::name()))
+    }
+
+    /*
+       Reading the hello (see hello()) a peer sends first on an rpc stream, to tell which machine the stream
+       comes from
+
+       Fails with InvalidData when the peer's codecs aren't this machine's, the stream is not to be served then
+    */
+    pub async fn read_hello<R: AsyncRead + Unpin>(&self, stream: &mut R) -> io::Result<MachineID> {
+        let (machine_id, command_codec, payload_codec) =
+            read_framed::<C, (MachineID, String, String), _>(stream, MAX_HEADER_LEN).await?;
+        if (command_codec.as_str(), payload_codec.as_str()) != (C::name(), P::name()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "machine {machine_id} speaks {command_codec}/{payload_codec}, this one {}/{}",
+                    C::name(),
+                    P::name()
+                ),
+            ));
+        }
+        Ok(machine_id)
+    }
+
+    /*
+       Connecting towards the worker of machine `peer` in the same process, in place of the TcpStreams
+           buffer: bytes each stream holds before writes wait on the reading side
+
+       Returns the ends `peer` reads this machine's rpc commands and results from, to be served like the streams
+       it accepts from this machine over tcp. Without an address in peer_addresses, a broken in-memory stream is
+       not reconnected (see send_rpc()).
+    */
+    pub async fn connect_in_memory(
+        &self,
+        peer: MachineID,
+        buffer: usize,
+    ) -> (DuplexStream, DuplexStream) {
+        let (rpc_sending_stream, rpc_receiving_stream) = tokio::io::duplex(buffer);
+        let (sending_stream, receiving_stream) = tokio::io::duplex(buffer);
+        self.rpc_sending_streams
+            .write()
+            .await
+            .insert(peer, Mutex::new(RpcSendStream::new(rpc_sending_stream)));
+        self.sending_streams
+            .write()
+            .await
+            .insert(peer, Mutex::new(Box::new(sending_stream)));
+        (rpc_receiving_stream, receiving_stream)
+    }
+
+    /*
+       Writing the framed request `bytes` to the rpc stream towards `location`, the way every request but
+       RPC::ExecuteDelta's goes out (see RemoteVertex::remote_execute_delta())
+
+       The stream's lock is only held for the write: results come back on the data stream, routed to their
+       request by id (see spawn_result_reader()), so any number of requests can be waiting on one machine at once
+    */
+    pub(crate) async fn write_request(
+        &self,
+        location: MachineID,
+        bytes: &[u8],
+    ) -> Result<(), RpcError> {
+        let rpc_sending_streams = self.rpc_sending_streams.read().await;
+        let mut rpc_sending_stream = rpc_sending_streams
+            .get(&location)
+            .ok_or(RpcError::UnknownMachine(location))?
+            .lock()
+            .await;
+        self.send_rpc(location, &mut rpc_sending_stream, bytes)
+            .await
+    }
+
+    /*
+       Writing the framed request `bytes` towards `location` through its outbound queue, see spawn_outbound(),
+       resolves once it is written to the stream
+
+       Before spawn_outbound(), the request is written as soon as it gets the stream's lock
+    */
+    pub(crate) async fn send_prioritized(
+        &self,
+        location: MachineID,
+        priority: u8,
+        bytes: Vec<u8>,
+    ) -> Result<(), RpcError> {
+        if self.pending_outbound.lock().unwrap().is_some() {
+            return self.write_request(location, &bytes).await;
+        }
+
+        let (sent, written) = oneshot::channel();
+        let request = OutboundRequest {
+            priority,
+            seq: self.outbound_seq.fetch_add(1, Ordering::Relaxed),
+            bytes,
+            sent,
+        };
+        // either fails only once the outbound task is gone
+        self.outbound
+            .send((location, request))
+            .map_err(|_| RpcError::ChannelClosed)?;
+        written.await.map_err(|_| RpcError::ChannelClosed)?
+    }
+
+    /*
+       Waiting for one of the max_in_flight slots towards `location`, freed once the permit is dropped
+           None without a limit
+
+       Callers wait in line rather than queueing up channels and requests without bound, which slows down the
+       recursion above them. Note: a slot is held for the whole remote call, nested calls back into this machine
+       included, so a limit below how often a computation goes back and forth between two machines deadlocks it
+    */
+    pub(crate) async fn in_flight_permit(
+        &self,
+        location: MachineID,
+    ) -> Option<OwnedSemaphorePermit> {
+        // a limit of 0 would block every request for good, it is taken as 1
+        let max = self.max_in_flight?.max(1);
+        let slots = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(location)
+            .or_insert_with(|| Arc::new(Semaphore::new(max)))
+            .clone();
+        Some(slots.acquire_owned().await.expect("never closed"))
+    }
+
+    // a remote execute of `bytes` was written towards `location`
+    pub(crate) fn record_execute_sent(&self, location: MachineID, bytes: usize) {
+        let mut rpc_metrics = self.rpc_metrics.lock().unwrap();
+        let metrics = rpc_metrics.entry(location).or_default();
+        metrics.executes += 1;
+        metrics.bytes_sent += bytes as u64;
+    }
+
+    // the result of a remote execute towards `location` came in `latency` after it was written
+    pub(crate) fn record_execute_answered(&self, location: MachineID, latency: Duration) {
+        let mut rpc_metrics = self.rpc_metrics.lock().unwrap();
+        let metrics = rpc_metrics.entry(location).or_default();
+        metrics.answered += 1;
+        metrics.total_latency += latency;
+        metrics.max_latency = metrics.max_latency.max(latency);
+    }
+
+    /*
+       The remote executes this machine issued so far, per machine they went to, e.g. to spot a hotspot or
+       an unbalanced partitioning
+    */
+    pub fn metrics_snapshot(&self) -> HashMap<MachineID, MachineRpcMetrics> {
+        self.rpc_metrics.lock().unwrap().clone()
+    }
+
+    /*
+       Health getters and setters
+    */
+    pub async fn health(&self, machine: MachineID) -> MachineHealth {
+        self.machine_health
+            .read()
+            .await
+            .get(&machine)
+            .copied()
+            .unwrap_or_default()
+    }
+    pub async fn set_health(&self, machine: MachineID, health: MachineHealth) {
+        self.machine_health.write().await.insert(machine, health);
+    }
+
+    // new work should only be sent to machines that are not draining
+    pub async fn check_health(&self, machine: MachineID) -> Result<(), FusionError> {
+        match self.health(machine).await {
+            MachineHealth::Healthy => Ok(()),
+            MachineHealth::Draining => Err(FusionError::Draining(machine)),
+        }
+    }
+
+    /*
+       Announcing the shutdown of this machine to every connected machine, so that they stop sending it work
+
+       Results of requests already received are still sent back, only new requests are refused by the peers
+    */
+    pub async fn initiate_shutdown(&self) {
+        let command = RPC::Shutdown(Uuid::new_v4(), 0, 0)
+            .frame::<C>(false)
+            .unwrap();
+        for (machine, rpc_sending_stream) in self.rpc_sending_streams.read().await.iter() {
+            // whatever the policy, the announcement shouldn't wait in a buffer
+            let sent = rpc_sending_stream
+                .lock()
+                .await
+                .send(&command, FlushPolicy::Immediate, self.rpc_key.as_deref())
+                .await;
+            // a peer already gone has nothing left to send here
+            if let Err(e) = sent {
+                debug!(error = %e, machine, "failed to announce the shutdown");
+            }
+        }
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    /*
+       Shutting the machine down gracefully, e.g. on SIGTERM, returns whether everything in flight finished
+
+       Announces the shutdown to the peers (see initiate_shutdown()) and refuses new executes from then on. Then
+       waits up to `grace` for the executes already accepted in `queue` to send their results, and for the
+       results of this machine's own requests to arrive. What is left by then is dropped: the executes
+       still queued or running, whose callers are answered with FusionError::Draining, and the result
+       channels, whose callers fail with FusionError::ChannelClosed.
+       The rpc and data streams are flushed and closed last.
+    */
+    pub async fn shutdown(&self, queue: &ExecuteQueue, grace: Duration) -> bool {
+        self.shutting_down.store(true, Ordering::Relaxed);
+        self.initiate_shutdown().await;
+
+        let deadline = Instant::now() + grace;
+        let drained = loop {
+            if queue.is_idle() && !self.awaiting_results().await {
+                break true;
+            }
+            if Instant::now() >= deadline {
+                break false;
+            }
+            tokio::time::sleep(SHUTDOWN_POLL_INTERVAL).await;
+        };
+        if !drained {
+            queue.cancel_all().await;
+            self.result_multiplexing_channels.write().await.clear();
+        }
+
+        // the peers may be gone already, there is nothing left to do about it then
+        for rpc_sending_stream in self.rpc_sending_streams.read().await.values() {
+            let _ = rpc_sending_stream.lock().await.close().await;
+        }
+        for sending_stream in self.sending_streams.read().await.values() {
+            let _ = sending_stream.lock().await.shutdown().await;
+        }
+        drained
+    }
+
+    // whether a caller still waits on the result of one of this machine's requests
+    async fn awaiting_results(&self) -> bool {
+        for channel in self.result_multiplexing_channels.read().await.values() {
+            if !channel.sender.lock().await.is_closed() {
+                return true;
+            }
+        }
+        false
+    }
+
+    /*
+       Pushing out the commands buffered towards `machine` right away, e.g. after enqueueing latency-sensitive work
+    */
+    pub async fn flush(&self, machine: MachineID) -> Result<(), FusionError> {
+        let rpc_sending_streams = self.rpc_sending_streams.read().await;
+        let rpc_sending_stream = rpc_sending_streams
+            .get(&machine)
+            .ok_or(FusionError::UnknownMachine(machine))?;
+        rpc_sending_stream
+            .lock()
+            .await
+            .flush()
+            .await
+            .map_err(RpcError::Io)?;
+        Ok(())
+    }
+
+    // a stream failing to flush (e.g. its peer is gone) doesn't keep the others from being flushed
+    pub async fn flush_all(&self) {
+        for (machine, rpc_sending_stream) in self.rpc_sending_streams.read().await.iter() {
+            if let Err(e) = rpc_sending_stream.lock().await.flush().await {
+                debug!(error = %e, machine_id = *machine, "failed to flush the rpc stream");
+            }
+        }
+    }
+
+    /*
+       Background flusher for FlushPolicy::EveryDuration, pushing out the buffered commands every period
+           returns None under the other policies, which need no flusher
+    */
+    pub fn spawn_flusher(self: &Arc<Self>) -> Option<JoinHandle<()>>
+    where
+        T: 'static,
+        V: 'static,
+    {
+        let FlushPolicy::EveryDuration(period) = self.flush_policy else {
+            return None;
+        };
+        let worker = self.clone();
+        Some(tokio::task::spawn_local(async move {
+            let mut ticker = tokio::time::interval(period);
+            loop {
+                ticker.tick().await;
+                worker.flush_all().await;
+            }
+        }))
+    }
+
+    /*
+       Background reaper of orphaned result channels, whose result never arrived (dropped by the remote,
+       routing bugs, ...): every `interval`, channels registered more than `threshold` ago are logged and removed
+
+       This is only a safety net behind the per-request cleanup, so that leaks are visible rather than silent.
+       A caller still waiting on a reaped channel sees it closed.
+    */
+    pub fn spawn_channel_reaper(
+        self: &Arc<Self>,
+        threshold: Duration,
+        interval: Duration,
+    ) -> JoinHandle<()>
+    where
+        T: 'static,
+        V: 'static,
+    {
+        let worker = self.clone();
+        tokio::task::spawn_local(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                worker.reap_channels(threshold).await;
+            }
+        })
+    }
+
+    /*
+       Background task writing the requests of remote executes to the rpc streams highest priority first
+       (see ExecOptions::priority), so that an urgent request doesn't wait behind the ones queued before it
+
+       One sender per machine, spawned once there is a request for it. Without them, remote executes write to the
+       stream in whatever order they get its lock.
+           panics if spawned twice
+    */
+    pub fn spawn_outbound(self: &Arc<Self>) -> JoinHandle<()>
+    where
+        T: 'static,
+        V: 'static,
+    {
+        let mut pending_outbound = self
+            .pending_outbound
+            .lock()
+            .unwrap()
+            .take()
+            .expect("outbound senders already spawned");
+        let worker = self.clone();
+        tokio::task::spawn_local(async move {
+            let mut queues: HashMap<MachineID, Rc<OutboundQueue>> = HashMap::new();
+            while let Some((location, request)) = pending_outbound.recv().await {
+                let queue = queues.entry(location).or_insert_with(|| {
+                    let queue = Rc::new(OutboundQueue::default());
+                    tokio::task::spawn_local(worker.clone().send_outbound(location, queue.clone()));
+                    queue
+                });
+                queue.pending.borrow_mut().push(request);
+                queue.ready.notify_one();
+            }
+        })
+    }
+
+    // the sender of the outbound queue towards `location`, the requests queue up while it is writing
+    async fn send_outbound(self: Arc<Self>, location: MachineID, queue: Rc<OutboundQueue>) {
+        loop {
+            let next = queue.pending.borrow_mut().pop();
+            let Some(request) = next else {
+                queue.ready.notified().await;
+                continue;
+            };
+
+            let sent = self.write_request(location, &request.bytes).await;
+            // the requester may have stopped waiting
+            let _ = request.sent.send(sent);
+        }
+    }
+
+    /*
+       Background reader of the results machine `location` sends back on `stream`, its data stream to this machine
+
+       The only reader of the stream, one per machine: each result goes to the channel registered for its
+       request, whatever order the requests were sent in. A full channel is waited on in a task of its own,
+       so that one slow caller doesn't hold up the results of the others. A result that can't be decoded
+       fails its request instead.
+
+       Ends once the stream closes, or a header can't be read. The result channels of the requests still
+       waiting on `location` are dropped then, so that their callers fail with FusionError::ChannelClosed.
+    */
+    pub fn spawn_result_reader(
+        self: &Arc<Self>,
+        location: MachineID,
+        mut stream: impl AsyncRead + Unpin + 'static,
+    ) -> JoinHandle<()>
+    where
+        T: Send + 'static,
+        V: DeserializeOwned + Send + 'static,
+    {
+        let worker = self.clone();
+        tokio::task::spawn_local(async move {
+            loop {
+                // past a header that doesn't decode, the stream can't be split into results anymore
+                let header = match read_framed::<C, RPCResponseHeader, _>(
+                    &mut stream,
+                    MAX_HEADER_LEN,
+                )
+                .await
+                {
+                    Ok(header) => header,
+                    Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                        warn!(location, error = %e, "closing data stream on a malformed header");
+                        break;
+                    }
+                    Err(_) => break,
+                };
+                if let ResType::NotYetNeeded = header.session_type {
+                    unimplemented!()
+                }
+
+                // receive the result, even if no one waits for it anymore
+                let mut res_bytes = vec![0u8; header.data_len];
+                if let Err(e) = stream.read_exact(&mut res_bytes).await {
+                    debug!(error = %e, "data stream closed mid-result");
+                    break;
+                }
+
+                // large results are decoded off the runtime, to keep reading from the other streams
+                let compressed = header.compressed;
+                let decode = move || {
+                    let res_bytes = if compressed {
+                        decompress(&res_bytes, MAX_DECOMPRESSED_LEN).map_err(|e| e.to_string())?
+                    } else {
+                        res_bytes
+                    };
+                    P::decode::<RPCResPayload<T, V>>(&res_bytes).map_err(|e| e.to_string())
+                };
+                let res = if header.data_len > worker.blocking_decode_threshold {
+                    tokio::task::spawn_blocking(decode)
+                        .await
+                        .unwrap_or_else(|e| Err(e.to_string()))
+                } else {
+                    decode()
+                };
+                let res = res.unwrap_or_else(|e| {
+                    RPCResPayload::ErrorResPayload(FusionError::Rpc(format!(
+                        "failed to decode the result: {e}"
+                    )))
+                });
+                worker.route_result(header.session_id, res).await;
+            }
+
+            worker
+                .result_multiplexing_channels
+                .write()
+                .await
+                .retain(|_, channel| channel.location != location);
+        })
+    }
+
+    // handing a result to the channel of request `id`, see spawn_result_reader()
+    async fn route_result(self: &Arc<Self>, id: Uuid, res: RPCResPayload<T, V>)
+    where
+        T: 'static,
+        V: 'static,
+    {
+        // (not holding the map while sending, see the lock order on Worker)
+        let res_channels = self.result_multiplexing_channels.read().await;
+        let Some(res_channel) = res_channels.get(&id) else {
+            // e.g. reaped as orphaned, the result has nowhere to go
+            debug!(request_id = %id, "dropping result for unknown session");
+            return;
+        };
+        let sender = res_channel.sender.lock().await.clone();
+        drop(res_channels);
+
+        let res = match sender.try_send(res) {
+            Ok(()) => return,
+            Err(TrySendError::Full(res)) => res,
+            Err(TrySendError::Closed(_)) => {
+                // the caller stopped waiting for it (e.g. it was cancelled)
+                debug!(request_id = %id, "caller went away, dropping its result");
+                self.forget_result_channel(&id).await;
+                return;
+            }
+        };
+        let worker = self.clone();
+        tokio::task::spawn_local(async move {
+            if sender.send(res).await.is_err() {
+                debug!(request_id = %id, "caller went away, dropping its result");
+                worker.forget_result_channel(&id).await;
+            }
+        });
+    }
+
+    /*
+       Background task sending RPC::Cancel for the requests abandoned while waiting on their result,
+       and dropping their result channels. Without it, cancellations just queue up.
+           panics if spawned twice
+    */
+    pub fn spawn_canceller(self: &Arc<Self>) -> JoinHandle<()>
+    where
+        T: 'static,
+        V: 'static,
+    {
+        let mut pending_cancellations = self
+            .pending_cancellations
+            .lock()
+            .unwrap()
+            .take()
+            .expect("canceller already spawned");
+        let worker = self.clone();
+        tokio::task::spawn_local(async move {
+            while let Some((location, id)) = pending_cancellations.recv().await {
+                worker.forget_result_channel(&id).await;
+
+                let command = RPC::Cancel(id, 0, 0).frame::<C>(false).unwrap();
+                if let Some(rpc_sending_stream) =
+                    worker.rpc_sending_streams.read().await.get(&location)
+                {
+                    let sent = rpc_sending_stream
+                        .lock()
+                        .await
+                        .send(&command, worker.flush_policy, worker.rpc_key.as_deref())
+                        .await;
+                    if let Err(e) = sent {
+                        debug!(error = %e, machine_id = location, request_id = %id, "failed to cancel a request");
+                    }
+                }
+            }
+        })
+    }
+
+    /*
+       Background task sending RPC::LeaseRevoked to the borrowers of the read leases revoked by writes here
+       (see LocalVertex::lend_read()). Without it, their copies are only dropped once the leases expire.
+           panics if spawned twice
+    */
+    pub fn spawn_revoker(self: &Arc<Self>) -> JoinHandle<()>
+    where
+        T: 'static,
+        V: 'static,
+    {
+        let mut pending_revocations = self
+            .pending_revocations
+            .lock()
+            .unwrap()
+            .take()
+            .expect("revoker already spawned");
+        let worker = self.clone();
+        tokio::task::spawn_local(async move {
+            while let Some((borrower, v_id, lease)) = pending_revocations.recv().await {
+                let trailing = C::encode(&lease).unwrap();
+                let command = worker
+                    .frame_request(|len| RPC::LeaseRevoked(Uuid::new_v4(), v_id, len), trailing)
+                    .unwrap();
+                if let Some(rpc_sending_stream) =
+                    worker.rpc_sending_streams.read().await.get(&borrower)
+                {
+                    let sent = rpc_sending_stream
+                        .lock()
+                        .await
+                        .send(&command, worker.flush_policy, worker.rpc_key.as_deref())
+                        .await;
+                    if let Err(e) = sent {
+                        debug!(error = %e, machine_id = borrower, v_id, "failed to revoke a read lease");
+                    }
+                }
+            }
+        })
+    }
+
+    // armed once the request `id` is sent to `location`, see CancelOnDrop
+    pub(crate) fn cancel_on_drop(&self, location: MachineID, id: Uuid) -> CancelOnDrop {
+        CancelOnDrop {
+            cancellations: self.cancellations.clone(),
+            location,
+            id,
+            armed: true,
+        }
+    }
+
+    // where the result of the request `id` is to be delivered, to be registered before it is sent
+    pub(crate) async fn register_result_channel(
+        &self,
+        location: MachineID,
+        id: Uuid,
+        tx: Sender<RPCResPayload<T, V>>,
+    ) {
+        self.result_multiplexing_channels
+            .write()
+            .await
+            .insert(id, ResultChannel::new(location, tx));
+    }
+
+    // dropping the result channel of a request that will not be waited on
+    pub(crate) async fn forget_result_channel(&self, id: &Uuid) {
+        self.result_multiplexing_channels.write().await.remove(id);
+    }
+
+    // single pass of the reaper, returns how many channels were removed
+    pub async fn reap_channels(&self, threshold: Duration) -> usize {
+        let now = Instant::now();
+        let mut channels = self.result_multiplexing_channels.write().await;
+        let before = channels.len();
+        channels.retain(|id, channel| {
+            let age = now.duration_since(channel.registered_at);
+            if age > threshold {
+                debug!(request_id = %id, ?age, "reaping orphaned result channel");
+                false
+            } else {
+                true
+            }
+        });
+        let reaped = before - channels.len();
+        self.reaped_channels
+            .fetch_add(reaped as u64, Ordering::Relaxed);
+        reaped
+    }
+
+    // metric: total number of result channels reaped
+    pub fn reaped_channels(&self) -> u64 {
+        self.reaped_channels.load(Ordering::Relaxed)
+    }
+
+    /*
+       Escape hatch to the raw rpc stream towards `machine`, for diagnostics (custom probes, socket stats, ...)
+           f runs while holding the stream's mutex, after the buffered commands are flushed (a broken stream
+           failing to flush still gets to f, to be diagnosed),
+       returns None if no stream exists for the machine or it isn't a socket (e.g. connected in memory)
+
+       WARNING: the stream carries framed RPC commands, writing or reading raw bytes through it desynchronizes
+       the framing and corrupts every subsequent RPC between the two machines
+    */
+    pub async fn with_stream<F, R>(&self, machine: MachineID, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut TcpStream) -> R,
+    {
+        let rpc_sending_streams = self.rpc_sending_streams.read().await;
+        let mut stream = rpc_sending_streams.get(&machine)?.lock().await;
+        if let Err(e) = stream.flush().await {
+            debug!(error = %e, machine_id = machine, "failed to flush the rpc stream");
+        }
+        stream.stream.get_mut().as_tcp().map(f)
+    }
+
+    /*
+       One RPC round trip towards `location`
+           command: builds the RPC from the session id and the length of the trailing data
+           trailing: bytes sent right after the command, on the same rpc stream
+
+       Unlike remote_execute(), a missing stream is reported instead of panicking, since the machine
+       may come from another node's view of the cluster (e.g. a redirect)
+    */
+    pub(crate) async fn request(
+        &self,
+        location: MachineID,
+        command: impl FnOnce(Uuid, usize) -> RPC,
+        trailing: Vec<u8>,
+    ) -> Result<RPCResPayload<T, V>, FusionError> {
+        self.check_health(location).await?;
+        let id = Uuid::new_v4();
+        let bytes = self.frame_request(|len| command(id, len), trailing)?;
+        charge_request(location, bytes.len())?;
+
+        // register the result channel before anything is sent
+        let (tx, mut rx) = mpsc::channel::<RPCResPayload<T, V>>(1);
+        self.register_result_channel(location, id, tx).await;
+
+        if let Err(e) = self.write_request(location, &bytes).await {
+            self.forget_result_channel(&id).await;
+            return Err(e.into());
+        }
+
+        let cancel_on_drop = self.cancel_on_drop(location, id);
+        let res = rx.recv().await.ok_or(RpcError::ChannelClosed);
+        cancel_on_drop.disarm();
+        self.forget_result_channel(&id).await;
+        Ok(res?)
+    }
+}
+
+/*
+    Accepting the next connection from whichever listener has one first
+
+    Lets a multi-homed machine listen on several addresses (e.g. internal and external) while every
+    connection still ends up in the same setup, whatever interface the peer reached it on
+*/
+pub async fn accept_any(listeners: &[TcpListener]) -> io::Result<(TcpStream, SocketAddr)> {
+    assert!(!listeners.is_empty(), "no listener to accept from");
+    let (res, _, _) =
+        select_all(listeners.iter().map(|listener| Box::pin(listener.accept()))).await;
+    res
+}
+
+/*
+    Queues the execution of a udf on a local vertex to be run in a separate task once the queue lets it run,
+    and sends the result back to the machine `id`, continuing its recursion from where `caller` is
+*/
+fn spawn_execute<
+    T: Serialize + DeserializeOwned + Send + Sync + 'static + Debug + Default,
+    V: Serialize + Send + Sync + 'static + Debug,
+    C: Codec,
+    P: Codec,
+>(
+    uuid: Uuid,
+    execution: impl Future<Output = Result<V, FusionError>> + 'static,
+    worker: Arc<Worker<T, V, C, P>>,
+    id: Arc<MachineID>,
+    queue: &Rc<ExecuteQueue>,
+    priority: u8,
+    caller: CallerContext,
+) {
+    let (drained_worker, drained_id) = (worker.clone(), id.clone());
+    let drained = async move {
+        let res: RPCResPayload<T, V> =
+            RPCResPayload::ErrorResPayload(FusionError::Draining(drained_worker.machine_id));
+        send_result(&drained_worker, &drained_id, uuid, ResType::ExecuteRes, res).await;
+    };
+
+    let execute = async move {
+        // calculate the result in a non-blocking manner, without holding onto locks prior to entrance
+        let (res, mut used) = continue_call(caller, execution).await;
+
+        // construct result that is to be sent back
+        let res: RPCResPayload<T, V> = match res {
+            Ok(res) => {
+                used.sent += P::encoded_len(&res).unwrap();
+                RPCResPayload::ExecuteResPayload(res, used)
+            }
+            Err(e) => RPCResPayload::ErrorResPayload(e),
+        };
+        send_result(&worker, &id, uuid, ResType::ExecuteRes, res).await;
+    };
+    queue.push(uuid, priority, execute, drained);
+}
+
+/*
+    Sends a result back to the machine `id` on the data stream, under the session id of its request
+
+    A machine that can't be written to anymore doesn't wait on the result either, so a failure is only logged
+*/
+async fn send_result<
+    T: Serialize + DeserializeOwned + Default,
+    V: Serialize + Debug,
+    C: Codec,
+    P: Codec,
+>(
+    worker: &Worker<T, V, C, P>,
+    id: &MachineID,
+    uuid: Uuid,
+    session_type: ResType,
+    res: RPCResPayload<T, V>,
+) {
+    let (res_bytes, compressed) =
+        worker.compress_payload(P::encode::<RPCResPayload<T, V>>(&res).unwrap());
+
+    // get sending_stream as mut
+    let sending_streams = worker.sending_streams.read().await;
+    let Some(sending_stream) = sending_streams.get(id) else {
+        warn!(machine_id = *id, "no data stream to send the result on");
+        return;
+    };
+    let mut sending_stream = sending_stream.lock().await;
+
+    // construct session header
+    let session_header_for_result = RPCResponseHeader {
+        session_id: uuid,
+        session_type,
+        data_len: res_bytes.len(),
+        compressed,
+    };
+    let session_header_for_result_bytes = frame::<C, _>(&session_header_for_result).unwrap();
+
+    // send all the data
+    if let Err(e) = sending_stream
+        .write_all(&[session_header_for_result_bytes, res_bytes].concat())
+        .await
+    {
+        warn!(machine_id = *id, error = %e, "failed to send result");
+    }
+}
+
+/*
+    Session of the result answering an execute command, which can be answered with an error instead
+*/
+fn execute_session(rpc: &RPC) -> Option<(Uuid, ResType)> {
+    match *rpc {
+        RPC::Execute(uuid, ..)
+        | RPC::ExecuteMut(uuid, ..)
+        | RPC::ExecuteDelta(uuid, ..)
+        | RPC::Offload(uuid, ..) => Some((uuid, ResType::ExecuteRes)),
+        RPC::ExecuteBatch(uuid, ..) => Some((uuid, ResType::ExecuteBatchRes)),
+        _ => None,
+    }
+}
+
+/*
+    Serving the rpc commands machine `id` sends on `stream`, its rpc stream to this machine, until it closes
+
+    Executes run `udf` (ExecuteMut runs `mut_udf`) through the shared `queue`, every other command is answered
+    in place or in a task of its own. The results go back on the data stream towards `id` (see
+    spawn_result_reader() on the other end).
+*/
+#[allow(clippy::too_many_arguments)]
+pub async fn serve_rpc_stream<
+    T: Serialize + DeserializeOwned + Send + Sync + 'static + Debug + Default,
+    U: Serialize + DeserializeOwned + AuxDelta + Send + Sync + 'static,
+    X: UserDefinedFunction<T, U, V, C, P> + Send + Sync + 'static + Clone,
+    M: MutableUserDefinedFunction<T, U, V, C, P> + Send + Sync + 'static + Clone,
+    V: Serialize + Send + Sync + 'static + Debug,
+    C: Codec,
+    P: Codec,
+>(
+    id: Arc<MachineID>,
+    mut stream: impl AsyncRead + Unpin,
+    worker: Arc<Worker<T, V, C, P>>,
+    data_store: Arc<DataStore<T, V, C, P>>,
+    udf: &X,
+    mut_udf: &M,
+    tx_req: Sender<MachineID>,
+    tx_res: Sender<()>,
+    queue: Rc<ExecuteQueue>,
+) {
+    loop {
+        // the command is length prefixed, so a partial read can't shift where its trailing data starts
+        let (cmd, rpc, compressed) = match read_command::<C, _>(&mut stream).await {
+            Ok(command) => command,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => {
+                warn!(machine_id = *id.as_ref(), error = %e, "closing rpc stream");
+                break;
+            }
+        };
+
+        // receive the trailing data, it comes in the same RPC stream as noted in remote_execute()
+        // then with a shared secret configured, the tag signing the command
+        let mut tag = [0u8; TAG_LEN];
+        let received = match read_trailing(&mut stream, rpc.trailing_len()).await {
+            Ok(trailing) if worker.rpc_key.is_some() => {
+                stream.read_exact(&mut tag).await.map(|_| trailing)
+            }
+            received => received,
+        };
+        let trailing = match received {
+            Ok(trailing) => trailing,
+            Err(e) => {
+                // the connection dropped mid-request, its caller is answered rather than left waiting
+                warn!(machine_id = *id.as_ref(), error = %e, "rpc stream ended within a request");
+                if let Some((uuid, session_type)) = execute_session(&rpc) {
+                    let res: RPCResPayload<T, V> = RPCResPayload::ErrorResPayload(
+                        FusionError::Rpc(format!("request truncated after the command: {e}")),
+                    );
+                    send_result(&worker, &id, uuid, session_type, res).await;
+                }
+                break;
+            }
+        };
+
+        // with a shared secret configured, only commands signed with it are dispatched
+        if let Some(key) = &worker.rpc_key {
+            if let Err(e) = verify(key, &[&cmd, &trailing], &tag) {
+                warn!(machine_id = *id.as_ref(), error = %e, "rejecting rpc");
+                // an execute is still answered, so that its caller fails rather than waits on it
+                if let Some((uuid, session_type)) = execute_session(&rpc) {
+                    let res: RPCResPayload<T, V> = RPCResPayload::ErrorResPayload(e);
+                    send_result(&worker, &id, uuid, session_type, res).await;
+                }
+                continue;
+            }
+        }
+        // the tag is over what was sent, so only decompressed once verified
+        let trailing = if compressed {
+            match decompress(&trailing, MAX_DECOMPRESSED_LEN) {
+                Ok(trailing) => trailing,
+                Err(e) => {
+                    warn!(machine_id = *id.as_ref(), error = %e, "rejecting rpc");
+                    if let Some((uuid, session_type)) = execute_session(&rpc) {
+                        let res: RPCResPayload<T, V> = RPCResPayload::ErrorResPayload(
+                            FusionError::Rpc(format!("failed to decompress the request: {e}")),
+                        );
+                        send_result(&worker, &id, uuid, session_type, res).await;
+                    }
+                    continue;
+                }
+            }
+        } else {
+            trailing
+        };
+
+        // shutting down, only the executes accepted before are still run (see Worker::shutdown())
+        if worker.is_shutting_down() {
+            if let Some((uuid, session_type)) = execute_session(&rpc) {
+                let res: RPCResPayload<T, V> =
+                    RPCResPayload::ErrorResPayload(FusionError::Draining(worker.machine_id));
+                send_result(&worker, &id, uuid, session_type, res).await;
+                continue;
+            }
+        }
+
+        match rpc {
+            RPC::Execute(uuid, v_id, _)
+            | RPC::ExecuteMut(uuid, v_id, _)
+            | RPC::Offload(uuid, v_id, _) => {
+                // println!("Arc counts: {:?}", Arc::strong_count(&data_store));
+
+                // caller context and priority first, then auxiliary information
+                let (caller, trailing) = CallerContext::split::<C>(&trailing);
+                let (priority, aux_info) =
+                    trailing.split_first().expect("Missing Execute Priority");
+                let aux_info = P::decode::<U>(aux_info).expect("Incorrect Auxiliary Info Format");
+
+                // construct variables to pass into the new thread, for non-blocking circular/recursive remote calls
+                let data_store = data_store.clone();
+                let execution: LocalBoxFuture<_> = match rpc {
+                    RPC::ExecuteMut(..) => {
+                        let mut_udf_clone = mut_udf.clone();
+                        Box::pin(async move {
+                            data_store
+                                .get_vertex_by_id(&v_id)
+                                .apply_function_mut(&mut_udf_clone, data_store.clone(), aux_info)
+                                .await
+                        })
+                    }
+                    RPC::Offload(..) => {
+                        let (udf_clone, lender) = (udf.clone(), *id);
+                        Box::pin(async move {
+                            data_store
+                                .run_borrowed(v_id, lender, &udf_clone, aux_info)
+                                .await
+                        })
+                    }
+                    _ => {
+                        let udf_clone = udf.clone();
+                        Box::pin(async move {
+                            data_store
+                                .apply_function_accounted(v_id, &udf_clone, aux_info)
+                                .await
+                        })
+                    }
+                };
+
+                // the recursion from here keeps the caller's priority for its own remote calls
+                let options = ExecOptions {
+                    priority: *priority,
+                    ..ExecOptions::default()
+                };
+                spawn_execute(
+                    uuid,
+                    run_with_options(options, execution),
+                    worker.clone(),
+                    id.clone(),
+                    &queue,
+                    *priority,
+                    caller,
+                );
+            }
+            RPC::ExecuteDelta(uuid, v_id, _) => {
+                // caller context and priority first, then the base and delta
+                let (caller, trailing) = CallerContext::split::<C>(&trailing);
+                let decoded = match trailing.split_first() {
+                    Some((priority, payload)) => C::decode::<AuxDeltaPayload>(payload)
+                        .map(|payload| (*priority, payload))
+                        .map_err(|e| format!("incorrect auxiliary delta format: {e}")),
+                    None => Err("missing execute priority".to_string()),
+                };
+                let (
+                    priority,
+                    AuxDeltaPayload {
+                        base_id,
+                        base,
+                        delta,
+                    },
+                ) = match decoded {
+                    Ok(decoded) => decoded,
+                    Err(reason) => {
+                        let res: RPCResPayload<T, V> =
+                            RPCResPayload::ErrorResPayload(FusionError::Rpc(reason));
+                        send_result(&worker, &id, uuid, ResType::ExecuteRes, res).await;
+                        continue;
+                    }
+                };
+
+                // the base travels with the first delta against it, before any other delta on this stream
+                let mut aux_bases = worker.aux_bases.write().await;
+                if let Some(base) = base {
+                    aux_bases.insert(base_id, base);
+                }
+                let Some(base) = aux_bases.get(&base_id) else {
+                    // e.g. this machine restarted since, the caller ships the base again on its next request
+                    drop(aux_bases);
+                    let res: RPCResPayload<T, V> =
+                        RPCResPayload::ErrorResPayload(FusionError::MissingAuxBase(base_id));
+                    send_result(&worker, &id, uuid, ResType::ExecuteRes, res).await;
+                    continue;
+                };
+
+                // rebuild the full auxiliary information from the base plus delta
+                let aux_info = match (P::decode::<U>(base), P::decode::<U::Delta>(&delta)) {
+                    (Ok(base), Ok(delta)) => Ok(U::from_delta(base, delta)),
+                    (Err(e), _) => Err(format!("incorrect auxiliary info format: {e}")),
+                    (_, Err(e)) => Err(format!("incorrect auxiliary delta format: {e}")),
+                };
+                drop(aux_bases);
+                let aux_info = match aux_info {
+                    Ok(aux_info) => aux_info,
+                    Err(reason) => {
+                        let res: RPCResPayload<T, V> =
+                            RPCResPayload::ErrorResPayload(FusionError::Rpc(reason));
+                        send_result(&worker, &id, uuid, ResType::ExecuteRes, res).await;
+                        continue;
+                    }
+                };
+
+                let data_store = data_store.clone();
+                let udf_clone = udf.clone();
+                // the recursion from here keeps the caller's priority for its own remote calls
+                let options = ExecOptions {
+                    priority,
+                    ..ExecOptions::default()
+                };
+                spawn_execute(
+                    uuid,
+                    run_with_options(options, async move {
+                        data_store
+                            .apply_function_accounted(v_id, &udf_clone, aux_info)
+                            .await
+                    }),
+                    worker.clone(),
+                    id.clone(),
+                    &queue,
+                    priority,
+                    caller,
+                );
+            }
+            RPC::Relay(_, _, _) => {
+                unimplemented!()
+            }
+            RPC::RequestData(_, _, _) => {
+                unimplemented!()
+            }
+            RPC::ExecuteWithData(_, _, _) => {
+                unimplemented!()
+            }
+            RPC::Update(uuid, v_id, _) => {
+                let data =
+                    P::decode::<Data<T>>(&trailing).expect("Incorrect Auxiliary Info Format");
+
+                // Note: Different here, doesn't need to multi-thread here, as update is pure local (synchronous in
+                // another sense
+
+                let res = data_store.get_vertex_by_id(&v_id).update(data).await;
+
+                // construct the payload to be sent
+                let res: RPCResPayload<T, V> = RPCResPayload::UpdateResPayload(res);
+                send_result(&worker, &id, uuid, ResType::UpdateRes, res).await;
+            }
+            RPC::Redirect(uuid, v_id, _) => {
+                // local lookup only, answer with where this machine believes the vertex lives
+                let res: RPCResPayload<T, V> = match data_store.try_get_vertex_by_id(&v_id) {
+                    Ok(vertex) => RPCResPayload::RedirectResPayload(match &vertex.v_type {
+                        VertexType::Local(_) | VertexType::Borrowed(_) => None,
+                        VertexType::Remote(remote_v) => Some(remote_v.location()),
+                    }),
+                    // a machine that doesn't know the vertex either can't point any further
+                    Err(e) => RPCResPayload::ErrorResPayload(e),
+                };
+                send_result(&worker, &id, uuid, ResType::RedirectRes, res).await;
+            }
+            RPC::SubtreeHash(uuid, v_id, _) => {
+                // may recurse into other machines, so computed without blocking the rpc stream
+                let worker = worker.clone();
+                let data_store = data_store.clone();
+                let id = id.clone();
+                tokio::task::spawn_local(async move {
+                    let res: RPCResPayload<T, V> = match data_store.subtree_hash(v_id).await {
+                        Ok(hash) => RPCResPayload::SubtreeHashResPayload(hash),
+                        Err(e) => RPCResPayload::ErrorResPayload(e),
+                    };
+                    send_result(&worker, &id, uuid, ResType::SubtreeHashRes, res).await;
+                });
+            }
+            RPC::FetchData(uuid, v_id, _) => {
+                let version =
+                    C::decode::<Option<u64>>(&trailing).expect("Incorrect Version Format");
+
+                // pure local read, same as update
+                let res = match data_store.try_get_vertex_by_id(&v_id) {
+                    Ok(vertex) => vertex.fetch_data(version).await,
+                    Err(e) => Err(e),
+                };
+
+                let res: RPCResPayload<T, V> = RPCResPayload::FetchDataResPayload(res);
+                send_result(&worker, &id, uuid, ResType::FetchDataRes, res).await;
+            }
+            RPC::GetNeighbors(uuid, v_id, _) => {
+                let which = C::decode::<Neighbors>(&trailing).expect("Incorrect Neighbors Format");
+
+                // pure local read, a vertex only referenced here is not followed to its owner
+                let res = match data_store.try_get_vertex_by_id(&v_id) {
+                    Ok(vertex) => match &vertex.v_type {
+                        VertexType::Local(local_v) | VertexType::Borrowed(local_v) => {
+                            Ok(local_v.neighbors(which).as_ref().clone())
+                        }
+                        VertexType::Remote(remote_v) => {
+                            Err(FusionError::NotLocal(v_id, remote_v.location()))
+                        }
+                    },
+                    Err(e) => Err(e),
+                };
+
+                let res: RPCResPayload<T, V> = RPCResPayload::NeighborsResPayload(res);
+                send_result(&worker, &id, uuid, ResType::NeighborsRes, res).await;
+            }
+            RPC::Borrow(uuid, v_id, _) => {
+                let mode = C::decode::<LeaseMode>(&trailing).expect("Incorrect Lease Mode Format");
+
+                // pure local, the vertex stays lent out until returned
+                let res = match data_store.try_get_vertex_by_id(&v_id) {
+                    Ok(vertex) => match &vertex.v_type {
+                        VertexType::Local(local_v) | VertexType::Borrowed(local_v) => match mode {
+                            LeaseMode::Exclusive => local_v.lend(v_id, worker.lease_duration).await,
+                            LeaseMode::Read => {
+                                local_v
+                                    .lend_read(
+                                        v_id,
+                                        *id,
+                                        worker.lease_duration,
+                                        &worker.revocations,
+                                    )
+                                    .await
+                            }
+                        },
+                        VertexType::Remote(remote_v) => {
+                            Err(FusionError::NotLocal(v_id, remote_v.location()))
+                        }
+                    },
+                    Err(e) => Err(e),
+                };
+
+                let res: RPCResPayload<T, V> = RPCResPayload::BorrowResPayload(res);
+                send_result(&worker, &id, uuid, ResType::BorrowRes, res).await;
+            }
+            RPC::ReturnBorrow(uuid, v_id, _) => {
+                let (lease, data) = P::decode::<(Uuid, Option<Data<T>>)>(&trailing)
+                    .expect("Incorrect Returned Data Format");
+
+                let res = match data_store.try_get_vertex_by_id(&v_id) {
+                    Ok(vertex) => match &vertex.v_type {
+                        VertexType::Local(local_v) | VertexType::Borrowed(local_v) => {
+                            local_v.take_back(v_id, lease, data).await
+                        }
+                        VertexType::Remote(remote_v) => {
+                            Err(FusionError::NotLocal(v_id, remote_v.location()))
+                        }
+                    },
+                    Err(e) => Err(e),
+                };
+
+                let res: RPCResPayload<T, V> = RPCResPayload::LeaseResPayload(res);
+                send_result(&worker, &id, uuid, ResType::LeaseRes, res).await;
+            }
+            RPC::LeaseRevoked(_, v_id, _) => {
+                let lease = C::decode::<Uuid>(&trailing).expect("Incorrect Lease Format");
+                data_store.lease_revoked(v_id, lease);
+            }
+            RPC::RenewLease(uuid, v_id, _) => {
+                let lease = C::decode::<Uuid>(&trailing).expect("Incorrect Lease Format");
+
+                let res = match data_store.try_get_vertex_by_id(&v_id) {
+                    Ok(vertex) => match &vertex.v_type {
+                        VertexType::Local(local_v) | VertexType::Borrowed(local_v) => {
+                            local_v.renew(v_id, lease, worker.lease_duration)
+                        }
+                        VertexType::Remote(remote_v) => {
+                            Err(FusionError::NotLocal(v_id, remote_v.location()))
+                        }
+                    },
+                    Err(e) => Err(e),
+                };
+
+                let res: RPCResPayload<T, V> = RPCResPayload::LeaseResPayload(res);
+                send_result(&worker, &id, uuid, ResType::LeaseRes, res).await;
+            }
+            RPC::ExecuteBatch(uuid, _, _) => {
+                let (caller, trailing) = CallerContext::split::<C>(&trailing);
+                let batch = P::decode::<Vec<(VertexID, U)>>(trailing)
+                    .expect("Incorrect Execute Batch Format");
+
+                // construct variable to pass into the new thread, for non-blocking circular/recursive remote calls
+                let worker = worker.clone();
+                let data_store = data_store.clone();
+                let udf_clone = udf.clone();
+                let id = id.clone();
+
+                tokio::task::spawn_local(async move {
+                    // roots of a batch are independent, execute them concurrently but keep their order
+                    let (res, mut used) = continue_call(
+                        caller,
+                        try_join_all(batch.into_iter().map(|(v_id, aux_info)| {
+                            data_store.apply_function_accounted(v_id, &udf_clone, aux_info)
+                        })),
+                    )
+                    .await;
+
+                    let res: RPCResPayload<T, V> = match res {
+                        Ok(res) => {
+                            used.sent += P::encoded_len(&res).unwrap();
+                            RPCResPayload::ExecuteBatchResPayload(res, used)
+                        }
+                        Err(e) => RPCResPayload::ErrorResPayload(e),
+                    };
+                    send_result(&worker, &id, uuid, ResType::ExecuteBatchRes, res).await;
+                });
+            }
+            RPC::Cancel(uuid, _, _) => {
+                // the execute may already be done, then there is nothing left to stop
+                queue.cancel(&uuid);
+            }
+            RPC::EndTraversal(_, _, _) => match C::decode::<u64>(&trailing) {
+                Ok(traversal) => data_store.forget_traversal(traversal),
+                Err(e) => warn!(machine_id = *id.as_ref(), error = %e, "malformed traversal"),
+            },
+            RPC::Ping(uuid, _, _) => {
+                let res: RPCResPayload<T, V> =
+                    RPCResPayload::PongResPayload(worker.is_shutting_down());
+                send_result(&worker, &id, uuid, ResType::PongRes, res).await;
+            }
+            RPC::Shutdown(_, _, _) => {
+                // stop sending new work to the machine, its in-flight results still arrive on the data stream
+                worker
+                    .set_health(*id.as_ref(), MachineHealth::Draining)
+                    .await;
+            }
+            RPC::UpdateMap(_, _, _) => {
+                debug!(machine_id = *id.as_ref(), "received update request");
+                tx_req.send(*id.as_ref()).await.unwrap();
+            }
+            RPC::UpdateMapRes(_, _, _) => {
+                debug!(machine_id = *id.as_ref(), "received update response");
+                tx_res.send(()).await.unwrap()
+            }
+        }
+    }
+}
+
+/*
+    Executes waiting to run on the serving side, dispatched highest priority first
+
+    At most max_running executes run at the same time, the others wait here ordered by (priority, arrival).
+    Note: an execute waiting on a remote call keeps its slot, so cyclic remote recursion deeper than
+          max_running stalls; the limit has to stay above the depth of such cycles
+*/
+pub struct ExecuteQueue {
+    pending: RefCell<BinaryHeap<PendingExecute>>,
+    accepted: RefCell<HashMap<Uuid, AcceptedExecute>>, // the executes queued or running, by request id
+    running: Cell<usize>,
+    next_seq: Cell<u64>,
+    max_running: usize,
+}
+
+// handle on an execute queued or running, see ExecuteQueue::push()
+struct AcceptedExecute {
+    abort_handle: AbortHandle,
+    drained: LocalBoxFuture<'static, ()>,
+}
+
+struct PendingExecute {
+    priority: u8,
+    seq: u64, // arrival order, FIFO among the same priority
+    task: LocalBoxFuture<'static, ()>,
+}
+
+impl PartialEq for PendingExecute {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority && self.seq == other.seq
+    }
+}
+
+impl Eq for PendingExecute {}
+
+impl PartialOrd for PendingExecute {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PendingExecute {
+    // max-heap: higher priority first, then earlier arrival
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl ExecuteQueue {
+    /*
+       Constructor
+    */
+    pub fn new(max_running: usize) -> Self {
+        ExecuteQueue {
+            pending: RefCell::new(BinaryHeap::new()),
+            accepted: RefCell::new(HashMap::new()),
+            running: Cell::new(0),
+            next_seq: Cell::new(0),
+            max_running,
+        }
+    }
+
+    /*
+       Queues the execute of request `id`, which is spawned onto the current LocalSet once a slot frees up
+           drained: answers the request instead, if the execute is dropped by cancel_all()
+    */
+    pub fn push(
+        self: &Rc<Self>,
+        id: Uuid,
+        priority: u8,
+        task: impl Future<Output = ()> + 'static,
+        drained: impl Future<Output = ()> + 'static,
+    ) {
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        self.accepted.borrow_mut().insert(
+            id,
+            AcceptedExecute {
+                abort_handle,
+                drained: drained.boxed_local(),
+            },
+        );
+
+        let queue = self.clone();
+        let task = async move {
+            let _ = Abortable::new(task, abort_registration).await;
+            queue.accepted.borrow_mut().remove(&id);
+        };
+
+        let seq = self.next_seq.get();
+        self.next_seq.set(seq + 1);
+        self.pending.borrow_mut().push(PendingExecute {
+            priority,
+            seq,
+            task: task.boxed_local(),
+        });
+        self.dispatch();
+    }
+
+    /*
+       Dropping the execute of request `id`, whether still waiting or running, returns false if there is none
+       A running execute stops at its next await, so the remote calls it waits on get cancelled in turn
+    */
+    pub fn cancel(&self, id: &Uuid) -> bool {
+        match self.accepted.borrow_mut().remove(id) {
+            Some(accepted) => {
+                accepted.abort_handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // dropping every execute, waiting or running, see cancel(), and answering their requests as drained
+    pub async fn cancel_all(&self) {
+        let accepted: Vec<AcceptedExecute> = self
+            .accepted
+            .borrow_mut()
+            .drain()
+            .map(|(_, accepted)| accepted)
+            .collect();
+        for accepted in &accepted {
+            accepted.abort_handle.abort();
+        }
+        join_all(accepted.into_iter().map(|accepted| accepted.drained)).await;
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.borrow().len()
+    }
+
+    // nothing waiting and nothing running
+    pub fn is_idle(&self) -> bool {
+        self.running.get() == 0 && self.pending.borrow().is_empty()
+    }
+
+    fn dispatch(self: &Rc<Self>) {
+        while self.running.get() < self.max_running {
+            let Some(next) = self.pending.borrow_mut().pop() else {
+                return;
+            };
+            self.running.set(self.running.get() + 1);
+
+            let queue = self.clone();
+            tokio::task::spawn_local(async move {
+                next.task.await;
+                queue.running.set(queue.running.get() - 1);
+                queue.dispatch();
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::datastore::{build_graph_integer_data, DEFAULT_YIELD_EVERY_N_VERTICES};
+    use crate::udf::{AccumulateSubtree, CollectAtLeast, GraphReduce, GraphSum, Sum};
+    use crate::vertex::tests::add_star;
+    use crate::{AuxBase, Data};
+
+    use bincode::Options;
+
+    // serving the rpc commands and reading the results `peer` sends over the in-memory streams, with `udf`
+    // and `mut_udf` as the UDFs run for it
+    fn serve_peer<U, X, M, V, C, P>(
+        worker: &Arc<Worker<isize, V, C, P>>,
+        data_store: &Arc<DataStore<isize, V, C, P>>,
+        (udf, mut_udf): (&X, &M),
+        peer: MachineID,
+        (rpc_stream, data_stream): (DuplexStream, DuplexStream),
+    ) where
+        U: Serialize + DeserializeOwned + AuxDelta + Send + Sync + 'static,
+        X: UserDefinedFunction<isize, U, V, C, P> + Send + Sync + 'static + Clone,
+        M: MutableUserDefinedFunction<isize, U, V, C, P> + Send + Sync + 'static + Clone,
+        V: Serialize + DeserializeOwned + Send + Sync + 'static + Debug,
+        C: Codec,
+        P: Codec,
+    {
+        let (tx_req, rx_req) = mpsc::channel(1);
+        let (tx_res, rx_res) = mpsc::channel(1);
+        let (server, data_store) = (worker.clone(), data_store.clone());
+        let (udf, mut_udf) = (udf.clone(), mut_udf.clone());
+        tokio::task::spawn_local(async move {
+            // keeps the update channels open while the stream is served
+            let _update_channels = (rx_req, rx_res);
+            serve_rpc_stream(
+                Arc::new(peer),
+                rpc_stream,
+                server,
+                data_store,
+                &udf,
+                &mut_udf,
+                tx_req,
+                tx_res,
+                Rc::new(ExecuteQueue::new(8)),
+            )
+            .await;
+        });
+        worker.spawn_result_reader(peer, data_stream);
+    }
+
+    #[tokio::test]
+    async fn cancel_all_answers_the_dropped_executes() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                // no slot to run in, so the execute is still queued when the queue is drained
+                let queue = Rc::new(ExecuteQueue::new(0));
+                let (ran, drained) = (Rc::new(Cell::new(false)), Rc::new(Cell::new(false)));
+                let (ran_flag, drained_flag) = (ran.clone(), drained.clone());
+                queue.push(
+                    Uuid::new_v4(),
+                    0,
+                    async move { ran_flag.set(true) },
+                    async move { drained_flag.set(true) },
+                );
+
+                queue.cancel_all().await;
+                assert!(drained.get());
+                assert!(!ran.get());
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn max_in_flight_of_zero_still_lets_a_request_out() {
+        let mut worker = Worker::<isize, isize>::new(1);
+        worker.max_in_flight = Some(0);
+        let permit = tokio::time::timeout(Duration::from_secs(1), worker.in_flight_permit(2)).await;
+        assert!(matches!(permit, Ok(Some(_))));
+    }
+
+    // the id of the one request waiting on its result, once it registered its channel
+    async fn waiting_request(worker: &Worker<isize, isize>) -> Uuid {
+        loop {
+            if let Some(id) = worker
+                .result_multiplexing_channels
+                .read()
+                .await
+                .keys()
+                .next()
+            {
+                return *id;
+            }
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn result_reader_fails_the_requests_it_leaves_waiting() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let worker = Worker::<isize, isize>::new(1);
+                let _from_1 = worker.connect_in_memory(2, 1 << 16).await;
+                let worker = Arc::new(worker);
+                let (data_stream, from_2) = tokio::io::duplex(1 << 16);
+                worker.spawn_result_reader(2, from_2);
+
+                let requester = worker.clone();
+                let ping = tokio::task::spawn_local(async move {
+                    requester
+                        .request(2, |uuid, len| RPC::Ping(uuid, 0, len), vec![])
+                        .await
+                });
+                waiting_request(&worker).await;
+
+                // machine 2 goes away without answering
+                drop(data_stream);
+                assert!(matches!(
+                    ping.await.unwrap(),
+                    Err(FusionError::ChannelClosed)
+                ));
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn undecodable_result_fails_its_request() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let worker = Worker::<isize, isize>::new(1);
+                let _from_1 = worker.connect_in_memory(2, 1 << 16).await;
+                let worker = Arc::new(worker);
+                let (mut data_stream, from_2) = tokio::io::duplex(1 << 16);
+                worker.spawn_result_reader(2, from_2);
+
+                let requester = worker.clone();
+                let ping = tokio::task::spawn_local(async move {
+                    requester
+                        .request(2, |uuid, len| RPC::Ping(uuid, 0, len), vec![])
+                        .await
+                });
+                let id = waiting_request(&worker).await;
+
+                // a well-formed header in front of a payload that isn't a RPCResPayload
+                let garbage = vec![u8::MAX; 16];
+                let header = frame::<BincodeCodec, _>(&RPCResponseHeader {
+                    session_id: id,
+                    session_type: ResType::PongRes,
+                    data_len: garbage.len(),
+                    compressed: false,
+                })
+                .unwrap();
+                data_stream
+                    .write_all(&[header, garbage].concat())
+                    .await
+                    .unwrap();
+                assert!(matches!(
+                    ping.await.unwrap(),
+                    Ok(RPCResPayload::ErrorResPayload(FusionError::Rpc(_)))
+                ));
+            })
+            .await;
+    }
+
+    // the data of the vertex, once let through by the gate
+    #[derive(Clone)]
+    struct Gated(Arc<Notify>);
+
+    #[async_trait::async_trait]
+    impl crate::TryUserDefinedFunction<isize, Option<u64>, isize> for Gated {
+        async fn try_execute(
+            &self,
+            vertex: &Vertex<isize, isize>,
+            _data_store: Arc<DataStore<isize, isize>>,
+            _aux_info: Option<u64>,
+        ) -> Result<isize, FusionError> {
+            self.0.notified().await;
+            Ok((*vertex.get_val().await).as_ref().map_or(0, |data| data.0))
+        }
+    }
+
+    #[tokio::test]
+    async fn a_result_for_an_aborted_caller_is_dropped() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let gate = Gated(Arc::new(Notify::new()));
+                let (data_store_1, _data_store_2) =
+                    in_memory_pair_serving((&gate, &NoMutation), build_graph_integer_data).await;
+                let execute = |data_store: Arc<DataStore<isize, isize>>, gate: Gated| async move {
+                    data_store
+                        .get_vertex_by_id(&8)
+                        .apply_function(&gate, data_store.clone(), None)
+                        .await
+                };
+
+                // the caller goes away while machine 2 is still executing
+                let caller = tokio::task::spawn_local(execute(data_store_1.clone(), gate.clone()));
+                let id = waiting_request(&data_store_1.worker).await;
+                caller.abort();
+                assert!(caller.await.unwrap_err().is_cancelled());
+
+                // then its result comes in, with no one to hand it to
+                gate.0.notify_one();
+                let worker = &data_store_1.worker;
+                let forgotten = tokio::time::timeout(Duration::from_secs(5), async {
+                    while worker
+                        .result_multiplexing_channels
+                        .read()
+                        .await
+                        .contains_key(&id)
+                    {
+                        tokio::task::yield_now().await;
+                    }
+                })
+                .await;
+                assert!(forgotten.is_ok());
+
+                // the result reader is still there for the next request
+                gate.0.notify_one();
+                let res = execute(data_store_1.clone(), gate.clone()).await;
+                // the data of vertex 8 on machine 2
+                assert_eq!(res.unwrap(), 100);
+            })
+            .await;
+    }
+
+    /*
+        Machines 1 and 2 serving each other over in-memory streams, `build` adding the vertices of each machine
+        (to be called from within a LocalSet)
+    */
+    pub(crate) async fn in_memory_pair(
+        build: impl FnMut(&mut DataStore<isize, isize>, MachineID, Arc<Worker<isize, isize>>),
+    ) -> (Arc<DataStore<isize, isize>>, Arc<DataStore<isize, isize>>) {
+        in_memory_pair_serving((&GraphSum, &AccumulateSubtree), build).await
+    }
+
+    #[tokio::test]
+    async fn flushing_a_broken_stream_fails_without_panicking() {
+        let mut worker = Worker::<isize, isize>::new(1);
+        worker.flush_policy = FlushPolicy::EveryN(100);
+        let worker = Arc::new(worker);
+        let (rpc_stream, _data_stream) = worker.connect_in_memory(2, 1 << 16).await;
+        let _machine_3 = worker.connect_in_memory(3, 1 << 16).await;
+
+        // buffered towards machine 2, which then goes away
+        worker
+            .write_request(
+                2,
+                &RPC::Ping(Uuid::new_v4(), 0, 0)
+                    .frame::<BincodeCodec>(false)
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        drop(rpc_stream);
+
+        assert!(matches!(worker.flush(2).await, Err(FusionError::Rpc(_))));
+        assert!(worker.flush(3).await.is_ok());
+        worker.flush_all().await;
+    }
+
+    #[tokio::test]
+    async fn canceller_outlives_a_broken_stream() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let worker = Arc::new(Worker::<isize, isize>::new(1));
+                // machine 2 is gone, writing to it fails
+                drop(worker.connect_in_memory(2, 1 << 16).await);
+                let canceller = worker.spawn_canceller();
+
+                for _ in 0..2 {
+                    worker.cancellations.send((2, Uuid::new_v4())).unwrap();
+                    for _ in 0..8 {
+                        tokio::task::yield_now().await;
+                    }
+                }
+                assert!(!canceller.is_finished());
+            })
+            .await;
+    }
+
+    // same as in_memory_pair(), with the machines running `udfs` for each other instead of GraphSum
+    pub(crate) async fn in_memory_pair_serving<U, X, M, V>(
+        udfs: (&X, &M),
+        build: impl FnMut(&mut DataStore<isize, V>, MachineID, Arc<Worker<isize, V>>),
+    ) -> (Arc<DataStore<isize, V>>, Arc<DataStore<isize, V>>)
+    where
+        U: Serialize + DeserializeOwned + AuxDelta + Send + Sync + 'static,
+        X: UserDefinedFunction<isize, U, V> + Send + Sync + 'static + Clone,
+        M: MutableUserDefinedFunction<isize, U, V> + Send + Sync + 'static + Clone,
+        V: Serialize + DeserializeOwned + Send + Sync + 'static + Debug,
+    {
+        in_memory_pair_configured(udfs, |_| {}, build).await
+    }
+
+    // same as in_memory_pair_serving(), with both workers going through `configure` before they are shared
+    pub(crate) async fn in_memory_pair_configured<U, X, M, V>(
+        udfs: (&X, &M),
+        configure: impl FnMut(&mut Worker<isize, V>),
+        build: impl FnMut(&mut DataStore<isize, V>, MachineID, Arc<Worker<isize, V>>),
+    ) -> (Arc<DataStore<isize, V>>, Arc<DataStore<isize, V>>)
+    where
+        U: Serialize + DeserializeOwned + AuxDelta + Send + Sync + 'static,
+        X: UserDefinedFunction<isize, U, V> + Send + Sync + 'static + Clone,
+        M: MutableUserDefinedFunction<isize, U, V> + Send + Sync + 'static + Clone,
+        V: Serialize + DeserializeOwned + Send + Sync + 'static + Debug,
+    {
+        in_memory_pair_with_codec(udfs, configure, build).await
+    }
+
+    // same as in_memory_pair_configured(), with the machines speaking C to each other
+    async fn in_memory_pair_with_codec<U, X, M, V, C, P>(
+        udfs: (&X, &M),
+        mut configure: impl FnMut(&mut Worker<isize, V, C, P>),
+        mut build: impl FnMut(&mut DataStore<isize, V, C, P>, MachineID, Arc<Worker<isize, V, C, P>>),
+    ) -> (
+        Arc<DataStore<isize, V, C, P>>,
+        Arc<DataStore<isize, V, C, P>>,
+    )
+    where
+        U: Serialize + DeserializeOwned + AuxDelta + Send + Sync + 'static,
+        X: UserDefinedFunction<isize, U, V, C, P> + Send + Sync + 'static + Clone,
+        M: MutableUserDefinedFunction<isize, U, V, C, P> + Send + Sync + 'static + Clone,
+        V: Serialize + DeserializeOwned + Send + Sync + 'static + Debug,
+        C: Codec,
+        P: Codec,
+    {
+        let (mut worker_1, mut worker_2) = (Worker::with_codec(1), Worker::with_codec(2));
+        configure(&mut worker_1);
+        configure(&mut worker_2);
+        let from_1 = worker_1.connect_in_memory(2, 1 << 16).await;
+        let from_2 = worker_2.connect_in_memory(1, 1 << 16).await;
+        let (worker_1, worker_2) = (Arc::new(worker_1), Arc::new(worker_2));
+
+        let mut data_store_1 = DataStore::new(worker_1.clone());
+        build(&mut data_store_1, 1, worker_1.clone());
+        let data_store_1 = Arc::new(data_store_1);
+        let mut data_store_2 = DataStore::new(worker_2.clone());
+        build(&mut data_store_2, 2, worker_2.clone());
+        let data_store_2 = Arc::new(data_store_2);
+
+        serve_peer(&worker_2, &data_store_2, udfs, 1, from_1);
+        serve_peer(&worker_1, &data_store_1, udfs, 2, from_2);
+        worker_1.spawn_revoker();
+        worker_2.spawn_revoker();
+        (data_store_1, data_store_2)
+    }
+
+    #[tokio::test]
+    async fn graph_sum_across_in_memory_workers() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let (data_store_1, _data_store_2) = in_memory_pair(build_graph_integer_data).await;
+
+                // the subtree under vertex 4 is summed on machine 2
+                let sum = data_store_1
+                    .get_vertex_by_id(&0)
+                    .apply_function(&GraphSum, data_store_1.clone(), None)
+                    .await
+                    .unwrap();
+                assert_eq!(sum, 2136);
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn a_malformed_delta_is_answered_with_an_error() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let (data_store_1, _data_store_2) = in_memory_pair_serving(
+                    (&GraphReduce(Sum), &NoMutation),
+                    build_graph_integer_data,
+                )
+                .await;
+
+                // a caller context and priority in front of a payload that isn't an AuxDeltaPayload
+                let trailing = [
+                    caller_context::<BincodeCodec>(None),
+                    vec![0],
+                    vec![u8::MAX; 16],
+                ]
+                .concat();
+                let res = data_store_1
+                    .worker
+                    .request(2, |uuid, len| RPC::ExecuteDelta(uuid, 8, len), trailing)
+                    .await;
+                assert!(matches!(
+                    res,
+                    Ok(RPCResPayload::ErrorResPayload(FusionError::Rpc(_)))
+                ));
+
+                // machine 2 still serves deltas, the base shipped with the first one
+                let remote = data_store_1.get_vertex_by_id(&8);
+                let expected = remote
+                    .apply_function(&GraphReduce(Sum), data_store_1.clone(), None)
+                    .await
+                    .unwrap();
+                let base = AuxBase::new(None);
+                for _ in 0..2 {
+                    let sum = remote
+                        .apply_function_with_base(
+                            &GraphReduce(Sum),
+                            data_store_1.clone(),
+                            Some(1),
+                            &base,
+                        )
+                        .await
+                        .unwrap();
+                    assert_eq!(sum, expected);
+                }
+            })
+            .await;
+    }
+
+    // bincode with variable length integers, a wire format other than BincodeCodec's
+    #[derive(Debug)]
+    struct VarintCodec;
+
+    impl Codec for VarintCodec {
+        fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, crate::codec::CodecError> {
+            Ok(bincode::DefaultOptions::new().serialize(value)?)
+        }
+        fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, crate::codec::CodecError> {
+            Ok(bincode::DefaultOptions::new().deserialize(bytes)?)
+        }
+    }
+
+    #[tokio::test]
+    async fn graph_sum_across_workers_speaking_another_codec() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let (data_store_1, _data_store_2) =
+                    in_memory_pair_with_codec::<_, _, _, _, VarintCodec, VarintCodec>(
+                        (&GraphSum, &AccumulateSubtree),
+                        |_| {},
+                        build_graph_integer_data,
+                    )
+                    .await;
+                let sum = data_store_1
+                    .get_vertex_by_id(&0)
+                    .apply_function(&GraphSum, data_store_1.clone(), None)
+                    .await
+                    .unwrap();
+                assert_eq!(sum, 2136);
+
+                // the formats differ, so anything still sent in BincodeCodec would have failed the sum
+                let command = RPC::Ping(Uuid::new_v4(), 0, 0);
+                assert_ne!(
+                    command.frame::<VarintCodec>(false).unwrap(),
+                    command.frame::<BincodeCodec>(false).unwrap()
+                );
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn graph_sum_across_workers_with_a_payload_codec_of_their_own() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                // the commands stay in BincodeCodec, the aux and the results go in VarintCodec
+                let (data_store_1, _data_store_2) =
+                    in_memory_pair_with_codec::<_, _, _, _, BincodeCodec, VarintCodec>(
+                        (&GraphSum, &AccumulateSubtree),
+                        |_| {},
+                        build_graph_integer_data,
+                    )
+                    .await;
+                // an aux the two codecs encode differently, unlike None
+                let sum = data_store_1
+                    .get_vertex_by_id(&0)
+                    .apply_function(&GraphSum, data_store_1.clone(), Some(300))
+                    .await
+                    .unwrap();
+                assert_eq!(sum, 2136);
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn a_hello_naming_other_codecs_is_refused() {
+        let worker = Worker::<isize, isize>::new(1);
+        let same = Worker::<isize, isize>::new(2);
+        let other_payloads = Worker::<isize, isize, BincodeCodec, VarintCodec>::with_codec(2);
+
+        let (mut sending, mut receiving) = tokio::io::duplex(1024);
+        sending.write_all(&worker.hello().unwrap()).await.unwrap();
+        assert_eq!(same.read_hello(&mut receiving).await.unwrap(), 1);
+
+        sending.write_all(&worker.hello().unwrap()).await.unwrap();
+        let refused = other_payloads.read_hello(&mut receiving).await;
+        assert!(matches!(refused, Err(e) if e.kind() == io::ErrorKind::InvalidData));
+    }
+
+    #[tokio::test]
+    async fn reference_executor_agrees_with_the_partitioned_graph() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let (data_store_1, _data_store_2) = in_memory_pair(build_graph_integer_data).await;
+                let distributed = data_store_1
+                    .get_vertex_by_id(&0)
+                    .apply_function(&GraphSum, data_store_1.clone(), None)
+                    .await
+                    .unwrap();
+
+                // the machine 1 portion alone still points to machine 2
+                assert!(matches!(
+                    data_store_1
+                        .apply_function_reference(0, &GraphSum, None)
+                        .await,
+                    Err(FusionError::NotLocal(_, 2))
+                ));
+
+                // the same graph, held whole by one machine
+                let worker = Arc::new(Worker::new(1));
+                let mut whole = DataStore::new(worker.clone());
+                build_graph_integer_data(&mut whole, 1, worker.clone());
+                let mut partition_2 = DataStore::new(Arc::new(Worker::new(2)));
+                build_graph_integer_data(&mut partition_2, 2, worker);
+                whole.merge(partition_2).unwrap();
+                let reference = Arc::new(whole)
+                    .apply_function_reference(0, &GraphSum, None)
+                    .await
+                    .unwrap();
+                assert_eq!(reference, distributed);
+            })
+            .await;
+    }
+
+    // for the UDFs served without a mutable variant, which is never asked for
+    #[derive(Clone)]
+    struct NoMutation;
+
+    #[async_trait::async_trait]
+    impl<U, V> MutableUserDefinedFunction<isize, U, V> for NoMutation
+    where
+        U: Serialize + DeserializeOwned + Send + 'static,
+        V: Debug + Send + Sync,
+    {
+        async fn execute_mut(
+            &self,
+            _vertex: &Vertex<isize, V>,
+            _data: &mut Data<isize>,
+            _data_store: Arc<DataStore<isize, V>>,
+            _aux_info: U,
+        ) -> Result<V, FusionError> {
+            Err(FusionError::Udf("not served".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn vec_results_come_back_across_machines() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let (data_store_1, _data_store_2) = in_memory_pair_serving(
+                    (&CollectAtLeast, &NoMutation),
+                    build_graph_integer_data,
+                )
+                .await;
+                let root = data_store_1.get_vertex_by_id(&0);
+
+                // results of different lengths over the same channel, the ids of machine 2 among them
+                let mut all = root
+                    .apply_function(&CollectAtLeast, data_store_1.clone(), None)
+                    .await
+                    .unwrap();
+                all.sort_unstable();
+                assert_eq!(all, (0..=13).collect::<Vec<VertexID>>());
+
+                let mut large = root
+                    .apply_function(&CollectAtLeast, data_store_1.clone(), Some(7))
+                    .await
+                    .unwrap();
+                large.sort_unstable();
+                assert_eq!(large, [6, 7, 8, 9, 10, 11, 12, 13]);
+
+                let none = root
+                    .apply_function(&CollectAtLeast, data_store_1.clone(), Some(isize::MAX))
+                    .await
+                    .unwrap();
+                assert!(none.is_empty());
+            })
+            .await;
+    }
+
+    // GraphSum carrying an aux along to every vertex, to measure what shipping it costs
+    #[derive(Clone)]
+    struct SumCarrying;
+
+    #[async_trait::async_trait]
+    impl crate::TryUserDefinedFunction<isize, Vec<u64>, isize> for SumCarrying {
+        async fn try_execute(
+            &self,
+            vertex: &Vertex<isize, isize>,
+            data_store: Arc<DataStore<isize, isize>>,
+            aux_info: Vec<u64>,
+        ) -> Result<isize, FusionError> {
+            let mut sum = (*vertex.get_val().await).as_ref().map_or(0, |data| data.0);
+            for child in vertex.children().iter() {
+                sum += data_store
+                    .get_vertex_by_id(child)
+                    .apply_function(self, data_store.clone(), aux_info.clone())
+                    .await?;
+            }
+            Ok(sum)
+        }
+    }
+
+    /*
+       Sizes and latency of a sum whose aux is 8 MiB of sorted ids, with and without compression, run with
+       `cargo test --release compression_on_a_large_aux -- --ignored --nocapture`
+    */
+    #[tokio::test]
+    #[ignore]
+    async fn compression_on_a_large_aux() {
+        const RUNS: u32 = 20;
+        let aux: Vec<u64> = (0..1 << 20).map(|i| i / 16).collect();
+        for level in [None, Some(1), Some(3)] {
+            let local = tokio::task::LocalSet::new();
+            local
+                .run_until(async {
+                    let (data_store_1, data_store_2) = in_memory_pair_configured(
+                        (&SumCarrying, &NoMutation),
+                        |worker| worker.compression = level,
+                        build_graph_integer_data,
+                    )
+                    .await;
+                    let root = data_store_1.get_vertex_by_id(&0);
+
+                    let start = Instant::now();
+                    for _ in 0..RUNS {
+                        let sum = root
+                            .apply_function(&SumCarrying, data_store_1.clone(), aux.clone())
+                            .await
+                            .unwrap();
+                        assert_eq!(sum, 2136);
+                    }
+                    let elapsed = start.elapsed() / RUNS;
+
+                    // only the compressed payloads are counted, uncompressed ones are sent as encoded
+                    let (encoded_1, sent_1) = data_store_1.worker.compression_stats();
+                    let (encoded_2, sent_2) = data_store_2.worker.compression_stats();
+                    println!(
+                        "compression {level:?}: {elapsed:?} per sum, {} of {} encoded bytes sent per sum",
+                        (sent_1 + sent_2) / u64::from(RUNS),
+                        (encoded_1 + encoded_2) / u64::from(RUNS),
+                    );
+                })
+                .await;
+        }
+    }
+
+    // a result of `aux_info` ids, whatever the vertex
+    #[derive(Clone)]
+    struct Ids;
+
+    #[async_trait::async_trait]
+    impl crate::TryUserDefinedFunction<isize, Option<usize>, Vec<u64>> for Ids {
+        async fn try_execute(
+            &self,
+            _vertex: &Vertex<isize, Vec<u64>>,
+            _data_store: Arc<DataStore<isize, Vec<u64>>>,
+            aux_info: Option<usize>,
+        ) -> Result<Vec<u64>, FusionError> {
+            Ok((0..aux_info.unwrap_or(0) as u64).collect())
+        }
+    }
+
+    /*
+       Latency of the pings machine 2 sends machine 1 while machine 1 decodes a 128 MiB result, decoded on a
+       blocking thread and inline, run with
+       `cargo test --release pings_while_decoding_a_large_result -- --ignored --nocapture`
+    */
+    #[tokio::test]
+    #[ignore]
+    async fn pings_while_decoding_a_large_result() {
+        for (mode, threshold) in [
+            ("blocking", DEFAULT_BLOCKING_DECODE_THRESHOLD),
+            ("inline", usize::MAX),
+        ] {
+            let local = tokio::task::LocalSet::new();
+            local
+                .run_until(async {
+                    let (data_store_1, data_store_2) = in_memory_pair_configured(
+                        (&Ids, &NoMutation),
+                        |worker| worker.blocking_decode_threshold = threshold,
+                        build_graph_integer_data,
+                    )
+                    .await;
+
+                    let done = Rc::new(Cell::new(false));
+                    let (pinger, pinger_done) = (data_store_2.worker.clone(), done.clone());
+                    let pings = tokio::task::spawn_local(async move {
+                        let mut latencies = vec![];
+                        while !pinger_done.get() {
+                            let start = Instant::now();
+                            pinger
+                                .request(1, |uuid, len| RPC::Ping(uuid, 0, len), vec![])
+                                .await
+                                .unwrap();
+                            latencies.push(start.elapsed());
+                        }
+                        latencies
+                    });
+
+                    // vertex 8 is on machine 2, its result decoded by machine 1
+                    let start = Instant::now();
+                    let ids = data_store_1
+                        .get_vertex_by_id(&8)
+                        .apply_function(&Ids, data_store_1.clone(), Some(1 << 24))
+                        .await
+                        .unwrap();
+                    let elapsed = start.elapsed();
+                    assert_eq!(ids.len(), 1 << 24);
+                    done.set(true);
+
+                    // machine 2 also builds and encodes the result on this thread, which stalls the pings
+                    // the same in both modes, so the longest stalls are listed rather than only the max
+                    let mut latencies = pings.await.unwrap();
+                    latencies.sort_unstable_by(|a, b| b.cmp(a));
+                    let mean = latencies.iter().sum::<Duration>() / latencies.len() as u32;
+                    println!(
+                        "{mode}: result in {elapsed:?}, {} pings meanwhile, mean {mean:?}, longest {:?}",
+                        latencies.len(),
+                        &latencies[..3],
+                    );
+                })
+                .await;
+        }
+    }
+
+    // the vertices of the subtree, without reading their data
+    #[derive(Clone)]
+    struct Count;
+
+    #[async_trait::async_trait]
+    impl crate::TryUserDefinedFunction<isize, Option<u64>, isize> for Count {
+        async fn try_execute(
+            &self,
+            vertex: &Vertex<isize, isize>,
+            data_store: Arc<DataStore<isize, isize>>,
+            aux_info: Option<u64>,
+        ) -> Result<isize, FusionError> {
+            vertex
+                .apply_function_to_children(
+                    self,
+                    data_store.clone(),
+                    aux_info,
+                    ExecOptions::default(),
+                    1,
+                    |acc, count| acc + count,
+                )
+                .await
+        }
+    }
+
+    /*
+       Latency of the pings machine 2 sends machine 1 while machine 1 folds a star of 10^6 leaves of its own,
+       yielding every DEFAULT_YIELD_EVERY_N_VERTICES local executes and never, run with
+       `cargo test --release pings_during_a_local_fold -- --ignored --nocapture`
+    */
+    #[tokio::test]
+    #[ignore]
+    async fn pings_during_a_local_fold() {
+        const STAR: VertexID = 1 << 20;
+        for yield_every in [DEFAULT_YIELD_EVERY_N_VERTICES, 0] {
+            let local = tokio::task::LocalSet::new();
+            local
+                .run_until(async {
+                    let (data_store_1, data_store_2) = in_memory_pair(|data_store, machine, worker| {
+                        build_graph_integer_data(data_store, machine, worker);
+                        if machine == 1 {
+                            data_store.set_yield_every_n_vertices(yield_every);
+                            add_star(data_store, STAR, STAR + 1..STAR + 1_000_001);
+                        }
+                    })
+                    .await;
+
+                    let done = Rc::new(Cell::new(false));
+                    let (pinger, pinger_done) = (data_store_2.worker.clone(), done.clone());
+                    let pings = tokio::task::spawn_local(async move {
+                        let mut latencies = vec![];
+                        while !pinger_done.get() {
+                            let start = Instant::now();
+                            pinger
+                                .request(1, |uuid, len| RPC::Ping(uuid, 0, len), vec![])
+                                .await
+                                .unwrap();
+                            latencies.push(start.elapsed());
+                        }
+                        latencies
+                    });
+                    // the first ping is on its way before the fold starts
+                    tokio::task::yield_now().await;
+
+                    let start = Instant::now();
+                    let sum = data_store_1
+                        .get_vertex_by_id(&STAR)
+                        .apply_function_to_children(
+                            &Count,
+                            data_store_1.clone(),
+                            None,
+                            ExecOptions::default(),
+                            0,
+                            |acc, sum| acc + sum,
+                        )
+                        .await
+                        .unwrap();
+                    let elapsed = start.elapsed();
+                    assert_eq!(sum, 1_000_000);
+                    done.set(true);
+
+                    let mut latencies = pings.await.unwrap();
+                    latencies.sort_unstable_by(|a, b| b.cmp(a));
+                    let mean = latencies.iter().sum::<Duration>() / latencies.len() as u32;
+                    println!(
+                        "yielding every {yield_every}: fold in {elapsed:?}, {} pings meanwhile, mean {mean:?}, longest {:?}",
+                        latencies.len(),
+                        &latencies[..latencies.len().min(3)],
+                    );
+                })
+                .await;
         }
     }
 }